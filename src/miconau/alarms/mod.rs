@@ -0,0 +1,257 @@
+use std::{fs, path::{Path, PathBuf}, sync::Arc, time::Duration};
+use chrono::{Datelike, Local, NaiveDateTime, Timelike, Weekday};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use crate::player::Player;
+
+const ALARMS_FILE: &str = "alarms.json";
+
+/// How often the scheduler looks at the clock. Anything well under a minute
+/// does, as alarms are set to the minute.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+fn default_ramp_seconds() -> u64 {
+    60
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// What an alarm plays. Stations and playlists are referred to by name
+/// rather than by index: indices shift whenever the library is rescanned or
+/// a stream is moved, and an alarm should not end up on another station.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum AlarmSource {
+    Stream { name: String },
+    Playlist { name: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Alarm {
+    /// Local time.
+    pub hour: u32,
+    pub minute: u32,
+    /// The days to ring on, as in `["Mon", "Tue"]`. Every day when empty.
+    #[serde(default)]
+    pub weekdays: Vec<Weekday>,
+    pub source: AlarmSource,
+    /// How long the volume takes to rise from silence to full. 0 starts at
+    /// full volume.
+    #[serde(default = "default_ramp_seconds")]
+    pub ramp_seconds: u64,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+impl Alarm {
+    fn check(&self) -> Result<(), String> {
+        if self.hour > 23 || self.minute > 59 {
+            return Err(format!("{}:{:02} is not a time of day", self.hour, self.minute));
+        }
+        Ok(())
+    }
+
+    /// Whether the alarm rings in the minute `at` falls in.
+    pub fn is_due(&self, at: NaiveDateTime) -> bool {
+        self.enabled
+            && at.hour() == self.hour
+            && at.minute() == self.minute
+            && (self.weekdays.is_empty() || self.weekdays.contains(&at.weekday()))
+    }
+}
+
+/// The alarms, kept in `alarms.json` in the data folder. Without a data
+/// folder they only last until miconau stops.
+pub struct Alarms {
+    file: Option<PathBuf>,
+    pub alarms: Vec<Alarm>,
+}
+
+impl Alarms {
+    pub fn load(data_folder: Option<&Path>) -> Alarms {
+        let file = data_folder.map(|folder| folder.join(ALARMS_FILE));
+        let alarms = match file.as_ref().map(fs::read_to_string) {
+            Some(Ok(content)) => serde_json::from_str(&content).unwrap_or_else(|error| {
+                // Keep going without them rather than not waking anyone at
+                // all; the file is only written again once an alarm changes.
+                println!("Could not read the alarms from {:?}: {}", file, error);
+                Vec::new()
+            }),
+            _ => Vec::new(),
+        };
+        Alarms { file, alarms }
+    }
+
+    /// Changes the alarms and writes them back. Like the streams, the change
+    /// is made to a copy that is only kept once it is on disk.
+    fn change(&mut self, change: impl FnOnce(&mut Vec<Alarm>) -> Result<(), String>) -> Result<(), String> {
+        let mut alarms = self.alarms.clone();
+        change(&mut alarms)?;
+        for alarm in &alarms {
+            alarm.check()?;
+        }
+        if let Some(file) = &self.file {
+            let temporary = file.with_extension("json.tmp");
+            let content = serde_json::to_string_pretty(&alarms).map_err(|error| error.to_string())?;
+            file.parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| fs::write(&temporary, content))
+                .and_then(|_| fs::rename(&temporary, file))
+                .map_err(|error| format!("Could not write {:?}: {}", file, error))?;
+        }
+        self.alarms = alarms;
+        Ok(())
+    }
+
+    /// Adds an alarm and returns its index.
+    pub fn add(&mut self, alarm: Alarm) -> Result<usize, String> {
+        self.change(|alarms| {
+            alarms.push(alarm);
+            Ok(())
+        })?;
+        Ok(self.alarms.len() - 1)
+    }
+
+    pub fn update(&mut self, index: usize, alarm: Alarm) -> Result<(), String> {
+        self.change(|alarms| {
+            *alarms.get_mut(index).ok_or(format!("Alarm {} not found", index))? = alarm;
+            Ok(())
+        })
+    }
+
+    pub fn delete(&mut self, index: usize) -> Result<(), String> {
+        self.change(|alarms| {
+            if index >= alarms.len() {
+                return Err(format!("Alarm {} not found", index));
+            }
+            alarms.remove(index);
+            Ok(())
+        })
+    }
+
+    fn due_at(&self, at: NaiveDateTime) -> Vec<Alarm> {
+        self.alarms.iter().filter(|alarm| alarm.is_due(at)).cloned().collect()
+    }
+}
+
+/// Starts playing what an alarm is set to, rising from silence if it ramps
+/// up. The player's ticks raise the volume, until anything else is done
+/// with the player.
+async fn ring(player: &Arc<Mutex<Player>>, alarm: Alarm) {
+    let mut player = player.lock().await;
+    // A sleep timer left over from the night would fade the alarm out.
+    player.cancel_sleep_timer();
+    let found = match &alarm.source {
+        AlarmSource::Stream { name } => player.library.streams
+            .iter()
+            .position(|stream| &stream.name == name)
+            .map(|index| (true, index)),
+        AlarmSource::Playlist { name } => player.library.playlists
+            .iter()
+            .position(|playlist| &playlist.title == name)
+            .map(|index| (false, index)),
+    };
+    println!("Alarm for {}:{:02} rings: {:?}", alarm.hour, alarm.minute, alarm.source);
+    let Some((is_stream, index)) = found else {
+        // Still wake whoever set it, if only with the error sound.
+        println!("{:?} is not in the library", alarm.source);
+        player.play_error();
+        return;
+    };
+    if is_stream {
+        player.play_stream(index);
+    } else {
+        player.play_playlist(index);
+    }
+    if alarm.ramp_seconds > 0 {
+        player.ramp_volume(Duration::from_secs(alarm.ramp_seconds));
+    }
+}
+
+/// Rings the alarms as they come due. Each minute is looked at once, so an
+/// alarm rings once even though the clock is checked several times a minute.
+pub fn spawn_alarm_scheduler(player: Arc<Mutex<Player>>, alarms: Arc<Mutex<Alarms>>) {
+    tokio::spawn(async move {
+        let mut last_minute = None;
+        loop {
+            let now = Local::now().naive_local();
+            let minute = now.with_second(0).and_then(|now| now.with_nanosecond(0));
+            if minute != last_minute {
+                last_minute = minute;
+                let due = alarms.lock().await.due_at(now);
+                for alarm in due {
+                    ring(&player, alarm).await;
+                }
+            }
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+    use chrono::NaiveDate;
+
+    fn alarm(hour: u32, minute: u32, weekdays: Vec<Weekday>) -> Alarm {
+        Alarm {
+            hour,
+            minute,
+            weekdays,
+            source: AlarmSource::Stream { name: "Radio".to_string() },
+            ramp_seconds: default_ramp_seconds(),
+            enabled: true,
+        }
+    }
+
+    #[test]
+    fn rings_at_its_time_on_its_days() {
+        // 2024-01-01 was a Monday
+        let monday = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let tuesday = NaiveDate::from_ymd_opt(2024, 1, 2).unwrap();
+        let weekdays = alarm(7, 30, vec![Weekday::Mon, Weekday::Fri]);
+        let every_day = alarm(7, 30, Vec::new());
+
+        assert!(weekdays.is_due(monday.and_hms_opt(7, 30, 0).unwrap()));
+        assert!(weekdays.is_due(monday.and_hms_opt(7, 30, 59).unwrap()));
+        assert!(!weekdays.is_due(monday.and_hms_opt(7, 31, 0).unwrap()));
+        assert!(!weekdays.is_due(tuesday.and_hms_opt(7, 30, 0).unwrap()));
+        assert!(every_day.is_due(tuesday.and_hms_opt(7, 30, 0).unwrap()));
+
+        let disabled = Alarm { enabled: false, ..every_day };
+        assert!(!disabled.is_due(tuesday.and_hms_opt(7, 30, 0).unwrap()));
+    }
+
+    #[test]
+    fn alarms_are_kept_in_the_data_folder() {
+        let temp = TempDir::new("alarms");
+        let folder = &temp.path;
+
+        let mut alarms = Alarms::load(Some(folder));
+        assert!(alarms.alarms.is_empty());
+        assert_eq!(alarms.add(alarm(6, 45, vec![Weekday::Sat])).unwrap(), 0);
+        assert_eq!(alarms.add(alarm(7, 0, Vec::new())).unwrap(), 1);
+        assert!(alarms.add(alarm(24, 0, Vec::new())).is_err());
+        assert!(alarms.update(2, alarm(8, 0, Vec::new())).is_err());
+        alarms.update(1, alarm(8, 0, Vec::new())).unwrap();
+        alarms.delete(0).unwrap();
+
+        let reloaded = Alarms::load(Some(folder));
+        assert_eq!(reloaded.alarms, vec![alarm(8, 0, Vec::new())]);
+    }
+
+    #[test]
+    fn reads_alarms_with_defaults() {
+        let alarm: Alarm = serde_json::from_str(
+            r#"{"hour": 7, "minute": 5, "weekdays": ["Mon", "Friday"], "source": {"type": "playlist", "name": "Morning"}}"#,
+        ).unwrap();
+        assert_eq!(alarm.weekdays, vec![Weekday::Mon, Weekday::Fri]);
+        assert_eq!(alarm.source, AlarmSource::Playlist { name: "Morning".to_string() });
+        assert_eq!(alarm.ramp_seconds, 60);
+        assert!(alarm.enabled);
+    }
+}
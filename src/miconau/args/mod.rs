@@ -1,11 +1,18 @@
 extern crate clap;
 use clap::Parser;
+use crate::library::Grouping;
+use crate::library::loudness::ReplayGainMode;
+use crate::web::DEFAULT_MAX_UPLOAD_MIB;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
-    #[arg(short, long)]
-    pub library_folder: String,
+    /// A folder to read playlists from. Can be given several times for a
+    /// library spread over several drives. A folder can be given a label,
+    /// as in `Archive=/mnt/usb2/Music`, that is put in front of the titles of
+    /// its playlists to keep them apart from those of the other folders.
+    #[arg(short, long, required = true)]
+    pub library_folder: Vec<String>,
 
     /// Folder holding `streams.txt` and the `logos/` it refers to. Streams are
     /// unrelated to the music library, so they live wherever the user keeps
@@ -14,6 +21,72 @@ pub struct Args {
     #[arg(long)]
     pub streams_folder: Option<String>,
 
+    /// Extensions of the files to pick up as tracks, separated by commas, e.g.
+    /// `mp3,flac,m4a`. Replaces the default list rather than adding to it.
+    #[arg(long, value_delimiter = ',')]
+    pub audio_extensions: Option<Vec<String>>,
+
+    /// How tracks are put together into playlists: one playlist per folder,
+    /// or one per album by the album and album artist tags.
+    #[arg(long, value_enum, default_value_t = Grouping::Folders)]
+    pub grouping: Grouping,
+
+    /// Whether to even out loudness differences between tracks, or between
+    /// albums, by their ReplayGain tags.
+    #[arg(long, value_enum, default_value_t = ReplayGainMode::Off)]
+    pub replaygain: ReplayGainMode,
+
+    /// Measure the loudness of the files without ReplayGain tags with ffmpeg,
+    /// so they are evened out as well. Runs in the background after the scan
+    /// and keeps its results in `.miconau-loudness.json` in each library
+    /// folder, so every file is only measured once.
+    #[arg(long)]
+    pub loudness_analysis: bool,
+
+    /// Check every this many seconds whether the streams answer, and show
+    /// the ones that don't as down in the web UI. Off without it.
+    #[arg(long)]
+    pub stream_check_interval: Option<u64>,
+
+    /// Seconds to fade out what plays when a key changes or stops it, and
+    /// to fade in what it changes to. Off at 0.
+    #[arg(long, default_value_t = 0.0)]
+    pub fade: f64,
+
+    /// Seconds to fade out the end of a track and fade in the next one. mpv
+    /// plays one file at a time, so the two don't overlap. Tracks cut from
+    /// one file by a CUE sheet play on without it. Off at 0.
+    #[arg(long, default_value_t = 0.0)]
+    pub crossfade: f64,
+
+    /// Folder for what is set up through the web UI rather than on the
+    /// command line, such as alarms and ratings, for the play history and for
+    /// listens waiting to be submitted. Without it those are forgotten when miconau
+    /// stops.
+    #[arg(long)]
+    pub data_folder: Option<String>,
+
+    /// Token to submit what is played to ListenBrainz with. Scrobbling is
+    /// off without it.
+    #[arg(long)]
+    pub listenbrainz_token: Option<String>,
+
+    /// Root of the ListenBrainz compatible API to submit to, for a server of
+    /// one's own.
+    #[arg(long, default_value = "https://api.listenbrainz.org")]
+    pub listenbrainz_url: String,
+
+    /// File of smart playlists: playlists of the tracks that match rules such
+    /// as `genre = Jazz` or `rating >= 4`, rather than of a folder.
+    #[arg(long)]
+    pub smart_playlists: Option<String>,
+
+    /// Also write star ratings to the tags of the files, as FMPS_RATING and
+    /// for MP3s POPM, for other players to see. Off by default, as it changes
+    /// the files.
+    #[arg(long)]
+    pub write_ratings_to_tags: bool,
+
     #[arg(short, long)]
     pub output_device: Option<String>,
 
@@ -26,6 +99,10 @@ pub struct Args {
     #[arg(short, long)]
     pub address: Option<String>,
 
+    /// Largest upload the web UI accepts, in MiB.
+    #[arg(long, default_value_t = DEFAULT_MAX_UPLOAD_MIB)]
+    pub max_upload_size: usize,
+
     #[arg(long, default_value = "/tmp/mpvsocket")]
     pub mpv_socket: String,
 }
@@ -0,0 +1,254 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+use image::{imageops::FilterType, ImageFormat};
+use lofty::prelude::*;
+use lofty::probe::Probe;
+
+/// File names (without extension) that are taken as the cover of the folder
+/// they are in, best first. Compared case-insensitively.
+const FOLDER_IMAGE_NAMES: [&str; 5] = ["cover", "folder", "front", "album", "albumart"];
+
+/// Extensions of the image files the scan looks at.
+pub const IMAGE_EXTENSIONS: [&str; 5] = ["jpg", "jpeg", "png", "webp", "gif"];
+
+/// Where a cover comes from. Only the path is kept, never the image: a few
+/// thousand albums add up to gigabytes of artwork, so covers are read from
+/// disk again when they are served.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CoverSource {
+    /// Artwork embedded in the tags of an audio file.
+    Embedded(PathBuf),
+    /// An image file next to the audio, such as `cover.jpg`.
+    File(PathBuf),
+}
+
+impl CoverSource {
+    pub fn path(&self) -> &Path {
+        match self {
+            CoverSource::Embedded(path) | CoverSource::File(path) => path,
+        }
+    }
+}
+
+/// Picks the image that stands for a folder out of the image files in it:
+/// the best-named one (`cover.jpg` before `folder.png` and so on), or, in a
+/// folder with nothing named like a cover, its only image.
+pub fn folder_image(images: &[PathBuf]) -> Option<PathBuf> {
+    let rank = |image: &PathBuf| {
+        let stem = image.file_stem()?.to_string_lossy().to_lowercase();
+        FOLDER_IMAGE_NAMES.iter().position(|name| *name == stem)
+    };
+
+    let named = images
+        .iter()
+        .filter_map(|image| rank(image).map(|rank| (rank, image)))
+        .min_by_key(|(rank, image)| (*rank, (*image).clone()))
+        .map(|(_, image)| image.clone());
+
+    match (named, images) {
+        (Some(image), _) => Some(image),
+        (None, [only]) => Some(only.clone()),
+        _ => None,
+    }
+}
+
+fn mime_for_extension(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        Some("png") => "image/png",
+        Some("webp") => "image/webp",
+        Some("gif") => "image/gif",
+        _ => "image/jpeg",
+    }
+}
+
+/// Reads the embedded cover of an audio file. Called when a cover is actually
+/// requested rather than during the scan, so covers never accumulate in memory.
+fn read_embedded(path: &Path) -> Option<(Vec<u8>, String)> {
+    let tagged_file = Probe::open(path).and_then(|p| p.read()).ok()?;
+    let tag = tagged_file.primary_tag().or_else(|| tagged_file.first_tag())?;
+    let picture = tag.pictures().first()?;
+    let mime = picture
+        .mime_type()
+        .map(|m| m.to_string())
+        .unwrap_or_else(|| "image/jpeg".to_string());
+    Some((picture.data().to_vec(), mime))
+}
+
+/// Reads a cover and its mime type from wherever it comes from.
+pub fn read_cover(source: &CoverSource) -> Option<(Vec<u8>, String)> {
+    match source {
+        CoverSource::Embedded(path) => read_embedded(path),
+        CoverSource::File(path) => fs::read(path)
+            .ok()
+            .map(|data| (data, mime_for_extension(path).to_string())),
+    }
+}
+
+/// Scales a cover down so it fits into `size` x `size` pixels, as a JPEG.
+/// Returns None when there is nothing to gain, either because the image is
+/// small enough already or because it is in a format that can't be decoded,
+/// and the original should be served instead.
+pub fn thumbnail(data: &[u8], size: u32) -> Option<Vec<u8>> {
+    let image = image::load_from_memory(data).ok()?;
+    if image.width() <= size && image.height() <= size {
+        return None;
+    }
+
+    // JPEG has no alpha channel, so transparency is flattened away.
+    let scaled = image.resize(size, size, FilterType::Triangle).to_rgb8();
+    let mut encoded = Cursor::new(Vec::new());
+    scaled.write_to(&mut encoded, ImageFormat::Jpeg).ok()?;
+    Some(encoded.into_inner())
+}
+
+/// Identifies a thumbnail: which cover, at which size, as of which version of
+/// the file. Including the modification time means an edited cover is
+/// rendered again instead of being served stale from the cache.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct ThumbnailKey {
+    source: CoverSource,
+    modified: Option<SystemTime>,
+    size: u32,
+}
+
+impl ThumbnailKey {
+    pub fn new(source: &CoverSource, size: u32) -> ThumbnailKey {
+        ThumbnailKey {
+            source: source.clone(),
+            modified: fs::metadata(source.path())
+                .and_then(|metadata| metadata.modified())
+                .ok(),
+            size,
+        }
+    }
+}
+
+/// The thumbnails rendered so far, kept in memory up to a total size. Scaling
+/// a cover down is slow on a Raspberry Pi and the playlist list asks for the
+/// same covers every time it is loaded, so each is only rendered once. When
+/// the cache is full, the oldest thumbnails make room.
+pub struct ThumbnailCache {
+    thumbnails: HashMap<ThumbnailKey, Arc<Vec<u8>>>,
+    order: VecDeque<ThumbnailKey>,
+    bytes: usize,
+    capacity: usize,
+}
+
+impl ThumbnailCache {
+    pub fn new(capacity: usize) -> ThumbnailCache {
+        ThumbnailCache {
+            thumbnails: HashMap::new(),
+            order: VecDeque::new(),
+            bytes: 0,
+            capacity,
+        }
+    }
+
+    pub fn get(&self, key: &ThumbnailKey) -> Option<Arc<Vec<u8>>> {
+        self.thumbnails.get(key).cloned()
+    }
+
+    pub fn insert(&mut self, key: ThumbnailKey, thumbnail: Arc<Vec<u8>>) {
+        if thumbnail.len() > self.capacity || self.thumbnails.contains_key(&key) {
+            return;
+        }
+        while self.bytes + thumbnail.len() > self.capacity {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            if let Some(evicted) = self.thumbnails.remove(&oldest) {
+                self.bytes -= evicted.len();
+            }
+        }
+        self.bytes += thumbnail.len();
+        self.order.push_back(key.clone());
+        self.thumbnails.insert(key, thumbnail);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(|name| PathBuf::from("/music/Album").join(name)).collect()
+    }
+
+    #[test]
+    fn prefers_images_named_like_a_cover() {
+        assert_eq!(
+            folder_image(&paths(&["back.jpg", "Folder.PNG", "cover.jpg"])),
+            Some(PathBuf::from("/music/Album/cover.jpg"))
+        );
+        assert_eq!(
+            folder_image(&paths(&["back.jpg", "Folder.PNG"])),
+            Some(PathBuf::from("/music/Album/Folder.PNG"))
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_only_image_of_a_folder() {
+        assert_eq!(
+            folder_image(&paths(&["scan.jpg"])),
+            Some(PathBuf::from("/music/Album/scan.jpg"))
+        );
+        // with several, none of them is obviously the cover
+        assert_eq!(folder_image(&paths(&["scan1.jpg", "scan2.jpg"])), None);
+        assert_eq!(folder_image(&[]), None);
+    }
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let image = image::RgbaImage::from_pixel(width, height, image::Rgba([200, 30, 30, 255]));
+        let mut encoded = Cursor::new(Vec::new());
+        image.write_to(&mut encoded, ImageFormat::Png).unwrap();
+        encoded.into_inner()
+    }
+
+    #[test]
+    fn thumbnails_fit_the_requested_size_and_keep_the_aspect_ratio() {
+        let thumbnail = thumbnail(&png(400, 200), 100).expect("should be scaled down");
+
+        let decoded = image::load_from_memory(&thumbnail).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (100, 50));
+        assert_eq!(
+            image::guess_format(&thumbnail).unwrap(),
+            ImageFormat::Jpeg
+        );
+    }
+
+    #[test]
+    fn small_or_undecodable_covers_are_not_thumbnailed() {
+        assert_eq!(thumbnail(&png(64, 64), 100), None);
+        assert_eq!(thumbnail(b"not an image", 100), None);
+    }
+
+    fn key(name: &str) -> ThumbnailKey {
+        ThumbnailKey::new(&CoverSource::File(PathBuf::from(name)), 100)
+    }
+
+    #[test]
+    fn the_cache_drops_the_oldest_thumbnails_when_full() {
+        let mut cache = ThumbnailCache::new(10);
+        cache.insert(key("a"), Arc::new(vec![0; 4]));
+        cache.insert(key("b"), Arc::new(vec![0; 4]));
+        cache.insert(key("c"), Arc::new(vec![0; 4]));
+
+        assert!(cache.get(&key("a")).is_none());
+        assert!(cache.get(&key("b")).is_some());
+        assert!(cache.get(&key("c")).is_some());
+
+        // too large to ever fit, so it isn't cached and evicts nothing
+        cache.insert(key("d"), Arc::new(vec![0; 11]));
+        assert!(cache.get(&key("d")).is_none());
+        assert!(cache.get(&key("b")).is_some());
+    }
+}
@@ -0,0 +1,297 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// A track as a CUE sheet describes it: where in which file it starts, and
+/// what it is called. Where it ends is only known once the whole sheet is
+/// read, as the start of the next track in the same file.
+#[derive(Debug, PartialEq)]
+pub struct CueTrack {
+    /// The audio file as the sheet names it, relative to the sheet's folder.
+    pub file: String,
+    pub number: u32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub start: Duration,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CueSheet {
+    /// The album artist, used for tracks without a performer of their own.
+    pub performer: Option<String>,
+    /// The title of the album.
+    pub title: Option<String>,
+    pub tracks: Vec<CueTrack>,
+}
+
+/// Splits the arguments of a CUE command into words, keeping quoted strings
+/// together: `FILE "Some Album.flac" WAVE` has two arguments, not four.
+fn arguments(rest: &str) -> Vec<String> {
+    let mut arguments = Vec::new();
+    let mut chars = rest.trim().chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            arguments.push(chars.by_ref().take_while(|&c| c != '"').collect());
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            arguments.push(word);
+        }
+    }
+    arguments
+}
+
+/// Parses an `mm:ss:ff` index time. Frames are CD frames, 75 to the second.
+fn parse_time(time: &str) -> Option<Duration> {
+    let parts: Vec<u64> = time
+        .split(':')
+        .map(|part| part.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    match parts[..] {
+        [minutes, seconds, frames] => Some(
+            Duration::from_secs(minutes * 60 + seconds)
+                + Duration::from_nanos(frames * 1_000_000_000 / 75),
+        ),
+        _ => None,
+    }
+}
+
+/// Parses the content of a CUE sheet. Only what is needed to split an album
+/// into tracks is read; everything else (REM comments, flags, pregaps) is
+/// skipped. Tracks without an `INDEX 01` cannot be played from anywhere and
+/// are dropped.
+pub fn parse(content: &str) -> CueSheet {
+    let mut sheet = CueSheet {
+        performer: None,
+        title: None,
+        tracks: Vec::new(),
+    };
+    let mut file: Option<String> = None;
+    // The track being read, if it is an audio track, and its INDEX 01 once
+    // that has turned up.
+    let mut current: Option<(CueTrack, Option<Duration>)> = None;
+    // Whether any TRACK has been seen, after which TITLE and PERFORMER are no
+    // longer about the album, even for the tracks that are skipped.
+    let mut in_tracks = false;
+
+    fn finish(current: Option<(CueTrack, Option<Duration>)>, tracks: &mut Vec<CueTrack>) {
+        if let Some((track, Some(start))) = current {
+            tracks.push(CueTrack { start, ..track });
+        }
+    }
+
+    // Sheets written by Windows rippers start with a byte order mark.
+    for line in content.trim_start_matches('\u{feff}').lines() {
+        let line = line.trim();
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let arguments = arguments(rest);
+
+        match command.to_uppercase().as_str() {
+            "FILE" => {
+                file = arguments.into_iter().next();
+            }
+            "TRACK" => {
+                finish(current.take(), &mut sheet.tracks);
+                in_tracks = true;
+                let is_audio = arguments
+                    .get(1)
+                    .is_none_or(|kind| kind.eq_ignore_ascii_case("AUDIO"));
+                if let (Some(file), true) = (file.clone(), is_audio) {
+                    let track = CueTrack {
+                        file,
+                        number: arguments.first().and_then(|n| n.parse().ok()).unwrap_or(0),
+                        title: None,
+                        performer: None,
+                        start: Duration::ZERO,
+                    };
+                    current = Some((track, None));
+                }
+            }
+            "TITLE" => {
+                let title = arguments.into_iter().next();
+                match current.as_mut() {
+                    Some((track, _)) => track.title = title,
+                    None if !in_tracks => sheet.title = title,
+                    None => {}
+                }
+            }
+            "PERFORMER" => {
+                let performer = arguments.into_iter().next();
+                match current.as_mut() {
+                    Some((track, _)) => track.performer = performer,
+                    None if !in_tracks => sheet.performer = performer,
+                    None => {}
+                }
+            }
+            "INDEX" => {
+                if let (Some((_, start)), Some("01")) = (
+                    current.as_mut(),
+                    arguments.first().map(|number| number.as_str()),
+                ) {
+                    *start = arguments.get(1).and_then(|time| parse_time(time));
+                }
+            }
+            _ => {}
+        }
+    }
+    finish(current.take(), &mut sheet.tracks);
+
+    sheet
+}
+
+/// The location mpv is given for the part of `file` between `start` and
+/// `end`, as an EDL ("edit decision list") URL. Every track of a single-file
+/// album gets a location of its own this way, so mpv's playlist holds one
+/// entry per track and its `path` property tells the tracks apart.
+///
+/// The path is length-prefixed (`%len%`) so file names with commas or
+/// semicolons, which separate EDL parameters, survive unescaped.
+pub fn edl_location(file: &Path, start: Duration, end: Option<Duration>) -> String {
+    let path = file.to_string_lossy();
+    let mut location = format!(
+        "edl://%{}%{},start={:.6}",
+        path.len(),
+        path,
+        start.as_secs_f64(),
+    );
+    // A sheet with its INDEX times out of order has tracks that end before
+    // they start. Those play to the end of the file rather than not at all.
+    if let Some(length) = end.and_then(|end| end.checked_sub(start)).filter(|length| !length.is_zero()) {
+        location.push_str(&format!(",length={:.6}", length.as_secs_f64()));
+    }
+    location
+}
+
+/// The audio file an `edl_location` points into, or None for anything else.
+pub fn edl_file(location: &str) -> Option<PathBuf> {
+    let rest = location.strip_prefix("edl://%")?;
+    let (length, rest) = rest.split_once('%')?;
+    let length: usize = length.parse().ok()?;
+    rest.get(..length).map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHEET: &str = "\u{feff}REM GENRE Rock
+PERFORMER \"The Band\"
+TITLE \"The Album\"
+FILE \"The Band - The Album.flac\" WAVE
+  TRACK 01 AUDIO
+    TITLE \"Opening\"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE \"Second Song\"
+    PERFORMER \"The Band feat. Guest\"
+    INDEX 00 04:10:00
+    INDEX 01 04:12:37
+  TRACK 03 AUDIO
+    INDEX 01 08:00:74
+";
+
+    #[test]
+    fn parses_tracks_titles_and_performers() {
+        let sheet = parse(SHEET);
+
+        assert_eq!(sheet.performer.as_deref(), Some("The Band"));
+        assert_eq!(sheet.title.as_deref(), Some("The Album"));
+        assert_eq!(sheet.tracks.len(), 3);
+        assert_eq!(sheet.tracks[0].file, "The Band - The Album.flac");
+        assert_eq!(sheet.tracks[0].title.as_deref(), Some("Opening"));
+        assert_eq!(sheet.tracks[0].performer, None);
+        assert_eq!(sheet.tracks[1].number, 2);
+        assert_eq!(sheet.tracks[1].performer.as_deref(), Some("The Band feat. Guest"));
+        // a track without a title of its own stays untitled
+        assert_eq!(sheet.tracks[2].title, None);
+    }
+
+    #[test]
+    fn tracks_start_at_index_01_counted_in_cd_frames() {
+        let sheet = parse(SHEET);
+
+        assert_eq!(sheet.tracks[0].start, Duration::ZERO);
+        // INDEX 00 is the pregap and is skipped; 37 frames are 37/75 s
+        assert_eq!(
+            sheet.tracks[1].start,
+            Duration::from_secs(252) + Duration::from_nanos(493_333_333)
+        );
+        assert_eq!(
+            sheet.tracks[2].start,
+            Duration::from_secs(480) + Duration::from_nanos(986_666_666)
+        );
+    }
+
+    #[test]
+    fn keeps_the_file_of_each_track_in_multi_file_sheets() {
+        let sheet = parse(
+            "FILE \"a.wav\" WAVE\nTRACK 01 AUDIO\nINDEX 01 00:00:00\n\
+             FILE b.wav WAVE\nTRACK 02 AUDIO\nINDEX 01 00:00:00\n",
+        );
+
+        assert_eq!(sheet.tracks[0].file, "a.wav");
+        assert_eq!(sheet.tracks[1].file, "b.wav");
+    }
+
+    #[test]
+    fn skips_data_tracks_and_tracks_without_a_start() {
+        let sheet = parse(
+            "FILE \"a.flac\" WAVE\nTRACK 01 MODE1/2352\nINDEX 01 00:00:00\n\
+             TRACK 02 AUDIO\nINDEX 00 01:00:00\nTRACK 03 AUDIO\nINDEX 01 02:00:00\n",
+        );
+
+        assert_eq!(sheet.tracks.len(), 1);
+        assert_eq!(sheet.tracks[0].number, 3);
+    }
+
+    #[test]
+    fn edl_locations_point_back_to_their_file() {
+        let file = Path::new("/music/Album, Live; 1999/album.flac");
+        let location = edl_location(
+            file,
+            Duration::from_secs(10),
+            Some(Duration::from_millis(12_500)),
+        );
+
+        assert_eq!(
+            location,
+            format!(
+                "edl://%{}%/music/Album, Live; 1999/album.flac,start=10.000000,length=2.500000",
+                file.to_string_lossy().len()
+            )
+        );
+        assert_eq!(edl_file(&location), Some(file.to_path_buf()));
+    }
+
+    #[test]
+    fn the_last_track_has_no_length_and_plays_to_the_end() {
+        let location = edl_location(Path::new("/a.flac"), Duration::from_secs(1), None);
+
+        assert_eq!(location, "edl://%7%/a.flac,start=1.000000");
+        assert_eq!(edl_file("/music/plain.flac"), None);
+    }
+
+    #[test]
+    fn tracks_ending_before_they_start_play_to_the_end() {
+        let sheet = parse(
+            "FILE \"album.flac\" WAVE\n\
+             TRACK 01 AUDIO\n\
+             INDEX 01 03:00:00\n\
+             TRACK 02 AUDIO\n\
+             INDEX 01 01:00:00\n",
+        );
+        let location = edl_location(Path::new("/a.flac"), sheet.tracks[0].start, Some(sheet.tracks[1].start));
+
+        assert_eq!(location, "edl://%7%/a.flac,start=180.000000");
+    }
+}
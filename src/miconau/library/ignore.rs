@@ -0,0 +1,208 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Name of the file listing what the scan should leave out, one glob pattern
+/// per line. It can be put into any folder of the library and applies to
+/// everything below that folder.
+pub const IGNORE_FILE: &str = ".miconauignore";
+
+/// A folder holding a file of this name is left out together with everything
+/// below it. The same marker keeps Android and other media scanners out, so
+/// many libraries already have it where it matters.
+pub const MARKER_FILE: &str = ".nomedia";
+
+/// One line of an ignore file.
+#[derive(Clone, Debug)]
+struct Rule {
+    /// The folder of the ignore file, which anchored patterns start from.
+    base: PathBuf,
+    /// The pattern, split at `/`.
+    segments: Vec<String>,
+    /// Whether the pattern is a path from `base` (it had a `/` other than at
+    /// its end), rather than a name to look for at any depth.
+    anchored: bool,
+    /// Whether only folders are matched (the pattern ended in `/`).
+    folders_only: bool,
+}
+
+/// The ignore rules in effect for a folder: those of its own ignore file and
+/// of every folder above it, in the way of a `.gitignore`.
+///
+/// A pattern without a slash, like `_incoming` or `*.tmp`, matches names at
+/// any depth. A pattern with one, like `Podcasts/old`, is a path from the
+/// folder of the ignore file. `*` and `?` match within a name, `**` matches
+/// any number of folders, and a trailing `/` restricts a pattern to folders.
+/// Lines starting with `#` are comments.
+#[derive(Clone, Debug, Default)]
+pub struct IgnoreRules {
+    rules: Vec<Rule>,
+}
+
+impl IgnoreRules {
+    /// The rules for `dir`: these ones, plus whatever the ignore file in `dir`
+    /// adds. Returns the rules unchanged when there is no such file, which is
+    /// most folders.
+    pub fn for_folder(&self, dir: &Path) -> IgnoreRules {
+        match fs::read_to_string(dir.join(IGNORE_FILE)) {
+            Ok(content) => {
+                let mut rules = self.clone();
+                rules.add(dir, &content);
+                rules
+            }
+            Err(_) => self.clone(),
+        }
+    }
+
+    fn add(&mut self, base: &Path, content: &str) {
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let folders_only = line.ends_with('/');
+            let pattern = line.trim_end_matches('/');
+            let anchored = pattern.contains('/');
+            let segments = pattern
+                .trim_start_matches('/')
+                .split('/')
+                .filter(|segment| !segment.is_empty())
+                .map(|segment| segment.to_string())
+                .collect::<Vec<String>>();
+            if segments.is_empty() {
+                continue;
+            }
+            self.rules.push(Rule {
+                base: base.to_path_buf(),
+                segments,
+                anchored,
+                folders_only,
+            });
+        }
+    }
+
+    /// Whether `path`, a file or folder the scan came across, is to be left
+    /// out.
+    pub fn is_ignored(&self, path: &Path, is_folder: bool) -> bool {
+        self.rules.iter().any(|rule| {
+            if rule.folders_only && !is_folder {
+                return false;
+            }
+            let Ok(relative) = path.strip_prefix(&rule.base) else {
+                return false;
+            };
+            let names: Vec<String> = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy().to_string())
+                .collect();
+
+            if rule.anchored {
+                matches_path(&rule.segments, &names)
+            } else {
+                // Parents are checked as the scan descends into them, so
+                // looking at the last name is enough.
+                names
+                    .last()
+                    .is_some_and(|name| rule.segments.len() == 1 && matches_name(&rule.segments[0], name))
+            }
+        })
+    }
+}
+
+/// Whether a folder is marked to be left out as a whole.
+pub fn has_marker(dir: &Path) -> bool {
+    dir.join(MARKER_FILE).exists()
+}
+
+/// Matches path segments against pattern segments, where a `**` segment
+/// stands for any number of names.
+fn matches_path(pattern: &[String], names: &[String]) -> bool {
+    match pattern.split_first() {
+        None => names.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=names.len()).any(|skip| matches_path(rest, &names[skip..]))
+        }
+        Some((first, rest)) => match names.split_first() {
+            Some((name, names)) => matches_name(first, name) && matches_path(rest, names),
+            None => false,
+        },
+    }
+}
+
+/// Matches a single name against a pattern with `*` and `?` wildcards,
+/// case-insensitively like the extension check of the scan.
+fn matches_name(pattern: &str, name: &str) -> bool {
+    fn matches(pattern: &[char], name: &[char]) -> bool {
+        match pattern.split_first() {
+            None => name.is_empty(),
+            Some(('*', rest)) => (0..=name.len()).any(|skip| matches(rest, &name[skip..])),
+            Some((&expected, rest)) => match name.split_first() {
+                Some((&actual, name)) => (expected == '?' || expected == actual) && matches(rest, name),
+                None => false,
+            },
+        }
+    }
+
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+    matches(&pattern, &name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(content: &str) -> IgnoreRules {
+        let mut rules = IgnoreRules::default();
+        rules.add(Path::new("/music"), content);
+        rules
+    }
+
+    #[test]
+    fn names_match_at_any_depth() {
+        let rules = rules("_incoming\n*.tmp");
+
+        assert!(rules.is_ignored(Path::new("/music/_incoming"), true));
+        assert!(rules.is_ignored(Path::new("/music/Artist/_Incoming"), true));
+        assert!(rules.is_ignored(Path::new("/music/Album/01.mp3.tmp"), false));
+        assert!(!rules.is_ignored(Path::new("/music/incoming"), true));
+    }
+
+    #[test]
+    fn paths_match_from_the_folder_of_the_ignore_file() {
+        let rules = rules("Podcasts/old\n/Demos\n");
+
+        assert!(rules.is_ignored(Path::new("/music/Podcasts/old"), true));
+        assert!(!rules.is_ignored(Path::new("/music/Podcasts/new"), true));
+        assert!(!rules.is_ignored(Path::new("/music/Other/Podcasts/old"), true));
+        // a leading slash anchors a single name to the base folder
+        assert!(rules.is_ignored(Path::new("/music/Demos"), true));
+        assert!(!rules.is_ignored(Path::new("/music/Artist/Demos"), true));
+    }
+
+    #[test]
+    fn double_stars_match_any_number_of_folders() {
+        let rules = rules("**/Bootlegs/**/Disc ?");
+
+        assert!(rules.is_ignored(Path::new("/music/Bootlegs/Disc 1"), true));
+        assert!(rules.is_ignored(Path::new("/music/A/Bootlegs/1999/Disc 2"), true));
+        assert!(!rules.is_ignored(Path::new("/music/A/Bootlegs/1999/Disc 10"), true));
+    }
+
+    #[test]
+    fn a_trailing_slash_only_matches_folders() {
+        let rules = rules("# comment\n\nsamples/");
+
+        assert!(rules.is_ignored(Path::new("/music/samples"), true));
+        assert!(!rules.is_ignored(Path::new("/music/Album/samples"), false));
+        assert!(!rules.is_ignored(Path::new("/music/# comment"), true));
+    }
+
+    #[test]
+    fn nothing_outside_the_base_folder_is_matched() {
+        let rules = rules("*");
+
+        assert!(!rules.is_ignored(Path::new("/elsewhere/Album"), true));
+    }
+}
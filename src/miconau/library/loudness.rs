@@ -0,0 +1,344 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::UNIX_EPOCH,
+};
+use serde::{Deserialize, Serialize};
+use super::{LibraryRoot, Playlist, Track};
+
+/// The loudness ReplayGain 2.0 brings everything to, in LUFS. Gains computed
+/// from an analysis aim at the same level as the gains of tagged files, so
+/// tagged and analysed albums play equally loud.
+pub const REFERENCE_LOUDNESS: f64 = -18.0;
+
+/// Name of the file in each library root that keeps the results of the
+/// loudness analysis. Hidden, so the scan passes it by.
+pub const STORE_FILE: &str = ".miconau-loudness.json";
+
+/// Which ReplayGain mpv applies.
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum ReplayGainMode {
+    /// Play files as loud as they are.
+    #[default]
+    Off,
+    /// Bring every track to the same loudness.
+    Track,
+    /// Bring every album to the same loudness, keeping the differences
+    /// between its tracks.
+    Album,
+}
+
+impl ReplayGainMode {
+    /// The value of mpv's `--replaygain` option for this mode.
+    pub fn mpv_value(&self) -> &'static str {
+        match self {
+            ReplayGainMode::Off => "no",
+            ReplayGainMode::Track => "track",
+            ReplayGainMode::Album => "album",
+        }
+    }
+}
+
+/// Parses a ReplayGain tag value such as `-6.48 dB`.
+pub fn parse_gain(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let number = value
+        .strip_suffix("dB")
+        .or_else(|| value.strip_suffix("db"))
+        .unwrap_or(value);
+    number.trim().parse().ok()
+}
+
+/// Whether mpv can take the gain of a track from its tags. Only tracks for
+/// which it can't need the analysis.
+pub fn has_gain_tags(track: &Track) -> bool {
+    track.track_gain.is_some() || track.album_gain.is_some()
+}
+
+/// Averages loudness values the way loudness adds up: as energy, not as
+/// decibels, so one quiet interlude doesn't make a loud album count as quiet.
+fn average_loudness(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    let energy: f64 = values.iter().map(|lufs| 10f64.powf(lufs / 10.0)).sum::<f64>()
+        / values.len() as f64;
+    Some(10.0 * energy.log10())
+}
+
+/// The gain in dB to play `track` of `playlist` at, from the analysis in
+/// `loudness`, for mpv's `replaygain-fallback`. mpv only falls back to it for
+/// files without ReplayGain tags, and those with tags are left to mpv, so
+/// this is None for them, as it is when ReplayGain is off or the track hasn't
+/// been analysed.
+///
+/// The tracks a CUE sheet cuts from one file share what was measured of the
+/// file, which is the loudness of their album. Track mode plays them at that
+/// too, as mpv does with an album gain when a file has no track gain.
+pub fn fallback_gain(
+    mode: ReplayGainMode,
+    playlist: &Playlist,
+    track: &Track,
+    loudness: &HashMap<PathBuf, f64>,
+) -> Option<f64> {
+    if has_gain_tags(track) {
+        return None;
+    }
+    let track_loudness = loudness.get(&track.filename).copied();
+    let album_loudness = || {
+        let values: Vec<f64> = playlist
+            .tracks
+            .iter()
+            .filter(|track| !has_gain_tags(track))
+            .filter_map(|track| loudness.get(&track.filename).copied())
+            .collect();
+        average_loudness(&values)
+    };
+
+    let measured = match mode {
+        ReplayGainMode::Off => None,
+        ReplayGainMode::Track => track_loudness,
+        ReplayGainMode::Album => album_loudness().or(track_loudness),
+    }?;
+    Some(REFERENCE_LOUDNESS - measured)
+}
+
+/// Reads the integrated loudness out of what ffmpeg's `ebur128` filter
+/// prints when it is done:
+///
+/// ```text
+///   Integrated loudness:
+///     I:         -16.8 LUFS
+/// ```
+pub fn parse_ebur128_summary(output: &str) -> Option<f64> {
+    let summary = &output[output.rfind("Integrated loudness:")?..];
+    let line = summary.lines().find(|line| line.trim_start().starts_with("I:"))?;
+    line.trim_start()
+        .trim_start_matches("I:")
+        .trim()
+        .trim_end_matches("LUFS")
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Whether ffmpeg, which the analysis runs on, is installed.
+pub fn ffmpeg_available() -> bool {
+    Command::new("ffmpeg")
+        .arg("-version")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Measures the integrated loudness of a file (EBU R128) with ffmpeg. None if
+/// ffmpeg isn't installed or can't read the file. Slow: the whole file has to
+/// be decoded.
+pub fn analyze(file: &Path) -> Option<f64> {
+    let output = Command::new("ffmpeg")
+        .args(["-nostats", "-hide_banner", "-threads", "1", "-i"])
+        .arg(file)
+        .args(["-map", "0:a:0", "-af", "ebur128=framelog=quiet", "-f", "null", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .output();
+    match output {
+        Ok(output) => parse_ebur128_summary(&String::from_utf8_lossy(&output.stderr)),
+        Err(error) => {
+            println!("Could not run ffmpeg to analyse {:?}: {}", file, error);
+            None
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredLoudness {
+    /// Modification time of the file when it was analysed, in seconds. A file
+    /// that has changed since is analysed again.
+    modified: u64,
+    loudness: f64,
+}
+
+/// The results of the loudness analysis of one library root, kept in a file
+/// in the root itself so they move with the music and survive restarts.
+/// Files are keyed by their path relative to the root.
+pub struct LoudnessStore {
+    root: PathBuf,
+    entries: HashMap<String, StoredLoudness>,
+}
+
+fn modified_seconds(file: &Path) -> Option<u64> {
+    let modified = fs::metadata(file).and_then(|metadata| metadata.modified()).ok()?;
+    modified.duration_since(UNIX_EPOCH).ok().map(|duration| duration.as_secs())
+}
+
+impl LoudnessStore {
+    /// Loads the store of a root. A missing or unreadable store is an empty
+    /// one: the worst that happens is that files are analysed again.
+    pub fn load(root: &LibraryRoot) -> LoudnessStore {
+        let entries = fs::read_to_string(root.folder.join(STORE_FILE))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        LoudnessStore {
+            root: root.folder.clone(),
+            entries,
+        }
+    }
+
+    fn key(&self, file: &Path) -> Option<String> {
+        file.strip_prefix(&self.root)
+            .ok()
+            .map(|relative| relative.to_string_lossy().to_string())
+    }
+
+    /// The stored loudness of a file, unless it has changed since.
+    pub fn get(&self, file: &Path) -> Option<f64> {
+        let entry = self.entries.get(&self.key(file)?)?;
+        (Some(entry.modified) == modified_seconds(file)).then_some(entry.loudness)
+    }
+
+    pub fn insert(&mut self, file: &Path, loudness: f64) {
+        let (Some(key), Some(modified)) = (self.key(file), modified_seconds(file)) else {
+            return;
+        };
+        self.entries.insert(key, StoredLoudness { modified, loudness });
+    }
+
+    /// Writes the store back into its root. Written to a temporary file first
+    /// and renamed into place, so an interrupted write can't lose the results
+    /// gathered so far.
+    pub fn save(&self) -> std::io::Result<()> {
+        let path = self.root.join(STORE_FILE);
+        let temporary = self.root.join(format!("{}.tmp", STORE_FILE));
+        fs::write(&temporary, serde_json::to_string(&self.entries)?)?;
+        fs::rename(temporary, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+
+    fn track(filename: &str, track_gain: Option<f64>) -> Track {
+        Track {
+            filename: PathBuf::from(filename),
+            artist: None,
+            title: None,
+            has_cover_art: false,
+            start: None,
+            end: None,
+            album: None,
+            album_artist: None,
+            disc_number: None,
+            track_number: None,
+            track_gain,
+            album_gain: None,
+            genre: None,
+            added: None,
+        }
+    }
+
+    #[test]
+    fn parses_replaygain_tag_values() {
+        assert_eq!(parse_gain("-6.48 dB"), Some(-6.48));
+        assert_eq!(parse_gain("+2.5 dB"), Some(2.5));
+        assert_eq!(parse_gain(" 1.00db"), Some(1.0));
+        assert_eq!(parse_gain("-3"), Some(-3.0));
+        assert_eq!(parse_gain("loud"), None);
+    }
+
+    #[test]
+    fn reads_the_integrated_loudness_from_the_ebur128_summary() {
+        let output = "[Parsed_ebur128_0 @ 0x55] Summary:
+
+  Integrated loudness:
+    I:         -16.8 LUFS
+    Threshold: -27.1 LUFS
+
+  Loudness range:
+    LRA:         6.2 LU
+";
+        assert_eq!(parse_ebur128_summary(output), Some(-16.8));
+        assert_eq!(parse_ebur128_summary("No such file or directory"), None);
+    }
+
+    #[test]
+    fn fallback_gains_bring_analysed_tracks_to_the_reference_loudness() {
+        let playlist = Playlist {
+            id: 0,
+            title: "Album".to_string(),
+            tracks: vec![track("/a/1.flac", None), track("/a/2.flac", None)],
+            cover_source: None,
+            smart: false,
+        };
+        let loudness = HashMap::from([
+            (PathBuf::from("/a/1.flac"), -8.0),
+            (PathBuf::from("/a/2.flac"), -18.0),
+        ]);
+
+        assert_eq!(
+            fallback_gain(ReplayGainMode::Track, &playlist, &playlist.tracks[0], &loudness),
+            Some(-10.0)
+        );
+        // the loud track dominates the album, as it does to the ear
+        let album_gain =
+            fallback_gain(ReplayGainMode::Album, &playlist, &playlist.tracks[1], &loudness)
+                .unwrap();
+        assert!((album_gain - -7.40).abs() < 0.01, "album gain was {}", album_gain);
+
+        assert_eq!(
+            fallback_gain(ReplayGainMode::Off, &playlist, &playlist.tracks[0], &loudness),
+            None
+        );
+    }
+
+    #[test]
+    fn tagged_and_unanalysed_tracks_get_no_fallback_gain() {
+        let playlist = Playlist {
+            id: 0,
+            title: "Album".to_string(),
+            tracks: vec![track("/a/1.flac", Some(-3.0)), track("/a/2.flac", None)],
+            cover_source: None,
+            smart: false,
+        };
+        let loudness = HashMap::from([(PathBuf::from("/a/1.flac"), -8.0)]);
+
+        assert_eq!(
+            fallback_gain(ReplayGainMode::Track, &playlist, &playlist.tracks[0], &loudness),
+            None
+        );
+        assert_eq!(
+            fallback_gain(ReplayGainMode::Album, &playlist, &playlist.tracks[1], &loudness),
+            None
+        );
+    }
+
+    #[test]
+    fn the_store_forgets_files_that_changed() {
+        let temp = TempDir::new("loudness-store");
+        let folder = &temp.path;
+        fs::create_dir_all(folder.join("Album")).unwrap();
+        let file = folder.join("Album/01.flac");
+        fs::write(&file, "").unwrap();
+        let root = LibraryRoot::new(folder.to_str().unwrap());
+
+        let mut store = LoudnessStore::load(&root);
+        store.insert(&file, -12.5);
+        store.save().unwrap();
+
+        let reloaded = LoudnessStore::load(&root);
+        assert_eq!(reloaded.get(&file), Some(-12.5));
+
+        let file_time = fs::File::options().write(true).open(&file).unwrap();
+        file_time
+            .set_modified(UNIX_EPOCH + std::time::Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(reloaded.get(&file), None);
+    }
+}
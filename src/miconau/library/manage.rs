@@ -0,0 +1,498 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use super::{cover::CoverSource, read_track, Grouping, Library, LibraryRoot, Playlist, Track};
+
+/// Name of the folder in each library root that deleted files are moved to.
+/// Hidden, so the scan passes it by. Nothing is ever deleted for good from the
+/// web UI: a playlist deleted by mistake is one move away from coming back.
+pub const TRASH_FOLDER: &str = ".miconau-trash";
+
+/// The folder in the trash of `root` for one deletion, named by when it
+/// happened. Whatever is deleted keeps its path relative to the root in
+/// there, so it is clear where to put it back.
+fn trash_batch(root: &LibraryRoot) -> PathBuf {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or(0);
+    root.folder.join(TRASH_FOLDER).join(millis.to_string())
+}
+
+/// Moves a file or folder, across file systems too. A rename can't move to
+/// another drive, which is where a track moved between library roots goes.
+fn move_path(from: &Path, to: &Path) -> Result<(), String> {
+    if to.exists() {
+        return Err(format!("{:?} already exists", to));
+    }
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent).map_err(|error| format!("Could not create {:?}: {}", parent, error))?;
+    }
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    if from.is_dir() {
+        return Err(format!("Could not move {:?} to {:?}", from, to));
+    }
+    fs::copy(from, to)
+        .and_then(|_| fs::remove_file(from))
+        .map_err(|error| format!("Could not move {:?} to {:?}: {}", from, to, error))
+}
+
+fn move_to_trash(root: &LibraryRoot, batch: &Path, path: &Path) -> Result<(), String> {
+    let relative = path
+        .strip_prefix(&root.folder)
+        .map_err(|_| format!("{:?} is not in library root {}", path, root.name()))?;
+    move_path(path, &batch.join(relative))
+}
+
+/// Swaps the `from` folder at the start of `path` for `to`.
+fn replace_prefix(path: &Path, from: &Path, to: &Path) -> PathBuf {
+    match path.strip_prefix(from) {
+        Ok(relative) => to.join(relative),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+impl Library {
+    /// The root a file or folder of the library is in.
+    pub fn root_of(&self, path: &Path) -> Option<&LibraryRoot> {
+        self.roots
+            .iter()
+            .filter(|root| path.starts_with(&root.folder))
+            .max_by_key(|root| root.folder.components().count())
+    }
+
+    /// Takes a playlist out of the library, along with the entries that lead
+    /// `find_track` to it.
+    pub(super) fn remove_playlist_at(&mut self, index: usize) -> Playlist {
+        let playlist = self.playlists.remove(index);
+        for ids in self.folder_playlists.values_mut() {
+            ids.retain(|id| *id != playlist.id);
+        }
+        self.folder_playlists.retain(|_, ids| !ids.is_empty());
+        self.search_index.remove_playlist(playlist.id);
+        playlist
+    }
+
+    /// The audio files of a playlist. Fewer than its tracks when a CUE sheet
+    /// cuts several of them from one file.
+    pub fn playlist_files(&self, index: usize) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = Vec::new();
+        for track in &self.playlists[index].tracks {
+            if !files.contains(&track.filename) {
+                files.push(track.filename.clone());
+            }
+        }
+        files
+    }
+
+    /// The folder a playlist was made from, when grouping by folders.
+    pub fn playlist_folder(&self, index: usize) -> Result<PathBuf, String> {
+        self.ensure_not_smart(index)?;
+        if self.scan_config.grouping != Grouping::Folders {
+            return Err("Playlists grouped by tags are not folders; edit their tags instead".to_string());
+        }
+        self.playlists[index]
+            .tracks
+            .first()
+            .and_then(|track| track.filename.parent())
+            .map(Path::to_path_buf)
+            .ok_or_else(|| "Playlist has no tracks".to_string())
+    }
+
+    /// Deletes a playlist by moving its files to the trash. A folder holding
+    /// nothing else, such as other playlists' tracks or subfolders, goes to
+    /// the trash as a whole, covers, CUE sheets and all; from any other folder
+    /// only the audio files of the playlist are taken.
+    pub fn delete_playlist(&mut self, index: usize) -> Result<(), String> {
+        self.ensure_not_smart(index)?;
+        let files = self.playlist_files(index);
+        let root = self
+            .root_of(&files[0])
+            .ok_or_else(|| format!("{:?} is not in the library", files[0]))?
+            .clone();
+        let batch = trash_batch(&root);
+
+        let mut folders: Vec<PathBuf> = Vec::new();
+        for file in &files {
+            let folder = file.parent().unwrap_or(&root.folder).to_path_buf();
+            if !folders.contains(&folder) {
+                folders.push(folder);
+            }
+        }
+
+        let playlist_files: HashSet<&PathBuf> = files.iter().collect();
+        for folder in folders {
+            let holds_only_playlist = folder != root.folder
+                && fs::read_dir(&folder)
+                    .map_err(|error| format!("Could not read {:?}: {}", folder, error))?
+                    .flatten()
+                    .all(|entry| {
+                        let path = entry.path();
+                        let is_audio = path
+                            .extension()
+                            .map(|extension| extension.to_string_lossy().to_lowercase())
+                            .is_some_and(|extension| self.scan_config.is_audio_extension(&extension));
+                        path.is_file() && (!is_audio || playlist_files.contains(&path))
+                    });
+
+            if holds_only_playlist {
+                move_to_trash(&root, &batch, &folder)?;
+            } else {
+                for file in files.iter().filter(|file| file.parent() == Some(&folder)) {
+                    move_to_trash(&root, &batch, file)?;
+                }
+            }
+        }
+
+        let playlist = self.remove_playlist_at(index);
+        self.forget_ratings(&playlist);
+        println!("Playlist deleted: {} (moved to {:?})", playlist.title, batch);
+        Ok(())
+    }
+
+    /// Renames the folder of a playlist. Subfolders move along, so the titles
+    /// of their playlists change too. `name` is a single folder name.
+    pub fn rename_playlist(&mut self, index: usize, name: &str) -> Result<(), String> {
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+            return Err(format!("Invalid folder name: {:?}", name));
+        }
+        let folder = self.playlist_folder(index)?;
+        let root = self
+            .root_of(&folder)
+            .ok_or_else(|| format!("{:?} is not in the library", folder))?
+            .clone();
+        if folder == root.folder {
+            return Err("The library folder itself can't be renamed".to_string());
+        }
+        let renamed = folder.with_file_name(name);
+        move_path(&folder, &renamed)?;
+
+        // Everything is where it was, only under another name, so the
+        // playlists are moved over in memory instead of being scanned again.
+        let mut moved: Vec<Playlist> = Vec::new();
+        let mut moved_tracks: Vec<(String, Option<String>)> = Vec::new();
+        let mut moved_playlists: Vec<(String, Option<String>)> = Vec::new();
+        let mut index = 0;
+        while index < self.playlists.len() {
+            let is_inside = self.playlists[index]
+                .tracks
+                .iter()
+                .any(|track| track.filename.starts_with(&folder));
+            if is_inside {
+                moved.push(self.remove_playlist_at(index));
+            } else {
+                index += 1;
+            }
+        }
+        for mut playlist in moved {
+            for track in &mut playlist.tracks {
+                let location = track.location();
+                track.filename = replace_prefix(&track.filename, &folder, &renamed);
+                moved_tracks.push((location, Some(track.location())));
+            }
+            playlist.cover_source = playlist.cover_source.map(|source| match source {
+                CoverSource::Embedded(path) => CoverSource::Embedded(replace_prefix(&path, &folder, &renamed)),
+                CoverSource::File(path) => CoverSource::File(replace_prefix(&path, &folder, &renamed)),
+            });
+            if let Some(dir) = playlist.tracks.first().and_then(|track| track.filename.parent()) {
+                let title = root.playlist_title(dir);
+                moved_playlists.push((playlist.title.clone(), Some(title.clone())));
+                playlist.title = title;
+            }
+            self.insert_playlist(playlist);
+        }
+        self.ratings.follow(&moved_tracks, &moved_playlists);
+        self.loudness = self
+            .loudness
+            .drain()
+            .map(|(file, loudness)| (replace_prefix(&file, &folder, &renamed), loudness))
+            .collect();
+        println!("Folder renamed: {:?} to {:?}", folder, renamed);
+        Ok(())
+    }
+
+    /// Forgets the ratings of a playlist that is gone and of its tracks. The
+    /// rating of its title stays while another playlist has that title.
+    fn forget_ratings(&mut self, playlist: &Playlist) {
+        let tracks: Vec<(String, Option<String>)> = playlist.tracks.iter().map(|track| (track.location(), None)).collect();
+        let title_is_gone = !self.playlists.iter().any(|other| other.title == playlist.title);
+        let playlists = if title_is_gone { vec![(playlist.title.clone(), None)] } else { Vec::new() };
+        self.ratings.follow(&tracks, &playlists);
+    }
+
+    /// Takes a track out of its playlist, moving its file to `to` or, without
+    /// a destination, to the trash. A playlist left without tracks is removed.
+    fn take_track(&mut self, playlist_index: usize, track_index: usize, to: Option<&Path>) -> Result<Track, String> {
+        self.ensure_not_smart(playlist_index)?;
+        let track = &self.playlists[playlist_index].tracks[track_index];
+        if track.start.is_some() {
+            return Err("The track is cut from a single file by a CUE sheet and can only go with its album".to_string());
+        }
+        let file = track.filename.clone();
+        match to {
+            Some(to) => move_path(&file, to)?,
+            None => {
+                let root = self
+                    .root_of(&file)
+                    .ok_or_else(|| format!("{:?} is not in the library", file))?
+                    .clone();
+                move_to_trash(&root, &trash_batch(&root), &file)?;
+            }
+        }
+
+        let playlist = &mut self.playlists[playlist_index];
+        let track = playlist.tracks.remove(track_index);
+        if playlist.cover_source == Some(CoverSource::Embedded(file.clone())) {
+            playlist.cover_source = playlist
+                .tracks
+                .first()
+                .filter(|track| track.has_cover_art)
+                .map(|track| CoverSource::Embedded(track.filename.clone()));
+        }
+        if playlist.tracks.is_empty() {
+            let playlist = self.remove_playlist_at(playlist_index);
+            self.forget_ratings(&playlist);
+        } else {
+            self.reindex_playlist(playlist_index);
+        }
+        Ok(track)
+    }
+
+    /// Deletes a single track by moving its file to the trash.
+    pub fn delete_track(&mut self, playlist_index: usize, track_index: usize) -> Result<(), String> {
+        let track = self.take_track(playlist_index, track_index, None)?;
+        self.ratings.follow(&[(track.location(), None)], &[]);
+        println!("Track deleted: {:?}", track.filename);
+        Ok(())
+    }
+
+    /// Adds an audio file to a playlist, moving it from `file` into the folder
+    /// of the playlist as `name`. A file of that name already there is left
+    /// alone and the file isn't added.
+    pub fn add_track(&mut self, index: usize, file: &Path, name: &str) -> Result<(), String> {
+        let is_audio = Path::new(name)
+            .extension()
+            .is_some_and(|extension| self.scan_config.is_audio_extension(&extension.to_string_lossy().to_lowercase()));
+        if !is_audio {
+            return Err(format!("{} is not an audio file", name));
+        }
+        let destination = self.playlist_folder(index)?.join(name);
+        move_path(file, &destination)?;
+
+        let track = read_track(destination);
+        let playlist = &mut self.playlists[index];
+        if playlist.cover_source.is_none() && track.has_cover_art {
+            playlist.cover_source = Some(CoverSource::Embedded(track.filename.clone()));
+        }
+        let position = playlist
+            .tracks
+            .partition_point(|existing| (&existing.filename, existing.start) <= (&track.filename, track.start));
+        println!("Track added: {:?} to {}", track.filename, playlist.title);
+        playlist.tracks.insert(position, track);
+        self.reindex_playlist(index);
+        Ok(())
+    }
+
+    /// Moves a track into the folder of another playlist.
+    pub fn move_track(&mut self, from_index: usize, track_index: usize, to_index: usize) -> Result<(), String> {
+        if from_index == to_index {
+            return Err("The track is in that playlist already".to_string());
+        }
+        let target_folder = self.playlist_folder(to_index)?;
+        let target_id = self.playlists[to_index].id;
+        let file_name = self.playlists[from_index].tracks[track_index]
+            .filename
+            .file_name()
+            .map(|name| name.to_owned())
+            .ok_or_else(|| "Track has no file name".to_string())?;
+        let destination = target_folder.join(file_name);
+
+        // Taking the track out can remove its playlist and shift the one it
+        // goes to, which is why that one is found again by its id.
+        let mut track = self.take_track(from_index, track_index, Some(&destination))?;
+        if let Some(loudness) = self.loudness.remove(&track.filename) {
+            self.loudness.insert(destination.clone(), loudness);
+        }
+        let location = track.location();
+        track.filename = destination;
+        self.ratings.follow(&[(location, Some(track.location()))], &[]);
+        let target_index = self
+            .playlists
+            .iter()
+            .position(|playlist| playlist.id == target_id)
+            .ok_or_else(|| "The playlist the track goes to is gone".to_string())?;
+        let target = &mut self.playlists[target_index];
+        let position = target
+            .tracks
+            .partition_point(|existing| (&existing.filename, existing.start) <= (&track.filename, track.start));
+        println!("Track moved: {:?} into {}", track.filename, target.title);
+        target.tracks.insert(position, track);
+        self.reindex_playlist(target_index);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::{RatingChange, ScanConfig};
+    use crate::utils::TempDir;
+
+    fn files(temp: &TempDir, relatives: &[&str]) {
+        for relative in relatives {
+            temp.file(relative, "");
+        }
+    }
+
+    fn scan(temp: &TempDir) -> Library {
+        Library::new(vec![LibraryRoot::new(temp.path.to_str().unwrap())], ScanConfig::default())
+    }
+
+    /// Everything in the trash, relative to the folder of its deletion.
+    fn trash(temp: &TempDir) -> Vec<String> {
+        fn walk(dir: &Path, base: &Path, found: &mut Vec<String>) {
+            for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+                if entry.path().is_dir() {
+                    walk(&entry.path(), base, found);
+                } else {
+                    let relative = entry.path().strip_prefix(base).unwrap().to_path_buf();
+                    // leave out the folder of the deletion, named by time
+                    let relative: PathBuf = relative.components().skip(1).collect();
+                    found.push(relative.to_string_lossy().replace('\\', "/"));
+                }
+            }
+        }
+        let mut found = Vec::new();
+        let trash = temp.path.join(TRASH_FOLDER);
+        walk(&trash, &trash, &mut found);
+        found.sort();
+        found
+    }
+
+    fn titles(library: &Library) -> Vec<String> {
+        library.playlists.iter().map(|playlist| playlist.title.clone()).collect()
+    }
+
+    fn position(library: &Library, title: &str) -> usize {
+        library.playlists.iter().position(|playlist| playlist.title == title).unwrap()
+    }
+
+    #[test]
+    fn deleted_playlists_go_to_the_trash() {
+        let temp = TempDir::new("delete-playlist");
+        files(&temp, &["Album/01.mp3", "Album/cover.jpg", "Mixed/01.mp3", "Mixed/Live/01.mp3", "Other/01.mp3"]);
+        let mut library = scan(&temp);
+
+        library.delete_playlist(position(&library, "Album")).unwrap();
+        // a folder with a subfolder only loses the playlist's own files
+        library.delete_playlist(position(&library, "Mixed")).unwrap();
+
+        assert_eq!(titles(&library), vec![format!("Mixed{}Live", std::path::MAIN_SEPARATOR), "Other".to_string()]);
+        assert_eq!(trash(&temp), vec!["Album/01.mp3", "Album/cover.jpg", "Mixed/01.mp3"]);
+        assert!(!temp.path.join("Album").exists());
+        assert!(temp.path.join("Mixed/Live/01.mp3").exists());
+        // the trash is hidden from the scan
+        assert_eq!(titles(&scan(&temp)), titles(&library));
+    }
+
+    #[test]
+    fn renamed_folders_take_their_subfolders_along() {
+        let temp = TempDir::new("rename-playlist");
+        files(&temp, &["Album/01.mp3", "Album/Bonus/01.mp3", "Other/01.mp3"]);
+        let mut library = scan(&temp);
+        library.loudness.insert(temp.path.join("Album/01.mp3"), -12.0);
+
+        library.rename_playlist(position(&library, "Album"), "Zebra").unwrap();
+
+        let expected = vec![
+            "Other".to_string(),
+            "Zebra".to_string(),
+            format!("Zebra{}Bonus", std::path::MAIN_SEPARATOR),
+        ];
+        assert_eq!(titles(&library), expected);
+        assert_eq!(titles(&scan(&temp)), expected);
+        let renamed = temp.path.join("Zebra/01.mp3");
+        assert!(library.find_track(&renamed.to_string_lossy()).is_some());
+        assert_eq!(library.loudness.get(&renamed), Some(&-12.0));
+
+        assert!(library.rename_playlist(position(&library, "Other"), "Zebra").is_err());
+        assert!(library.rename_playlist(position(&library, "Other"), "../Up").is_err());
+    }
+
+    #[test]
+    fn tracks_are_deleted_and_moved_between_playlists() {
+        let temp = TempDir::new("move-track");
+        files(&temp, &["A/01.mp3", "A/02.mp3", "B/03.mp3"]);
+        let mut library = scan(&temp);
+
+        library.delete_track(position(&library, "A"), 1).unwrap();
+        assert_eq!(trash(&temp), vec!["A/02.mp3"]);
+
+        // moving the last track out of A removes the playlist
+        library.move_track(position(&library, "A"), 0, position(&library, "B")).unwrap();
+        assert_eq!(titles(&library), vec!["B".to_string()]);
+        let files: Vec<PathBuf> = library.playlists[0].tracks.iter().map(|track| track.filename.clone()).collect();
+        assert_eq!(files, vec![temp.path.join("B/01.mp3"), temp.path.join("B/03.mp3")]);
+        assert!(library.find_track(&temp.path.join("B/01.mp3").to_string_lossy()).is_some());
+    }
+
+    #[test]
+    fn ratings_follow_what_is_renamed_moved_and_deleted() {
+        let temp = TempDir::new("manage-ratings");
+        files(&temp, &["A/01.mp3", "A/02.mp3", "B/03.mp3", "C/04.mp3"]);
+        let mut library = scan(&temp);
+        let location = |relative: &str| temp.path.join(relative).to_string_lossy().to_string();
+        let stars = RatingChange { stars: Some(4), ..RatingChange::default() };
+        for title in ["A", "B", "C"] {
+            let index = position(&library, title);
+            library.rate_playlist(index, &stars).unwrap();
+            library.rate_track(index, 0, &stars).unwrap();
+        }
+
+        library.rename_playlist(position(&library, "A"), "Z").unwrap();
+        assert_eq!(library.ratings.playlist("Z").stars, Some(4));
+        assert_eq!(library.ratings.playlist("A").stars, None);
+        assert_eq!(library.ratings.track(&location("Z/01.mp3")).stars, Some(4));
+        assert_eq!(library.ratings.track(&location("A/01.mp3")).stars, None);
+
+        library.move_track(position(&library, "Z"), 0, position(&library, "B")).unwrap();
+        assert_eq!(library.ratings.track(&location("B/01.mp3")).stars, Some(4));
+        assert_eq!(library.ratings.track(&location("Z/01.mp3")).stars, None);
+
+        // a folder of the same name later on starts without ratings
+        library.delete_playlist(position(&library, "C")).unwrap();
+        assert_eq!(library.ratings.playlist("C").stars, None);
+        assert_eq!(library.ratings.track(&location("C/04.mp3")).stars, None);
+        library.delete_track(position(&library, "B"), 0).unwrap();
+        assert_eq!(library.ratings.track(&location("B/01.mp3")).stars, None);
+        assert_eq!(library.ratings.playlist("B").stars, Some(4));
+    }
+
+    #[test]
+    fn tracks_are_added_to_the_folder_of_their_playlist() {
+        let temp = TempDir::new("add-track");
+        files(&temp, &["A/01.mp3", "A/03.mp3"]);
+        let incoming = TempDir::new("add-track-incoming");
+        files(&incoming, &["02.mp3", "04.mp3", "notes.txt"]);
+        let mut library = scan(&temp);
+        let index = position(&library, "A");
+
+        library.add_track(index, &incoming.path.join("02.mp3"), "02.mp3").unwrap();
+        let files: Vec<PathBuf> = library.playlists[index].tracks.iter().map(|track| track.filename.clone()).collect();
+        assert_eq!(files, vec![temp.path.join("A/01.mp3"), temp.path.join("A/02.mp3"), temp.path.join("A/03.mp3")]);
+        assert!(!incoming.path.join("02.mp3").exists());
+        assert!(library.find_track(&temp.path.join("A/02.mp3").to_string_lossy()).is_some());
+        assert_eq!(library.search("02", 10).tracks.len(), 1);
+
+        // neither over a file that is there already nor anything but audio
+        assert!(library.add_track(index, &incoming.path.join("04.mp3"), "01.mp3").is_err());
+        assert!(incoming.path.join("04.mp3").exists());
+        assert!(library.add_track(index, &incoming.path.join("notes.txt"), "notes.txt").is_err());
+        assert_eq!(library.playlists[index].tracks.len(), 3);
+    }
+}
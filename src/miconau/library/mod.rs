@@ -1,12 +1,40 @@
+mod cover;
+mod cue;
+mod ignore;
+pub mod loudness;
+mod manage;
+mod ratings;
+mod search;
+mod smart;
+mod streams;
+mod tags;
+mod text;
+
 use std::{
+    collections::HashMap,
     fs,
     path::{Path, PathBuf},
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
 use lofty::prelude::*;
 use lofty::probe::Probe;
+use lofty::tag::ItemKey;
 use crate::utils::format_duration;
 
+pub use cover::{read_cover, thumbnail, CoverSource, ThumbnailCache, ThumbnailKey, IMAGE_EXTENSIONS};
+pub use ratings::{Rating, RatingChange, Ratings};
+pub use search::SearchResults;
+use search::SearchIndex;
+pub use smart::{read_smart_playlists, SmartPlaylist};
+pub use streams::probe_stream;
+pub use tags::TagChanges;
+use ignore::IgnoreRules;
+
+/// A folder holding a file of this name is a gapless album, such as a live
+/// recording or a DJ mix cut into tracks: its tracks play into each other
+/// without a crossfade, like the tracks of a CUE sheet.
+pub const GAPLESS_MARKER_FILE: &str = ".gapless";
+
 /// How often the scan reports that it is still alive while working through a
 /// single folder.
 const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
@@ -50,6 +78,7 @@ impl ScanProgress {
     }
 }
 
+#[derive(Clone)]
 pub struct Track {
     pub filename: PathBuf,
     pub artist: Option<String>,
@@ -58,6 +87,25 @@ pub struct Track {
     /// a few thousand albums with embedded covers add up to gigabytes, so
     /// covers are read from the file again when they are served.
     pub has_cover_art: bool,
+    /// Where the track starts within `filename`, for the tracks a CUE sheet
+    /// cuts out of a single-file album. None for a track that is a whole file.
+    pub start: Option<Duration>,
+    /// Where such a track ends. None plays on to the end of the file.
+    pub end: Option<Duration>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub disc_number: Option<u32>,
+    pub track_number: Option<u32>,
+    /// ReplayGain of the track and of its album from the tags, in dB. mpv
+    /// applies them itself; they are read so that only the tracks without
+    /// them are left for the loudness analysis.
+    pub track_gain: Option<f64>,
+    pub album_gain: Option<f64>,
+    pub genre: Option<String>,
+    /// When the file came into the library: when it was created where the
+    /// file system knows, which copying and uploading set, and otherwise
+    /// when it was last modified.
+    pub added: Option<SystemTime>,
 }
 
 impl Track {
@@ -71,51 +119,205 @@ impl Track {
                 .unwrap_or_else(|| "Unknown".to_string())
         })
     }
+
+    /// What mpv is given to play this track, and what it reports back as the
+    /// `path` of the playing file. That is the file itself, or for a track of
+    /// a CUE sheet the part of the file between its start and end.
+    pub fn location(&self) -> String {
+        match self.start {
+            Some(start) => cue::edl_location(&self.filename, start, self.end),
+            None => self.filename.to_string_lossy().to_string(),
+        }
+    }
 }
 
 /// Reads the tags of one audio file. The scan already opens every file here,
 /// so noting whether it has artwork costs nothing extra and saves reopening
 /// the first track of every playlist.
 fn read_track(path: PathBuf) -> Track {
-    let (artist, title, has_cover_art) = match Probe::open(&path).and_then(|p| p.read()) {
-        Ok(tagged_file) => {
-            match tagged_file.primary_tag().or_else(|| tagged_file.first_tag()) {
-                Some(tag) => (
-                    tag.artist().map(|s| s.to_string()),
-                    tag.title().map(|s| s.to_string()),
-                    !tag.pictures().is_empty(),
-                ),
-                None => (None, None, false),
-            }
+    let added = fs::metadata(&path)
+        .ok()
+        .and_then(|metadata| metadata.created().or_else(|_| metadata.modified()).ok());
+    let mut track = Track {
+        filename: path,
+        artist: None,
+        title: None,
+        has_cover_art: false,
+        start: None,
+        end: None,
+        album: None,
+        album_artist: None,
+        disc_number: None,
+        track_number: None,
+        track_gain: None,
+        album_gain: None,
+        genre: None,
+        added,
+    };
+
+    let Ok(tagged_file) = Probe::open(&track.filename).and_then(|p| p.read()) else {
+        return track;
+    };
+    if let Some(tag) = tagged_file.primary_tag().or_else(|| tagged_file.first_tag()) {
+        track.artist = tag.artist().map(|s| s.to_string());
+        track.title = tag.title().map(|s| s.to_string());
+        track.has_cover_art = !tag.pictures().is_empty();
+        track.album = tag.album().map(|s| s.to_string());
+        track.album_artist = tag.get_string(&ItemKey::AlbumArtist).map(|s| s.to_string());
+        track.disc_number = tag.disk();
+        track.track_number = tag.track();
+        track.track_gain = tag
+            .get_string(&ItemKey::ReplayGainTrackGain)
+            .and_then(loudness::parse_gain);
+        track.album_gain = tag
+            .get_string(&ItemKey::ReplayGainAlbumGain)
+            .and_then(loudness::parse_gain);
+        track.genre = tag.genre().map(|s| s.to_string());
+    }
+    track
+}
+
+/// Turns a CUE sheet into the tracks it describes, cut out of the audio files
+/// it names. `files` are the audio files of the sheet's folder as the scan
+/// read them; a track takes its cover and, if the sheet has none, its artist
+/// from the file it is cut from.
+///
+/// Returns the tracks together with the files they replace, as those must not
+/// show up a second time as one long track. Tracks in files that aren't there
+/// are skipped, but a sheet naming `album.wav` is matched to the `album.flac`
+/// next to it: rippers write the sheet before the audio is compressed.
+fn read_cue_tracks(cue_path: &Path, files: &HashMap<PathBuf, Track>) -> (Vec<Track>, Vec<PathBuf>) {
+    let content = match fs::read(cue_path) {
+        // Older sheets are often Latin-1 rather than UTF-8. A lossy read keeps
+        // their timings, which is what matters, and most of their titles.
+        Ok(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+        Err(error) => {
+            println!("Could not read CUE sheet {:?}: {}", cue_path, error);
+            return (Vec::new(), Vec::new());
         }
-        Err(_) => (None, None, false),
+    };
+    let sheet = cue::parse(&content);
+    let folder = cue_path.parent().unwrap_or(Path::new(""));
+
+    let resolve = |name: &str| -> Option<&Track> {
+        let named = folder.join(name);
+        files.get(&named).or_else(|| {
+            let stem = named.file_stem()?;
+            files
+                .values()
+                .find(|track| track.filename.parent() == named.parent()
+                    && track.filename.file_stem() == Some(stem))
+        })
     };
 
-    Track {
-        filename: path,
-        artist,
-        title,
-        has_cover_art,
+    let mut tracks: Vec<Track> = Vec::new();
+    let mut replaced: Vec<PathBuf> = Vec::new();
+    for (position, cue_track) in sheet.tracks.iter().enumerate() {
+        let Some(file) = resolve(&cue_track.file) else {
+            println!("CUE sheet {:?} names a missing file: {}", cue_path, cue_track.file);
+            continue;
+        };
+
+        // A track ends where the next one in the same file begins.
+        let end = sheet.tracks[position + 1..]
+            .iter()
+            .find(|next| next.file == cue_track.file)
+            .map(|next| next.start);
+
+        tracks.push(Track {
+            filename: file.filename.clone(),
+            artist: cue_track
+                .performer
+                .clone()
+                .or_else(|| sheet.performer.clone())
+                .or_else(|| file.artist.clone()),
+            title: Some(
+                cue_track
+                    .title
+                    .clone()
+                    .unwrap_or_else(|| format!("Track {:02}", cue_track.number)),
+            ),
+            has_cover_art: file.has_cover_art,
+            start: Some(cue_track.start),
+            end,
+            album: sheet.title.clone().or_else(|| file.album.clone()),
+            album_artist: sheet.performer.clone().or_else(|| file.album_artist.clone()),
+            disc_number: file.disc_number,
+            track_number: Some(cue_track.number),
+            // Only the gain of the whole file is known, which is the gain of
+            // the album as far as a single-file album goes, whatever the tag
+            // it was written to.
+            track_gain: None,
+            album_gain: file.album_gain.or(file.track_gain),
+            genre: file.genre.clone(),
+            added: file.added,
+        });
+        if !replaced.contains(&file.filename) {
+            replaced.push(file.filename.clone());
+        }
     }
+
+    (tracks, replaced)
 }
 
-/// Reads the embedded cover of an audio file. Called when a cover is actually
-/// requested rather than during the scan, so covers never accumulate in memory.
-pub fn read_cover_art(path: &Path) -> Option<(Vec<u8>, String)> {
-    match Probe::open(path).and_then(|p| p.read()) {
-        Ok(tagged_file) => {
-            if let Some(tag) = tagged_file.primary_tag().or_else(|| tagged_file.first_tag()) {
-                if let Some(picture) = tag.pictures().first() {
-                    let mime = picture
-                        .mime_type()
-                        .map(|m| m.to_string())
-                        .unwrap_or_else(|| "image/jpeg".to_string());
-                    return Some((picture.data().to_vec(), mime));
-                }
-            }
-            None
+/// The audio file extensions the scan picks up unless it is told otherwise.
+pub const DEFAULT_AUDIO_EXTENSIONS: [&str; 6] = ["mp3", "flac", "wav", "ogg", "oga", "opus"];
+
+/// How the library is scanned. The library keeps it, so that anything
+/// scanned again later is found the same way as by the first scan.
+#[derive(Clone, Debug)]
+pub struct ScanConfig {
+    /// Extensions of the files that are tracks, lower case and without the
+    /// dot. mpv plays far more formats than the default list, but a library
+    /// can also hold files that mpv would happily play and nobody wants as a
+    /// track, so the list is up to the user.
+    pub audio_extensions: Vec<String>,
+    pub grouping: Grouping,
+}
+
+/// How tracks are put together into playlists.
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum Grouping {
+    /// One playlist per folder with audio files in it.
+    #[default]
+    Folders,
+    /// One playlist per album, by the album and album artist tags of the
+    /// tracks, wherever in the library they are. Gathers albums split into
+    /// `CD1`/`CD2` folders and splits up folders of unrelated tracks. Tracks
+    /// without an album tag stay in a playlist for their folder.
+    Tags,
+}
+
+impl ScanConfig {
+    /// A config for the given extensions, or the default ones. Extensions are
+    /// accepted with or without a leading dot and in any case.
+    pub fn new(audio_extensions: Option<Vec<String>>) -> ScanConfig {
+        let audio_extensions = match audio_extensions {
+            Some(extensions) => extensions
+                .iter()
+                .map(|extension| extension.trim().trim_start_matches('.').to_lowercase())
+                .filter(|extension| !extension.is_empty())
+                .collect(),
+            None => DEFAULT_AUDIO_EXTENSIONS
+                .iter()
+                .map(|extension| extension.to_string())
+                .collect(),
+        };
+        ScanConfig {
+            audio_extensions,
+            grouping: Grouping::default(),
         }
-        Err(_) => None,
+    }
+
+    /// Whether a lower case extension is one of an audio file.
+    pub fn is_audio_extension(&self, extension: &str) -> bool {
+        self.audio_extensions.iter().any(|audio| audio == extension)
+    }
+}
+
+impl Default for ScanConfig {
+    fn default() -> ScanConfig {
+        ScanConfig::new(None)
     }
 }
 
@@ -133,17 +335,93 @@ fn playlist_title(dir: &Path, root: &Path) -> String {
     }
 }
 
+/// One folder the library is read from. A library can span several, such as
+/// two drives, and a label tells apart the playlists of roots that have
+/// folders of the same name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LibraryRoot {
+    pub folder: PathBuf,
+    /// Put in front of the titles of the playlists from this root, as if it
+    /// were one more folder level.
+    pub label: Option<String>,
+}
+
+impl LibraryRoot {
+    pub fn new(folder: &str) -> LibraryRoot {
+        LibraryRoot {
+            folder: PathBuf::from(folder),
+            label: None,
+        }
+    }
+
+    /// Reads a root as it is given on the command line: a folder, optionally
+    /// preceded by a label and `=`, as in `Archive=/mnt/usb2/Music`. Only a
+    /// label without slashes is split off, so a folder that happens to have
+    /// a `=` in its path still reads as just a folder.
+    pub fn parse(argument: &str) -> LibraryRoot {
+        match argument.split_once('=') {
+            Some((label, folder))
+                if !label.is_empty() && !label.contains(['/', '\\']) && !folder.is_empty() =>
+            {
+                LibraryRoot {
+                    folder: PathBuf::from(folder),
+                    label: Some(label.to_string()),
+                }
+            }
+            _ => LibraryRoot::new(argument),
+        }
+    }
+
+    /// Whether the root can be read right now. A root on a USB drive that
+    /// isn't plugged in is not, and must not keep the other roots from being
+    /// scanned.
+    pub fn is_available(&self) -> bool {
+        self.folder.is_dir()
+    }
+
+    /// The title of the playlist for a folder of this root, see
+    /// `playlist_title`. With a label, the label takes the place of the name
+    /// of the root folder.
+    pub fn playlist_title(&self, dir: &Path) -> String {
+        let Some(label) = &self.label else {
+            return playlist_title(dir, &self.folder);
+        };
+        match dir.strip_prefix(&self.folder) {
+            Ok(relative) if relative.as_os_str().is_empty() => label.clone(),
+            Ok(relative) => Path::new(label).join(relative).to_string_lossy().to_string(),
+            Err(_) => playlist_title(dir, &self.folder),
+        }
+    }
+
+    /// Name to show for the root in the log.
+    pub fn name(&self) -> String {
+        match &self.label {
+            Some(label) => format!("{} ({})", label, self.folder.to_string_lossy()),
+            None => self.folder.to_string_lossy().to_string(),
+        }
+    }
+}
+
 /// Walks `dir` and all of its subfolders, handing every folder that directly
-/// contains audio files to `on_playlist` as a playlist.
+/// contains audio files to `on_playlist` as a playlist. Leaves out whatever
+/// `ignore` rules out, as well as what the ignore files and markers found
+/// along the way do (see `IgnoreRules`).
 fn scan_folder(
     dir: &Path,
-    root: &Path,
-    allowed_extensions: &[&str],
+    root: &LibraryRoot,
+    config: &ScanConfig,
+    ignore: &IgnoreRules,
     on_playlist: &mut dyn FnMut(Playlist),
     progress: &mut ScanProgress,
 ) {
+    if ignore::has_marker(dir) {
+        println!("Skipping {:?}, it is marked with {}", dir, ignore::MARKER_FILE);
+        return;
+    }
+
     progress.folders += 1;
     progress.heartbeat(dir);
+    let ignore = ignore.for_folder(dir);
 
     let entries = match fs::read_dir(dir) {
         Ok(entries) => entries,
@@ -154,6 +432,8 @@ fn scan_folder(
     };
 
     let mut tracks: Vec<Track> = Vec::new();
+    let mut cue_sheets: Vec<PathBuf> = Vec::new();
+    let mut images: Vec<PathBuf> = Vec::new();
     let mut subfolders: Vec<PathBuf> = Vec::new();
 
     for entry in entries.flatten() {
@@ -163,38 +443,82 @@ fn scan_folder(
             continue;
         }
 
-        if path.is_dir() {
+        let is_folder = path.is_dir();
+        if ignore.is_ignored(&path, is_folder) {
+            println!("Ignoring {:?}", path);
+            continue;
+        }
+
+        if is_folder {
             subfolders.push(path);
             continue;
         }
 
-        let is_audio_file = path
+        let extension = path
             .extension()
             .and_then(|extension| extension.to_str())
-            .map(|extension| allowed_extensions.contains(&extension.to_lowercase().as_str()))
+            .map(|extension| extension.to_lowercase());
+        let is_audio_file = extension
+            .as_deref()
+            .map(|extension| config.is_audio_extension(extension))
             .unwrap_or(false);
 
         if is_audio_file {
             tracks.push(read_track(path));
             progress.tracks += 1;
             progress.heartbeat(dir);
+        } else if extension.as_deref() == Some("cue") {
+            cue_sheets.push(path);
+        } else if extension
+            .as_deref()
+            .is_some_and(|extension| cover::IMAGE_EXTENSIONS.contains(&extension))
+        {
+            images.push(path);
+        }
+    }
+
+    // A single-file album with a CUE sheet becomes the tracks the sheet
+    // describes instead of one track the length of the album, so next and
+    // previous move through it like through any other album.
+    if !cue_sheets.is_empty() {
+        cue_sheets.sort();
+        let files: HashMap<PathBuf, Track> = tracks
+            .drain(..)
+            .map(|track| (track.filename.clone(), track))
+            .collect();
+        let mut replaced: Vec<PathBuf> = Vec::new();
+        for cue_sheet in &cue_sheets {
+            let (cue_tracks, cue_files) = read_cue_tracks(cue_sheet, &files);
+            // Two sheets for the same rip (one per encoding, say) would
+            // otherwise list every track twice.
+            if cue_files.iter().any(|file| replaced.contains(file)) {
+                continue;
+            }
+            tracks.extend(cue_tracks);
+            replaced.extend(cue_files);
         }
+        tracks.extend(files.into_values().filter(|track| !replaced.contains(&track.filename)));
     }
 
     if !tracks.is_empty() {
-        tracks.sort_by_key(|a| a.filename.clone());
+        // Tracks cut from the same file are ordered by where they start.
+        tracks.sort_by_key(|a| (a.filename.clone(), a.start));
 
-        // The first track's artwork represents the playlist. Only the path is
-        // kept; the image is read when it is served.
+        // The first track's artwork represents the playlist, and failing that
+        // a cover image in the folder. Only the path is kept; the image is
+        // read when it is served.
         let cover_source = tracks
             .first()
             .filter(|track| track.has_cover_art)
-            .map(|track| track.filename.clone());
+            .map(|track| CoverSource::Embedded(track.filename.clone()))
+            .or_else(|| cover::folder_image(&images).map(CoverSource::File));
 
         let album = Playlist {
-            title: playlist_title(dir, root),
+            id: 0,
+            title: root.playlist_title(dir),
             tracks,
             cover_source,
+            smart: false,
         };
 
         println!("Playlist found: {} ({} tracks)", album.title, album.tracks.len());
@@ -204,20 +528,148 @@ fn scan_folder(
 
     subfolders.sort();
     for subfolder in subfolders {
-        scan_folder(&subfolder, root, allowed_extensions, on_playlist, progress);
+        scan_folder(&subfolder, root, config, &ignore, on_playlist, progress);
+    }
+}
+
+/// Regroups the folder playlists of a root into albums by their tags, see
+/// `Grouping::Tags`.
+///
+/// Tracks belong together if they share the album tag and the album artist,
+/// or the artist for files without an album artist, compared
+/// case-insensitively. Within an album they are ordered by disc and track
+/// number, so the discs of a multi-disc album play in turn.
+fn group_by_tags(folder_playlists: Vec<Playlist>, root: &LibraryRoot) -> Vec<Playlist> {
+    struct Album {
+        artist: Option<String>,
+        title: String,
+        tracks: Vec<Track>,
+        folder_cover: Option<CoverSource>,
     }
+
+    let mut albums: Vec<Album> = Vec::new();
+    let mut album_positions: HashMap<(String, String), usize> = HashMap::new();
+    let mut playlists: Vec<Playlist> = Vec::new();
+
+    for mut folder_playlist in folder_playlists {
+        let (tagged, untagged): (Vec<Track>, Vec<Track>) = folder_playlist
+            .tracks
+            .drain(..)
+            .partition(|track| track.album.is_some());
+
+        for track in tagged {
+            let title = track.album.clone().unwrap_or_default();
+            let artist = track.album_artist.clone().or_else(|| track.artist.clone());
+            let key = (
+                artist.clone().unwrap_or_default().to_lowercase(),
+                title.trim().to_lowercase(),
+            );
+            let position = *album_positions.entry(key).or_insert_with(|| {
+                albums.push(Album {
+                    artist,
+                    title,
+                    tracks: Vec::new(),
+                    folder_cover: None,
+                });
+                albums.len() - 1
+            });
+            let album = &mut albums[position];
+            if album.folder_cover.is_none() {
+                album.folder_cover = folder_playlist.cover_source.clone();
+            }
+            album.tracks.push(track);
+        }
+
+        if !untagged.is_empty() {
+            folder_playlist.tracks = untagged;
+            playlists.push(folder_playlist);
+        }
+    }
+
+    for mut album in albums {
+        album.tracks.sort_by_key(album_order);
+        let cover_source = album
+            .tracks
+            .iter()
+            .find(|track| track.has_cover_art)
+            .map(|track| CoverSource::Embedded(track.filename.clone()))
+            .or(album.folder_cover);
+        let title = match album.artist {
+            Some(artist) => format!("{} - {}", artist, album.title),
+            None => album.title,
+        };
+        let title = match &root.label {
+            Some(label) => Path::new(label).join(title).to_string_lossy().to_string(),
+            None => title,
+        };
+        playlists.push(Playlist {
+            id: 0,
+            title,
+            tracks: album.tracks,
+            cover_source,
+            smart: false,
+        });
+    }
+
+    playlists
+}
+
+/// Where a track goes within an album grouped by tags: by disc and track
+/// number, so the discs of a multi-disc album play in turn.
+fn album_order(track: &Track) -> (u32, u32, PathBuf, Option<Duration>) {
+    (
+        track.disc_number.unwrap_or(0),
+        track.track_number.unwrap_or(0),
+        track.filename.clone(),
+        track.start,
+    )
 }
 
-/// Walks the library folder, handing every playlist to `on_playlist` the
-/// moment it is found. Lets callers fill a library progressively instead of
-/// waiting for the whole (potentially very slow) scan to finish.
-pub fn scan_playlists(library_folder: &str, on_playlist: &mut dyn FnMut(Playlist)) {
-    let allowed_extensions = vec!["mp3", "flac", "wav", "ogg", "oga", "opus"];
-    let root = PathBuf::from(library_folder);
+/// Walks a library root, handing every playlist to `on_playlist` the moment
+/// it is found. Lets callers fill a library progressively instead of waiting
+/// for the whole (potentially very slow) scan to finish.
+///
+/// Returns false without scanning anything if the root isn't there, so the
+/// caller can try again once it is.
+pub fn scan_playlists(
+    root: &LibraryRoot,
+    config: &ScanConfig,
+    on_playlist: &mut dyn FnMut(Playlist),
+) -> bool {
+    if !root.is_available() {
+        println!("Library root {} is not available, skipping it.", root.name());
+        return false;
+    }
 
-    println!("Scanning library at {}...", library_folder);
+    println!(
+        "Scanning library root {} for {}...",
+        root.name(),
+        config.audio_extensions.join(", "),
+    );
     let mut progress = ScanProgress::new();
-    scan_folder(&root, &root, &allowed_extensions, on_playlist, &mut progress);
+    match config.grouping {
+        Grouping::Folders => {
+            scan_folder(&root.folder, root, config, &IgnoreRules::default(), on_playlist, &mut progress);
+        }
+        Grouping::Tags => {
+            // An album can be spread over any number of folders, so nothing
+            // can be handed on before the whole root has been read.
+            let mut folder_playlists: Vec<Playlist> = Vec::new();
+            scan_folder(
+                &root.folder,
+                root,
+                config,
+                &IgnoreRules::default(),
+                &mut |playlist| folder_playlists.push(playlist),
+                &mut progress,
+            );
+            let playlists = group_by_tags(folder_playlists, root);
+            progress.playlists = playlists.len();
+            for playlist in playlists {
+                on_playlist(playlist);
+            }
+        }
+    }
 
     println!(
         "Scan finished in {}: {} playlists, {} tracks in {} folders.",
@@ -226,6 +678,51 @@ pub fn scan_playlists(library_folder: &str, on_playlist: &mut dyn FnMut(Playlist
         progress.tracks,
         progress.folders,
     );
+    true
+}
+
+/// Scans a single folder of a library root, subfolders included, into the
+/// playlists the scan of the whole root would have found there. For a folder
+/// that has just been added, such as an upload, where scanning the whole
+/// library again would take minutes.
+///
+/// The ignore files and markers of the folders above it apply as they would
+/// in a full scan. When grouping by tags, only the tracks in the folder are
+/// grouped; `Library::replace_playlists` adds them to what the library has of
+/// their albums from other folders, such as the discs uploaded before.
+pub fn scan_folder_playlists(root: &LibraryRoot, dir: &Path, config: &ScanConfig) -> Vec<Playlist> {
+    let mut ignore = IgnoreRules::default();
+    let mut ancestors: Vec<&Path> = dir
+        .ancestors()
+        .skip(1)
+        .take_while(|ancestor| ancestor.starts_with(&root.folder))
+        .collect();
+    ancestors.reverse();
+    for ancestor in ancestors {
+        if ignore::has_marker(ancestor) {
+            println!("Skipping {:?}, {:?} is marked with {}", dir, ancestor, ignore::MARKER_FILE);
+            return Vec::new();
+        }
+        ignore = ignore.for_folder(ancestor);
+    }
+    if dir != root.folder && ignore.is_ignored(dir, true) {
+        println!("Ignoring {:?}", dir);
+        return Vec::new();
+    }
+
+    let mut playlists: Vec<Playlist> = Vec::new();
+    scan_folder(
+        dir,
+        root,
+        config,
+        &ignore,
+        &mut |playlist| playlists.push(playlist),
+        &mut ScanProgress::new(),
+    );
+    match config.grouping {
+        Grouping::Folders => playlists,
+        Grouping::Tags => group_by_tags(playlists, root),
+    }
 }
 
 /// Reads the streams from `streams.txt` in `streams_folder`, with the logos
@@ -265,12 +762,20 @@ pub fn read_streams(streams_folder: &str) -> Vec<Stream> {
             let name = lines[0].trim();
             let url = lines[1].trim();
 
-            // Optional logo filename
-            let logo_svg = if lines.len() >= 3 {
-                let filename = lines[2].trim().to_string();
+            // After those, any number of fallback URLs to try when the
+            // first one fails, and optionally the logo filename. URLs are
+            // told from the logo by their scheme, so blocks written before
+            // there were fallbacks read the same as ever.
+            let (fallback_urls, others): (Vec<&str>, Vec<&str>) = lines[2..]
+                .iter()
+                .map(|line| line.trim())
+                .filter(|line| !line.is_empty())
+                .partition(|line| line.contains("://"));
+            let logo_file = others.first().map(|line| line.to_string());
+            let logo_svg = if let Some(filename) = &logo_file {
                 let filepath = PathBuf::from(streams_folder)
                     .join("logos")
-                    .join(&filename);
+                    .join(filename);
                 println!("Logo file path: {:?}", filepath);
                 let svg = fs::read_to_string(filepath);
                 match svg {
@@ -288,6 +793,9 @@ pub fn read_streams(streams_folder: &str) -> Vec<Stream> {
                 name: name.to_string(),
                 url: url.to_string(),
                 logo_svg: logo_svg.clone(),
+                logo_file,
+                fallback_urls: fallback_urls.iter().map(|url| url.to_string()).collect(),
+                up: None,
             });
 
             println!(
@@ -309,12 +817,19 @@ fn playlist_sort_key(title: &str) -> String {
 }
 
 pub struct Playlist {
+    /// Tells playlists apart where titles don't: two unlabelled roots with a
+    /// folder of the same name give two playlists of one title. Handed out by
+    /// `Library::insert_playlist`, 0 until then.
+    pub id: u64,
     pub title: String,
     pub tracks: Vec<Track>,
-    /// The audio file whose embedded cover represents this playlist, if any.
-    /// Holding the path instead of the image keeps a large library's covers
-    /// out of memory.
-    pub cover_source: Option<PathBuf>,
+    /// Where the cover that represents this playlist comes from, if it has
+    /// one: the artwork embedded in its first track, or an image file such as
+    /// `cover.jpg` in its folder.
+    pub cover_source: Option<CoverSource>,
+    /// Whether this is a smart playlist, made of tracks of the others that
+    /// match its rules, rather than of files of its own.
+    pub smart: bool,
 }
 
 impl Playlist {
@@ -323,70 +838,205 @@ impl Playlist {
     /// title or artist of one of its tracks - but they may turn up in
     /// different places, so "beatles revolver" finds the album even though
     /// neither word alone identifies it. An empty filter matches everything.
+    /// The web API asks `Library::filter_playlists` instead, for the ranking.
+    #[cfg(test)]
     pub fn matches_filter(&self, filter: &str) -> bool {
-        let words: Vec<String> = filter
-            .split_whitespace()
-            .map(|word| word.to_lowercase())
-            .collect();
-        if words.is_empty() {
-            return true;
-        }
+        self.filter_score(filter, false).is_some()
+    }
+
+    /// How well this playlist matches `filter`, if it does at all, as
+    /// `matches_filter` has it. Accents and case don't count, and other
+    /// scripts match as they are written in Latin letters. With `typos`, a
+    /// word also matches a word it is only a letter or two off from, which
+    /// ranks below a match as typed.
+    pub fn filter_score(&self, filter: &str, typos: bool) -> Option<u32> {
+        let words: Vec<String> = filter.split_whitespace().map(text::fold).collect();
 
-        let mut fields: Vec<String> = vec![self.title.to_lowercase()];
+        let mut fields: Vec<String> = vec![text::fold(&self.title)];
         for track in &self.tracks {
-            fields.push(track.display_title().to_lowercase());
+            fields.push(text::fold(&track.display_title()));
             if let Some(artist) = &track.artist {
-                fields.push(artist.to_lowercase());
+                fields.push(text::fold(artist));
             }
         }
 
         words
             .iter()
-            .all(|word| fields.iter().any(|field| field.contains(word)))
+            .map(|word| fields.iter().filter_map(|field| word_score(word, field, typos)).max())
+            .sum()
+    }
+}
+
+/// How well a word of a filter matches a field: as a whole word, at the
+/// start of one, anywhere, or with typos, from best to worst. Both are folded.
+fn word_score(word: &str, field: &str, typos: bool) -> Option<u32> {
+    let field_words = || field.split(|character: char| !character.is_alphanumeric()).filter(|part| !part.is_empty());
+    if field_words().any(|part| part == word) {
+        return Some(4);
+    }
+    if field_words().any(|part| part.starts_with(word)) {
+        return Some(3);
     }
+    if field.contains(word) {
+        return Some(2);
+    }
+    (typos && field_words().any(|part| text::matches_with_typos(word, part))).then_some(1)
 }
 
+#[derive(Clone)]
 pub struct Stream {
     pub name: String,
     pub url: String,
     pub logo_svg: Option<String>,
+    /// The logo as `streams.txt` names it, kept even when the file is
+    /// missing so writing the streams back doesn't lose it.
+    pub logo_file: Option<String>,
+    /// URLs to try in turn when `url` can't be played, such as the same
+    /// station at another bitrate or from another server.
+    pub fallback_urls: Vec<String>,
+    /// Whether the station answered the last health check, if streams are
+    /// being checked at all.
+    pub up: Option<bool>,
 }
 
 pub struct Library {
-    pub folder: String,
+    pub roots: Vec<LibraryRoot>,
+    pub scan_config: ScanConfig,
     pub playlists: Vec<Playlist>,
     pub streams: Vec<Stream>,
+    /// Where `streams.txt` is, if streams are configured at all. Changes to
+    /// the streams are written back there.
+    pub streams_folder: Option<PathBuf>,
+    /// The ids of the playlists holding tracks from each folder. A folder is
+    /// one playlist when grouping by folders, but grouping by tags can put
+    /// its tracks into any number of them, and this is how `find_track` gets
+    /// to a track without going through the whole library.
+    folder_playlists: HashMap<PathBuf, Vec<u64>>,
+    /// The id the next playlist inserted gets.
+    next_playlist_id: u64,
+    /// Integrated loudness in LUFS of the files the loudness analysis has
+    /// measured so far, for the tracks without ReplayGain tags.
+    pub loudness: HashMap<PathBuf, f64>,
+    pub ratings: Ratings,
+    /// The rules of the smart playlists, which `refresh_smart_playlists`
+    /// turns into playlists.
+    pub smart_playlists: Vec<SmartPlaylist>,
+    /// The words of the playlists and tracks, for `search`.
+    search_index: SearchIndex,
 }
 
 impl Library {
     /// An unscanned library, used while the real scan runs in the background.
-    pub fn empty(library_folder: String) -> Library {
+    pub fn empty(roots: Vec<LibraryRoot>, scan_config: ScanConfig) -> Library {
         Library {
-            folder: library_folder,
+            roots,
+            scan_config,
             playlists: Vec::new(),
             streams: Vec::new(),
+            streams_folder: None,
+            folder_playlists: HashMap::new(),
+            next_playlist_id: 1,
+            loudness: HashMap::new(),
+            ratings: Ratings::default(),
+            smart_playlists: Vec::new(),
+            search_index: SearchIndex::default(),
         }
     }
 
     /// Inserts a playlist at its sorted position, so the library stays ordered
-    /// even while a background scan is still adding to it.
-    pub fn insert_playlist(&mut self, playlist: Playlist) {
-        let key = playlist_sort_key(&playlist.title);
-        let position = self
-            .playlists
-            .partition_point(|existing| playlist_sort_key(&existing.title) <= key);
+    /// even while a background scan is still adding to it. Smart playlists
+    /// only borrow their tracks, so `find_track` is not led to them.
+    pub fn insert_playlist(&mut self, mut playlist: Playlist) {
+        let position = self.sorted_position(&playlist.title);
+        playlist.id = self.next_playlist_id;
+        self.next_playlist_id += 1;
+
+        if !playlist.smart {
+            for track in &playlist.tracks {
+                let Some(folder) = track.filename.parent() else {
+                    continue;
+                };
+                let ids = self.folder_playlists.entry(folder.to_path_buf()).or_default();
+                if !ids.contains(&playlist.id) {
+                    ids.push(playlist.id);
+                }
+            }
+        }
+
+        self.search_index.index_playlist(&playlist);
         self.playlists.insert(position, playlist);
     }
 
-    /// Finds the playlist and track a file belongs to. Tracks are grouped by
-    /// the folder they live in, so the file's own path says which playlist to
-    /// look in and no scan of the whole library is needed.
-    pub fn find_track(&self, file_path: &Path) -> Option<(&Playlist, &Track)> {
-        let folder = file_path.parent()?;
-        let title = playlist_title(folder, Path::new(&self.folder));
-        let playlist = self.playlists.iter().find(|playlist| playlist.title == title)?;
-        let track = playlist.tracks.iter().find(|track| track.filename == file_path)?;
-        Some((playlist, track))
+    /// Where a playlist of this title goes to keep the library sorted.
+    fn sorted_position(&self, title: &str) -> usize {
+        let key = playlist_sort_key(title);
+        self.playlists
+            .partition_point(|existing| playlist_sort_key(&existing.title) <= key)
+    }
+
+    /// Puts playlists scanned again from `scanned` into the library, in place
+    /// of any playlist of the same title from the same root. A folder that has
+    /// been added to is then still one playlist rather than two. The tracks
+    /// such a playlist has from outside `scanned` are kept, which only happens
+    /// when grouping by tags: the second disc of an album joins the first.
+    pub fn replace_playlists(&mut self, playlists: Vec<Playlist>, scanned: &Path) {
+        for mut playlist in playlists {
+            let root = |playlist: &Playlist| {
+                let track = playlist.tracks.first()?;
+                self.root_of(&track.filename).map(|root| root.folder.clone())
+            };
+            let playlist_root = root(&playlist);
+            if let Some(position) = self
+                .playlists
+                .iter()
+                .position(|existing| !existing.smart && existing.title == playlist.title && root(existing) == playlist_root)
+            {
+                let existing = self.remove_playlist_at(position);
+                let kept: Vec<Track> = existing
+                    .tracks
+                    .into_iter()
+                    .filter(|track| !track.filename.starts_with(scanned))
+                    .collect();
+                if !kept.is_empty() {
+                    playlist.tracks.extend(kept);
+                    playlist.tracks.sort_by_key(album_order);
+                    playlist.cover_source = playlist.cover_source.or(existing.cover_source);
+                }
+            }
+            self.insert_playlist(playlist);
+        }
+    }
+
+    /// The audio file behind a location mpv reports (see `Track::location`).
+    pub fn location_file(location: &str) -> PathBuf {
+        cue::edl_file(location).unwrap_or_else(|| PathBuf::from(location))
+    }
+
+    /// Whether one audio file plays right into the next, without a
+    /// crossfade: tracks of a CUE sheet are cut from the same file, and
+    /// tracks of a gapless album are in a folder marked as one.
+    pub fn plays_gapless(current: &Path, next: &Path) -> bool {
+        current == next
+            || (current.parent() == next.parent()
+                && current.parent().is_some_and(|folder| folder.join(GAPLESS_MARKER_FILE).exists()))
+    }
+
+    /// Finds the playlist and track a file belongs to, by the location mpv
+    /// reports for it (see `Track::location`). The folder of the file says
+    /// which playlists to look in, so no scan of the whole library is needed.
+    pub fn find_track(&self, location: &str) -> Option<(&Playlist, &Track)> {
+        let file_path = Library::location_file(location);
+        let ids = self.folder_playlists.get(file_path.parent()?)?;
+        self.playlists
+            .iter()
+            .filter(|playlist| ids.contains(&playlist.id))
+            .find_map(|playlist| {
+                playlist
+                    .tracks
+                    .iter()
+                    .find(|track| track.filename == file_path && track.location() == location)
+                    .map(|track| (playlist, track))
+            })
     }
 
     /// Logs the playlists with the index each one is reachable at, both on the
@@ -397,18 +1047,23 @@ impl Library {
         }
     }
 
-    /// Scans the whole library at once. Blocks until the scan is done, so
-    /// callers that need to stay responsive should use `scan_playlists`
-    /// together with `insert_playlist` instead.
+    /// Scans the whole library at once. Blocks until the scan is done, which
+    /// only the tests can afford: the player scans with `scan_playlists`
+    /// together with `insert_playlist`, and after an upload only the uploaded
+    /// folder with `scan_folder_playlists`.
     ///
     /// Leaves `streams` empty: they come from a folder of their own that the
-    /// library knows nothing about, so a caller replacing its library with a
-    /// rescan has to carry the streams over itself.
-    pub fn new(library_folder: String) -> Library {
-        let mut library = Library::empty(library_folder.clone());
+    /// library knows nothing about. Roots that aren't available are skipped.
+    #[cfg(test)]
+    pub fn new(roots: Vec<LibraryRoot>, scan_config: ScanConfig) -> Library {
+        let mut library = Library::empty(roots, scan_config);
 
         let mut playlists: Vec<Playlist> = Vec::new();
-        scan_playlists(&library_folder, &mut |playlist| playlists.push(playlist));
+        for root in &library.roots {
+            scan_playlists(root, &library.scan_config, &mut |playlist| {
+                playlists.push(playlist)
+            });
+        }
         for playlist in playlists {
             library.insert_playlist(playlist);
         }
@@ -421,21 +1076,27 @@ impl Library {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::path::MAIN_SEPARATOR;
+    use crate::utils::TempDir;
+    use std::ops::Deref;
+    use std::path::MAIN_SEPARATOR_STR;
 
     /// A library folder in the system temp dir that deletes itself again when
     /// the test ends.
     struct TempLibrary {
-        path: PathBuf,
+        dir: TempDir,
+    }
+
+    impl Deref for TempLibrary {
+        type Target = TempDir;
+
+        fn deref(&self) -> &TempDir {
+            &self.dir
+        }
     }
 
     impl TempLibrary {
         fn new(name: &str) -> TempLibrary {
-            let path = std::env::temp_dir()
-                .join(format!("miconau-test-{}-{}", std::process::id(), name));
-            let _ = fs::remove_dir_all(&path);
-            fs::create_dir_all(&path).unwrap();
-            TempLibrary { path }
+            TempLibrary { dir: TempDir::new(name) }
         }
 
         /// Creates an empty folder, relative to the library root.
@@ -448,23 +1109,26 @@ mod tests {
         /// library root. The content is irrelevant for scanning, so tracks are
         /// simply empty files without tags.
         fn file(&self, relative: &str, content: &str) -> &TempLibrary {
-            let path = self.path.join(relative);
-            fs::create_dir_all(path.parent().unwrap()).unwrap();
-            fs::write(path, content).unwrap();
+            self.dir.file(relative, content);
             self
         }
 
         /// Creates a file with raw bytes, for content that has to be a valid
         /// audio file.
         fn bytes(&self, relative: &str, content: &[u8]) -> PathBuf {
-            let path = self.path.join(relative);
-            fs::create_dir_all(path.parent().unwrap()).unwrap();
-            fs::write(&path, content).unwrap();
-            path
+            self.dir.file(relative, content)
         }
 
         fn scan(&self) -> Library {
-            Library::new(self.path.to_str().unwrap().to_string())
+            self.scan_with(ScanConfig::default())
+        }
+
+        fn scan_with(&self, config: ScanConfig) -> Library {
+            Library::new(vec![self.root()], config)
+        }
+
+        fn root(&self) -> LibraryRoot {
+            LibraryRoot::new(self.path.to_str().unwrap())
         }
 
         fn playlist_titles(&self) -> Vec<String> {
@@ -480,16 +1144,10 @@ mod tests {
         }
     }
 
-    impl Drop for TempLibrary {
-        fn drop(&mut self) {
-            let _ = fs::remove_dir_all(&self.path);
-        }
-    }
-
     /// Builds a playlist title from path segments, so expectations don't depend
     /// on the platform's path separator.
     fn title(segments: &[&str]) -> String {
-        segments.join(&MAIN_SEPARATOR.to_string())
+        segments.join(MAIN_SEPARATOR_STR)
     }
 
     #[test]
@@ -505,6 +1163,95 @@ mod tests {
         assert_eq!(playlist_title(&root, &root), "music");
     }
 
+    #[test]
+    fn library_roots_are_read_with_an_optional_label() {
+        assert_eq!(LibraryRoot::parse("/mnt/usb1/Music"), LibraryRoot::new("/mnt/usb1/Music"));
+        assert_eq!(
+            LibraryRoot::parse("Archive=/mnt/usb2/Music"),
+            LibraryRoot {
+                folder: PathBuf::from("/mnt/usb2/Music"),
+                label: Some("Archive".to_string()),
+            }
+        );
+        // an `=` inside the path is not a label
+        assert_eq!(LibraryRoot::parse("/music/a=b"), LibraryRoot::new("/music/a=b"));
+        assert_eq!(LibraryRoot::parse("=/music"), LibraryRoot::new("=/music"));
+    }
+
+    #[test]
+    fn a_label_takes_the_place_of_the_root_folder_in_titles() {
+        let root = LibraryRoot::parse("Archive=/mnt/usb2/Music");
+
+        assert_eq!(
+            root.playlist_title(Path::new("/mnt/usb2/Music/Artist/Album")),
+            title(&["Archive", "Artist", "Album"])
+        );
+        assert_eq!(root.playlist_title(Path::new("/mnt/usb2/Music")), "Archive");
+    }
+
+    #[test]
+    fn scans_every_root_and_skips_missing_ones() {
+        let first = TempLibrary::new("roots-first");
+        let second = TempLibrary::new("roots-second");
+        first.file("Live/01.mp3", "");
+        second.file("Live/01.mp3", "").file("Other/01.mp3", "");
+
+        let labelled = LibraryRoot {
+            folder: second.path.clone(),
+            label: Some("Second".to_string()),
+        };
+        let unplugged = LibraryRoot::new(first.path.join("unplugged").to_str().unwrap());
+        let scanned = Library::new(
+            vec![first.root(), unplugged, labelled],
+            ScanConfig::default(),
+        );
+
+        assert_eq!(
+            scanned.playlists.iter().map(|playlist| playlist.title.clone()).collect::<Vec<String>>(),
+            vec![
+                "Live".to_string(),
+                title(&["Second", "Live"]),
+                title(&["Second", "Other"]),
+            ]
+        );
+
+        // and each file is found in the playlist of its own root
+        let (playlist, _) = scanned
+            .find_track(&second.path.join("Live/01.mp3").to_string_lossy())
+            .expect("track should be found");
+        assert_eq!(playlist.title, title(&["Second", "Live"]));
+        let (playlist, _) = scanned
+            .find_track(&first.path.join("Live/01.mp3").to_string_lossy())
+            .expect("track should be found");
+        assert_eq!(playlist.title, "Live");
+    }
+
+    #[test]
+    fn tells_apart_folders_of_the_same_name_in_unlabelled_roots() {
+        let first = TempLibrary::new("same-name-first");
+        let second = TempLibrary::new("same-name-second");
+        first.file("Live/01.mp3", "");
+        second.file("Live/01.mp3", "").file("Live/02.mp3", "");
+        let mut scanned = Library::new(vec![first.root(), second.root()], ScanConfig::default());
+        assert_eq!(scanned.playlists.len(), 2);
+
+        let in_second = second.path.join("Live/02.mp3").to_string_lossy().to_string();
+        let (playlist, _) = scanned.find_track(&in_second).expect("track should be found");
+        assert_eq!(playlist.tracks.len(), 2);
+
+        // a folder of the first root scanned again replaces only its own playlist
+        let rescanned = scan_folder_playlists(&first.root(), &first.path.join("Live"), &ScanConfig::default());
+        scanned.replace_playlists(rescanned, &first.path.join("Live"));
+        assert_eq!(scanned.playlists.len(), 2);
+        assert!(scanned.find_track(&in_second).is_some());
+
+        // and taking one out leaves the other one findable
+        let position = scanned.playlists.iter().position(|playlist| playlist.tracks.len() == 1).unwrap();
+        scanned.remove_playlist_at(position);
+        assert!(scanned.find_track(&in_second).is_some());
+        assert!(scanned.find_track(&first.path.join("Live/01.mp3").to_string_lossy()).is_none());
+    }
+
     #[test]
     fn finds_playlists_in_subdirectories() {
         let library = TempLibrary::new("subdirectories");
@@ -598,6 +1345,118 @@ mod tests {
         );
     }
 
+    #[test]
+    fn scans_the_configured_extensions_instead_of_the_default_ones() {
+        let library = TempLibrary::new("configured-extensions");
+        library
+            .file("Album/01.mp3", "")
+            .file("Album/02.M4A", "")
+            .file("Album/03.wv", "")
+            .file("Album/04.aiff", "");
+
+        let config = ScanConfig::new(Some(vec![
+            "m4a".to_string(),
+            ".WV".to_string(),
+            "mp3".to_string(),
+        ]));
+        let playlists = library.scan_with(config).playlists;
+        assert_eq!(
+            playlists[0]
+                .tracks
+                .iter()
+                .map(|track| track.filename.file_name().unwrap().to_string_lossy().to_string())
+                .collect::<Vec<String>>(),
+            vec!["01.mp3".to_string(), "02.M4A".to_string(), "03.wv".to_string()]
+        );
+    }
+
+    #[test]
+    fn leaves_out_what_ignore_files_rule_out() {
+        let library = TempLibrary::new("ignore-file");
+        library
+            .file(".miconauignore", "Podcasts/old\n_incoming\n")
+            .file("Podcasts/old/01.mp3", "")
+            .file("Podcasts/new/01.mp3", "")
+            .file("Artist/_incoming/01.mp3", "")
+            .file("Artist/Album/01.mp3", "")
+            // an ignore file further down only applies below its own folder
+            .file("Artist/Album/.miconauignore", "*.wav")
+            .file("Artist/Album/02.wav", "")
+            .file("Other/02.wav", "");
+
+        let scanned = library.scan();
+        assert_eq!(
+            scanned.playlists.iter().map(|playlist| playlist.title.clone()).collect::<Vec<String>>(),
+            vec![
+                title(&["Artist", "Album"]),
+                "Other".to_string(),
+                title(&["Podcasts", "new"]),
+            ]
+        );
+        assert_eq!(scanned.playlists[0].tracks.len(), 1);
+    }
+
+    #[test]
+    fn leaves_out_folders_with_a_marker_file() {
+        let library = TempLibrary::new("marker-file");
+        library
+            .file("Samples/.nomedia", "")
+            .file("Samples/01.mp3", "")
+            .file("Samples/Drums/01.mp3", "")
+            .file("Album/01.mp3", "");
+
+        assert_eq!(library.playlist_titles(), vec!["Album".to_string()]);
+    }
+
+    #[test]
+    fn scans_a_single_folder_as_the_full_scan_would() {
+        let library = TempLibrary::new("single-folder");
+        library
+            .file(".miconauignore", "*.tmp\n")
+            .file("Artist/Album/01.mp3", "")
+            .file("Artist/Album/02.mp3.tmp", "")
+            .file("Artist/Album/Bonus/01.mp3", "")
+            .file("Artist/Other/01.mp3", "")
+            .file("Samples/.nomedia", "")
+            .file("Samples/Upload/01.mp3", "");
+        let root = library.root();
+
+        let playlists =
+            scan_folder_playlists(&root, &library.path.join("Artist/Album"), &ScanConfig::default());
+        assert_eq!(
+            playlists.iter().map(|playlist| playlist.title.clone()).collect::<Vec<String>>(),
+            vec![title(&["Artist", "Album"]), title(&["Artist", "Album", "Bonus"])]
+        );
+        // the ignore file of the root applies down here too
+        assert_eq!(playlists[0].tracks.len(), 1);
+
+        let marked =
+            scan_folder_playlists(&root, &library.path.join("Samples/Upload"), &ScanConfig::default());
+        assert!(marked.is_empty());
+    }
+
+    #[test]
+    fn replaced_playlists_take_the_place_of_those_of_the_same_title() {
+        let library = TempLibrary::new("replace-playlists");
+        library.file("Album/01.mp3", "").file("Other/01.mp3", "");
+        let mut scanned = library.scan();
+
+        library.file("Album/02.mp3", "").file("New/01.mp3", "");
+        let root = library.root();
+        for folder in ["Album", "New"] {
+            let folder = library.path.join(folder);
+            scanned.replace_playlists(scan_folder_playlists(&root, &folder, &ScanConfig::default()), &folder);
+        }
+
+        assert_eq!(
+            scanned.playlists.iter().map(|playlist| playlist.title.clone()).collect::<Vec<String>>(),
+            vec!["Album".to_string(), "New".to_string(), "Other".to_string()]
+        );
+        assert_eq!(scanned.playlists[0].tracks.len(), 2);
+        let added = library.path.join("Album/02.mp3");
+        assert!(scanned.find_track(&added.to_string_lossy()).is_some());
+    }
+
     #[test]
     fn sorts_tracks_by_filename() {
         let library = TempLibrary::new("track-order");
@@ -617,6 +1476,95 @@ mod tests {
         );
     }
 
+    const CUE_SHEET: &str = "PERFORMER \"The Band\"
+FILE \"album.wav\" WAVE
+  TRACK 01 AUDIO
+    TITLE \"One\"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE \"Two\"
+    PERFORMER \"Guest\"
+    INDEX 01 03:00:00
+  TRACK 03 AUDIO
+    INDEX 01 07:30:00
+";
+
+    #[test]
+    fn splits_a_single_file_album_along_its_cue_sheet() {
+        let library = TempLibrary::new("cue-sheet");
+        library
+            .file("Album/album.cue", CUE_SHEET)
+            // the sheet names album.wav, the rip was compressed to flac later
+            .file("Album/album.flac", "")
+            .file("Album/bonus.mp3", "");
+
+        let playlists = library.scan().playlists;
+        assert_eq!(playlists.len(), 1);
+        let tracks = &playlists[0].tracks;
+        assert_eq!(
+            tracks.iter().map(|track| track.display_title()).collect::<Vec<String>>(),
+            vec!["One", "Two", "Track 03", "bonus"]
+        );
+
+        let album = library.path.join("Album/album.flac");
+        assert!(tracks[..3].iter().all(|track| track.filename == album));
+        assert_eq!(tracks[0].start, Some(Duration::ZERO));
+        assert_eq!(tracks[0].end, Some(Duration::from_secs(180)));
+        assert_eq!(tracks[1].end, Some(Duration::from_secs(450)));
+        // the last track plays to the end of the file
+        assert_eq!(tracks[2].start, Some(Duration::from_secs(450)));
+        assert_eq!(tracks[2].end, None);
+        // and a file the sheet doesn't mention stays a track of its own
+        assert_eq!(tracks[3].start, None);
+
+        assert_eq!(tracks[0].artist.as_deref(), Some("The Band"));
+        assert_eq!(tracks[1].artist.as_deref(), Some("Guest"));
+    }
+
+    #[test]
+    fn tracks_of_a_cue_sheet_are_told_apart_by_their_location() {
+        let library = TempLibrary::new("cue-location");
+        library
+            .file("Album/album.cue", CUE_SHEET)
+            .file("Album/album.wav", "");
+        let scanned = library.scan();
+
+        let tracks = &scanned.playlists[0].tracks;
+        assert_ne!(tracks[0].location(), tracks[1].location());
+        let (playlist, track) = scanned
+            .find_track(&tracks[1].location())
+            .expect("track should be found");
+        assert_eq!(playlist.title, "Album");
+        assert_eq!(track.display_title(), "Two");
+        // the file itself is not a track any more, only its parts are
+        assert!(scanned.find_track(&library.path.join("Album/album.wav").to_string_lossy()).is_none());
+    }
+
+    #[test]
+    fn the_gain_of_a_cue_sheet_file_is_the_gain_of_its_album() {
+        let library = TempLibrary::new("cue-gain");
+        let cue = library.bytes("Album/album.cue", CUE_SHEET.as_bytes());
+        let mut file = track(&library.path.join("Album/album.wav").to_string_lossy(), None, None);
+        file.track_gain = Some(-6.5);
+        let files = HashMap::from([(file.filename.clone(), file)]);
+
+        let (tracks, _) = read_cue_tracks(&cue, &files);
+        assert_eq!(tracks.len(), 3);
+        assert!(tracks.iter().all(|track| track.track_gain.is_none() && track.album_gain == Some(-6.5)));
+    }
+
+    #[test]
+    fn a_cue_sheet_without_its_audio_file_is_ignored() {
+        let library = TempLibrary::new("cue-missing-file");
+        library
+            .file("Album/album.cue", CUE_SHEET)
+            .file("Album/other.mp3", "");
+
+        let playlists = library.scan().playlists;
+        assert_eq!(playlists[0].tracks.len(), 1);
+        assert_eq!(playlists[0].tracks[0].display_title(), "other");
+    }
+
     #[test]
     fn finds_a_track_by_its_file_path() {
         let library = TempLibrary::new("find-track");
@@ -627,7 +1575,7 @@ mod tests {
         let scanned = library.scan();
 
         let (playlist, track) = scanned
-            .find_track(&library.path.join("Artist/Album/02.mp3"))
+            .find_track(&library.path.join("Artist/Album/02.mp3").to_string_lossy())
             .expect("track should be found");
         assert_eq!(playlist.title, title(&["Artist", "Album"]));
         assert_eq!(track.display_title(), "02");
@@ -640,7 +1588,7 @@ mod tests {
         let scanned = library.scan();
 
         let (playlist, _) = scanned
-            .find_track(&library.path.join("01.mp3"))
+            .find_track(&library.path.join("01.mp3").to_string_lossy())
             .expect("track should be found");
         assert_eq!(playlist.title, library.name());
     }
@@ -652,8 +1600,8 @@ mod tests {
         let scanned = library.scan();
 
         // a stream, and a file in a folder the library does know
-        assert!(scanned.find_track(Path::new("http://example.com/stream")).is_none());
-        assert!(scanned.find_track(&library.path.join("Album/99.mp3")).is_none());
+        assert!(scanned.find_track("http://example.com/stream").is_none());
+        assert!(scanned.find_track(&library.path.join("Album/99.mp3").to_string_lossy()).is_none());
     }
 
     #[test]
@@ -719,29 +1667,232 @@ mod tests {
         // the scan records where the cover is, not the cover itself
         assert_eq!(
             with_cover.cover_source,
-            Some(library.path.join("With Cover/01.mp3"))
+            Some(CoverSource::Embedded(library.path.join("With Cover/01.mp3")))
         );
         assert!(with_cover.tracks[0].has_cover_art);
         assert_eq!(without_cover.cover_source, None);
         assert!(!without_cover.tracks[0].has_cover_art);
 
         // and serving it reads the image back out of the file
-        let (data, mime) = read_cover_art(with_cover.cover_source.as_ref().unwrap()).unwrap();
+        let (data, mime) = read_cover(with_cover.cover_source.as_ref().unwrap()).unwrap();
         assert_eq!(data, picture_data);
         assert_eq!(mime, "image/jpeg");
     }
 
+    #[test]
+    fn falls_back_to_a_cover_image_in_the_folder() {
+        let library = TempLibrary::new("folder-cover");
+        write_mp3_with_cover(&library, "Embedded/01.mp3");
+        library
+            .file("Embedded/cover.jpg", "folder image")
+            .file("Image Only/01.mp3", "")
+            .file("Image Only/Folder.PNG", "folder image")
+            .file("Image Only/back.jpg", "back of the case");
+
+        let scanned = library.scan();
+        let cover_of = |title: &str| {
+            scanned
+                .playlists
+                .iter()
+                .find(|playlist| playlist.title == title)
+                .unwrap()
+                .cover_source
+                .clone()
+        };
+
+        // embedded artwork still comes first
+        assert_eq!(
+            cover_of("Embedded"),
+            Some(CoverSource::Embedded(library.path.join("Embedded/01.mp3")))
+        );
+        let folder_cover = cover_of("Image Only").unwrap();
+        assert_eq!(
+            folder_cover,
+            CoverSource::File(library.path.join("Image Only/Folder.PNG"))
+        );
+
+        let (data, mime) = read_cover(&folder_cover).unwrap();
+        assert_eq!(data, b"folder image");
+        assert_eq!(mime, "image/png");
+    }
+
+    #[test]
+    fn images_alone_do_not_make_a_playlist() {
+        let library = TempLibrary::new("images-only");
+        library.file("Scans/cover.jpg", "").file("Album/01.mp3", "");
+
+        assert_eq!(library.playlist_titles(), vec!["Album".to_string()]);
+    }
+
     fn empty_playlist(title: &str) -> Playlist {
         Playlist {
+            id: 0,
             title: title.to_string(),
             tracks: Vec::new(),
             cover_source: None,
+            smart: false,
         }
     }
 
+    /// Writes a silent mp3 with the given tags, for tests that need the tags
+    /// to be read back by the scan.
+    fn write_tagged_mp3(
+        library: &TempLibrary,
+        relative: &str,
+        album: &str,
+        album_artist: Option<&str>,
+        disc: Option<u32>,
+        track: u32,
+    ) {
+        use lofty::config::WriteOptions;
+        use lofty::tag::{Tag, TagType};
+
+        let mut frame = vec![0xFF, 0xFB, 0x90, 0x00];
+        frame.resize(417, 0);
+        let path = library.bytes(relative, &frame.repeat(8));
+
+        let mut tag = Tag::new(TagType::Id3v2);
+        tag.set_album(album.to_string());
+        tag.set_artist("Track Artist".to_string());
+        if let Some(album_artist) = album_artist {
+            tag.insert_text(ItemKey::AlbumArtist, album_artist.to_string());
+        }
+        if let Some(disc) = disc {
+            tag.set_disk(disc);
+        }
+        tag.set_track(track);
+        tag.save_to_path(&path, WriteOptions::default()).unwrap();
+    }
+
+    #[test]
+    fn grouping_by_tags_joins_discs_and_splits_mixed_folders() {
+        let library = TempLibrary::new("group-by-tags");
+        write_tagged_mp3(&library, "Big Album/CD2/01.mp3", "Big Album", Some("The Band"), Some(2), 1);
+        write_tagged_mp3(&library, "Big Album/CD1/02.mp3", "Big Album", Some("The Band"), Some(1), 2);
+        write_tagged_mp3(&library, "Big Album/CD1/01.mp3", "Big Album", Some("The Band"), Some(1), 1);
+        write_tagged_mp3(&library, "Singles/a.mp3", "First Single", None, None, 1);
+        write_tagged_mp3(&library, "Singles/b.mp3", "Second Single", None, None, 1);
+        library.file("Singles/untagged.mp3", "");
+
+        let scanned = library.scan_with(ScanConfig {
+            grouping: Grouping::Tags,
+            ..ScanConfig::default()
+        });
+
+        assert_eq!(
+            scanned.playlists.iter().map(|playlist| playlist.title.clone()).collect::<Vec<String>>(),
+            vec![
+                "Singles".to_string(),
+                "The Band - Big Album".to_string(),
+                // without an album artist, the artist stands in for it
+                "Track Artist - First Single".to_string(),
+                "Track Artist - Second Single".to_string(),
+            ]
+        );
+
+        let big_album = &scanned.playlists[1];
+        assert_eq!(
+            big_album
+                .tracks
+                .iter()
+                .map(|track| track.filename.strip_prefix(&library.path).unwrap().to_path_buf())
+                .collect::<Vec<PathBuf>>(),
+            vec![
+                PathBuf::from("Big Album/CD1/01.mp3"),
+                PathBuf::from("Big Album/CD1/02.mp3"),
+                PathBuf::from("Big Album/CD2/01.mp3"),
+            ]
+        );
+        // the untagged file keeps a playlist for its folder
+        assert_eq!(scanned.playlists[0].tracks.len(), 1);
+
+        // and tracks are found no matter how they were grouped
+        let (playlist, _) = scanned
+            .find_track(&library.path.join("Big Album/CD2/01.mp3").to_string_lossy())
+            .expect("track should be found");
+        assert_eq!(playlist.title, "The Band - Big Album");
+        let (playlist, _) = scanned
+            .find_track(&library.path.join("Singles/b.mp3").to_string_lossy())
+            .expect("track should be found");
+        assert_eq!(playlist.title, "Track Artist - Second Single");
+        let (playlist, _) = scanned
+            .find_track(&library.path.join("Singles/untagged.mp3").to_string_lossy())
+            .expect("track should be found");
+        assert_eq!(playlist.title, "Singles");
+    }
+
+    #[test]
+    fn a_disc_scanned_on_its_own_joins_the_rest_of_its_album() {
+        let library = TempLibrary::new("group-by-tags-upload");
+        write_tagged_mp3(&library, "Big Album/CD1/01.mp3", "Big Album", Some("The Band"), Some(1), 1);
+        let config = ScanConfig {
+            grouping: Grouping::Tags,
+            ..ScanConfig::default()
+        };
+        let mut scanned = library.scan_with(config.clone());
+
+        write_tagged_mp3(&library, "Upload/01.mp3", "Big Album", Some("The Band"), Some(2), 1);
+        write_tagged_mp3(&library, "Upload/02.mp3", "Big Album", Some("The Band"), Some(2), 2);
+        let upload = library.path.join("Upload");
+        scanned.replace_playlists(scan_folder_playlists(&library.root(), &upload, &config), &upload);
+
+        assert_eq!(scanned.playlists.len(), 1);
+        assert_eq!(
+            scanned.playlists[0]
+                .tracks
+                .iter()
+                .map(|track| track.filename.strip_prefix(&library.path).unwrap().to_path_buf())
+                .collect::<Vec<PathBuf>>(),
+            vec![
+                PathBuf::from("Big Album/CD1/01.mp3"),
+                PathBuf::from("Upload/01.mp3"),
+                PathBuf::from("Upload/02.mp3"),
+            ]
+        );
+        assert!(scanned.find_track(&library.path.join("Big Album/CD1/01.mp3").to_string_lossy()).is_some());
+
+        // scanning the upload again doesn't add its tracks twice
+        scanned.replace_playlists(scan_folder_playlists(&library.root(), &upload, &config), &upload);
+        assert_eq!(scanned.playlists[0].tracks.len(), 3);
+    }
+
+    #[test]
+    fn grouping_by_tags_ignores_case_and_keeps_labels() {
+        let album_track = |filename: &str, album: &str, artist: &str| Track {
+            album: Some(album.to_string()),
+            album_artist: Some(artist.to_string()),
+            ..track(filename, None, None)
+        };
+        let folder = |title: &str, tracks: Vec<Track>| Playlist {
+            id: 0,
+            title: title.to_string(),
+            tracks,
+            cover_source: Some(CoverSource::File(PathBuf::from(format!("/{}/cover.jpg", title)))),
+            smart: false,
+        };
+        let root = LibraryRoot::parse("Archive=/music");
+
+        let playlists = group_by_tags(
+            vec![
+                folder("a", vec![album_track("/a/1.mp3", "Album", "Artist")]),
+                folder("b", vec![album_track("/b/2.mp3", "album ", "ARTIST")]),
+            ],
+            &root,
+        );
+
+        assert_eq!(playlists.len(), 1);
+        assert_eq!(playlists[0].title, title(&["Archive", "Artist - Album"]));
+        assert_eq!(playlists[0].tracks.len(), 2);
+        // without embedded art, the cover of the first folder stands in
+        assert_eq!(
+            playlists[0].cover_source,
+            Some(CoverSource::File(PathBuf::from("/a/cover.jpg")))
+        );
+    }
+
     #[test]
     fn inserted_playlists_stay_sorted() {
-        let mut library = Library::empty("/music".to_string());
+        let mut library = Library::empty(vec![LibraryRoot::new("/music")], ScanConfig::default());
         for title in ["Zebra", "apple", "Middle", "Apricot"] {
             library.insert_playlist(empty_playlist(title));
         }
@@ -766,9 +1917,8 @@ mod tests {
             .file("Artist/01.mp3", "")
             .file("Artist/Album/01.mp3", "");
 
-        let folder = temp.path.to_str().unwrap().to_string();
-        let mut progressive = Library::empty(folder.clone());
-        scan_playlists(&folder, &mut |playlist| {
+        let mut progressive = Library::empty(vec![temp.root()], ScanConfig::default());
+        scan_playlists(&temp.root(), &ScanConfig::default(), &mut |playlist| {
             progressive.insert_playlist(playlist)
         });
 
@@ -789,11 +1939,22 @@ mod tests {
             artist: artist.map(|artist| artist.to_string()),
             title: title.map(|title| title.to_string()),
             has_cover_art: false,
+            start: None,
+            end: None,
+            album: None,
+            album_artist: None,
+            disc_number: None,
+            track_number: None,
+            track_gain: None,
+            album_gain: None,
+            genre: None,
+            added: None,
         }
     }
 
     fn filter_playlist() -> Playlist {
         Playlist {
+            id: 0,
             title: "The Beatles/Revolver".to_string(),
             tracks: vec![
                 track("01.mp3", Some("Taxman"), Some("The Beatles")),
@@ -801,6 +1962,7 @@ mod tests {
                 track("03 Untagged Song.mp3", None, None),
             ],
             cover_source: None,
+            smart: false,
         }
     }
 
@@ -826,6 +1988,45 @@ mod tests {
         assert!(playlist.matches_filter("axma"));
     }
 
+    #[test]
+    fn filter_ignores_accents_and_reads_other_scripts() {
+        let playlist = Playlist {
+            id: 0,
+            title: "Sigur Rós/Ágætis byrjun".to_string(),
+            tracks: vec![
+                track("01.mp3", Some("Halo"), Some("Beyoncé")),
+                track("02.mp3", Some("Кино"), Some("Виктор Цой")),
+            ],
+            cover_source: None,
+            smart: false,
+        };
+
+        assert!(playlist.matches_filter("sigur ros"));
+        assert!(playlist.matches_filter("agaetis"));
+        assert!(playlist.matches_filter("beyonce"));
+        assert!(playlist.matches_filter("BEYONCÉ"));
+        assert!(playlist.matches_filter("viktor tsoy"));
+        assert!(playlist.matches_filter("Цой"));
+    }
+
+    #[test]
+    fn typos_only_match_when_asked_for_and_rank_lower() {
+        let playlist = filter_playlist();
+
+        assert!(!playlist.matches_filter("beatels"));
+        assert_eq!(playlist.filter_score("beatels", true), Some(1));
+        // still being typed, with a typo
+        assert!(playlist.filter_score("revlo", true).is_some());
+        // too short for a typo
+        assert_eq!(playlist.filter_score("tax", true), playlist.filter_score("tax", false));
+        assert_eq!(playlist.filter_score("zappa", true), None);
+
+        // a whole word beats the start of one, which beats a typo
+        assert!(playlist.filter_score("taxman", true) > playlist.filter_score("taxm", true));
+        assert!(playlist.filter_score("taxm", true) > playlist.filter_score("tazman", true));
+        assert_eq!(playlist.filter_score("", true), Some(0));
+    }
+
     #[test]
     fn every_filter_word_has_to_match_but_they_may_match_different_fields() {
         let playlist = filter_playlist();
@@ -860,6 +2061,21 @@ mod tests {
         assert_eq!(streams[1].name, "B Stream");
     }
 
+    #[test]
+    fn reads_fallback_urls_with_or_without_a_logo() {
+        let folder = TempLibrary::new("stream-fallbacks");
+        folder.file(
+            "streams.txt",
+            "With Logo\nhttp://example.com/a\nstation.svg\nhttp://backup.example.com/a\n\nNo Logo\nhttp://example.com/b\nhttps://backup.example.com/b",
+        );
+
+        let streams = read_streams(folder.path.to_str().unwrap());
+        assert_eq!(streams[0].logo_file.as_deref(), Some("station.svg"));
+        assert_eq!(streams[0].fallback_urls, vec!["http://backup.example.com/a"]);
+        assert_eq!(streams[1].logo_file, None);
+        assert_eq!(streams[1].fallback_urls, vec!["https://backup.example.com/b"]);
+    }
+
     #[test]
     fn reads_stream_logos_from_the_streams_folder() {
         let folder = TempLibrary::new("stream-logos");
@@ -880,6 +2096,24 @@ mod tests {
         assert_eq!(streams[2].logo_svg, None);
     }
 
+    #[test]
+    fn tracks_of_a_cue_sheet_or_a_marked_folder_play_gapless() {
+        let library = TempLibrary::new("gapless");
+        library
+            .file("Mix/01.flac", "")
+            .file("Mix/02.flac", "")
+            .file(&format!("Mix/{}", GAPLESS_MARKER_FILE), "")
+            .file("Album/01.flac", "")
+            .file("Album/02.flac", "");
+        let file = |relative: &str| library.path.join(relative);
+
+        assert!(Library::plays_gapless(&file("Mix/01.flac"), &file("Mix/02.flac")));
+        assert!(Library::plays_gapless(&file("Album/01.flac"), &file("Album/01.flac")));
+        assert!(!Library::plays_gapless(&file("Album/01.flac"), &file("Album/02.flac")));
+        // the marker is about the folder's own tracks
+        assert!(!Library::plays_gapless(&file("Mix/02.flac"), &file("Album/01.flac")));
+    }
+
     #[test]
     fn a_missing_streams_folder_yields_no_streams() {
         let folder = TempLibrary::new("no-streams");
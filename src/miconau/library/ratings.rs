@@ -0,0 +1,325 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use super::{tags, Library, Playlist};
+
+const RATINGS_FILE: &str = "ratings.json";
+
+/// The title of the playlist of favourite tracks.
+const FAVOURITES_TITLE: &str = "Favourites";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Rating {
+    #[serde(default)]
+    pub favourite: bool,
+    /// 1 to 5.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stars: Option<u8>,
+}
+
+impl Rating {
+    fn is_empty(&self) -> bool {
+        !self.favourite && self.stars.is_none()
+    }
+}
+
+/// A change to a rating. What is left out stays as it is, and 0 stars takes
+/// the stars away.
+#[derive(Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RatingChange {
+    pub favourite: Option<bool>,
+    pub stars: Option<u8>,
+}
+
+impl RatingChange {
+    fn apply(&self, mut rating: Rating) -> Result<Rating, String> {
+        if let Some(favourite) = self.favourite {
+            rating.favourite = favourite;
+        }
+        match self.stars {
+            Some(0) => rating.stars = None,
+            Some(stars @ 1..=5) => rating.stars = Some(stars),
+            Some(stars) => return Err(format!("{} stars is not a rating from 1 to 5", stars)),
+            None => {}
+        }
+        Ok(rating)
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct StoredRatings {
+    /// By the location of the track, which is its path unless it is cut
+    /// from a single file by a CUE sheet.
+    #[serde(default)]
+    tracks: BTreeMap<String, Rating>,
+    /// By title, as playlists grouped by tags have no single folder.
+    #[serde(default)]
+    playlists: BTreeMap<String, Rating>,
+}
+
+/// Favourites and star ratings, kept apart from the music in `ratings.json`
+/// in the data folder: not every format has a place for them, and the
+/// library may be on a drive that is read only. Without a data folder they
+/// last until miconau stops.
+#[derive(Default)]
+pub struct Ratings {
+    file: Option<PathBuf>,
+    stored: StoredRatings,
+    /// Whether star ratings are written to the tags of the files as well.
+    pub write_to_tags: bool,
+    /// Tracks whose stars are yet to be written to their tags. Files that are
+    /// playing are left alone until they are done.
+    pending_tags: Vec<String>,
+}
+
+impl Ratings {
+    pub fn load(data_folder: Option<&Path>) -> Ratings {
+        let file = data_folder.map(|folder| folder.join(RATINGS_FILE));
+        let stored = match file.as_ref().map(fs::read_to_string) {
+            Some(Ok(content)) => serde_json::from_str(&content).unwrap_or_else(|error| {
+                println!("Could not read the ratings from {:?}: {}", file, error);
+                StoredRatings::default()
+            }),
+            _ => StoredRatings::default(),
+        };
+        Ratings { file, stored, ..Ratings::default() }
+    }
+
+    pub fn track(&self, location: &str) -> Rating {
+        self.stored.tracks.get(location).copied().unwrap_or_default()
+    }
+
+    pub fn playlist(&self, title: &str) -> Rating {
+        self.stored.playlists.get(title).copied().unwrap_or_default()
+    }
+
+    pub fn pending_tags(&self) -> &[String] {
+        &self.pending_tags
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        let content = serde_json::to_string_pretty(&self.stored).map_err(|error| error.to_string())?;
+        let temporary = file.with_extension("json.tmp");
+        file.parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&temporary, content))
+            .and_then(|_| fs::rename(&temporary, file))
+            .map_err(|error| format!("Could not write {:?}: {}", file, error))
+    }
+}
+
+impl Ratings {
+    /// Moves ratings over to the new locations of tracks and titles of
+    /// playlists, given as pairs of old and new keys, and saves them. A new
+    /// key of `None` forgets the rating, for what was deleted: a track or
+    /// playlist that turns up under the old key later shouldn't be rated.
+    pub(super) fn follow(&mut self, tracks: &[(String, Option<String>)], playlists: &[(String, Option<String>)]) {
+        let mut changed = rekey(&mut self.stored.tracks, tracks);
+        changed |= rekey(&mut self.stored.playlists, playlists);
+        self.pending_tags = self
+            .pending_tags
+            .drain(..)
+            .filter_map(|location| match tracks.iter().find(|(old, _)| *old == location) {
+                Some((_, new)) => new.clone(),
+                None => Some(location),
+            })
+            .collect();
+        if changed {
+            if let Err(error) = self.save() {
+                println!("{}", error);
+            }
+        }
+    }
+}
+
+/// Moves ratings to new keys, or removes them for a new key of `None`, and
+/// tells whether any were. All are taken out before any go back in, so two
+/// that swap places don't overwrite each other.
+fn rekey(ratings: &mut BTreeMap<String, Rating>, changes: &[(String, Option<String>)]) -> bool {
+    let moved: Vec<(&Option<String>, Rating)> = changes
+        .iter()
+        .filter_map(|(old, new)| ratings.remove(old).map(|rating| (new, rating)))
+        .collect();
+    let changed = !moved.is_empty();
+    for (new, rating) in moved {
+        if let Some(new) = new {
+            ratings.insert(new.clone(), rating);
+        }
+    }
+    changed
+}
+
+/// Sets or, for an empty rating, removes a rating.
+fn set_rating(ratings: &mut BTreeMap<String, Rating>, key: &str, rating: Rating) {
+    if rating.is_empty() {
+        ratings.remove(key);
+    } else {
+        ratings.insert(key.to_string(), rating);
+    }
+}
+
+impl Library {
+    /// Rates a track and returns its new rating. Its stars are left for the
+    /// player to write to its tags, once the file isn't playing.
+    pub fn rate_track(&mut self, playlist_index: usize, track_index: usize, change: &RatingChange) -> Result<Rating, String> {
+        let track = self.playlists
+            .get(playlist_index)
+            .and_then(|playlist| playlist.tracks.get(track_index))
+            .ok_or(format!("Track {} of playlist {} not found", track_index, playlist_index))?;
+        let location = track.location();
+        // The tags of a CUE sheet's file belong to the whole album.
+        let tagged = track.start.is_none();
+        let old = self.ratings.track(&location);
+        let rating = change.apply(old)?;
+        set_rating(&mut self.ratings.stored.tracks, &location, rating);
+        if let Err(error) = self.ratings.save() {
+            set_rating(&mut self.ratings.stored.tracks, &location, old);
+            return Err(error);
+        }
+        if self.ratings.write_to_tags && tagged && rating.stars != old.stars && !self.ratings.pending_tags.contains(&location) {
+            self.ratings.pending_tags.push(location);
+        }
+        Ok(rating)
+    }
+
+    pub fn rate_playlist(&mut self, playlist_index: usize, change: &RatingChange) -> Result<Rating, String> {
+        let title = self.playlists
+            .get(playlist_index)
+            .map(|playlist| playlist.title.clone())
+            .ok_or(format!("Playlist {} not found", playlist_index))?;
+        let old = self.ratings.playlist(&title);
+        let rating = change.apply(old)?;
+        set_rating(&mut self.ratings.stored.playlists, &title, rating);
+        if let Err(error) = self.ratings.save() {
+            set_rating(&mut self.ratings.stored.playlists, &title, old);
+            return Err(error);
+        }
+        Ok(rating)
+    }
+
+    /// Where a track is in the library, by its location: in the playlist of
+    /// its folder or tags, never in a smart playlist.
+    pub fn track_position(&self, location: &str) -> Option<(usize, usize)> {
+        self.playlists.iter().enumerate().filter(|(_, playlist)| !playlist.smart).find_map(|(playlist_index, playlist)| {
+            playlist.tracks
+                .iter()
+                .position(|track| track.location() == location)
+                .map(|track_index| (playlist_index, track_index))
+        })
+    }
+
+    /// Writes the stars of a track that were waiting for it to be done
+    /// playing to its tags.
+    pub fn write_rating_tags(&mut self, location: &str) {
+        self.ratings.pending_tags.retain(|pending| pending != location);
+        let stars = self.ratings.track(location).stars;
+        if let Err(error) = tags::write_rating(&Library::location_file(location), stars) {
+            println!("{}", error);
+        }
+    }
+
+    /// The favourite tracks as a playlist of their own, in the order of the
+    /// library. Built when asked for, so it follows the ratings and the
+    /// scans without being kept up to date.
+    pub fn favourites(&self) -> Playlist {
+        let tracks = self.playlists
+            .iter()
+            .filter(|playlist| !playlist.smart)
+            .flat_map(|playlist| &playlist.tracks)
+            .filter(|track| self.ratings.track(&track.location()).favourite)
+            .cloned()
+            .collect();
+        Playlist {
+            id: 0,
+            title: FAVOURITES_TITLE.to_string(),
+            tracks,
+            cover_source: None,
+            smart: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+    use crate::library::{ScanConfig, Track};
+
+    fn library(folder: &Path) -> Library {
+        let mut library = Library::empty(Vec::new(), ScanConfig::default());
+        library.ratings = Ratings::load(Some(folder));
+        let track = |name: &str| Track {
+            filename: PathBuf::from(format!("/music/album/{}.mp3", name)),
+            artist: None,
+            title: Some(name.to_string()),
+            has_cover_art: false,
+            start: None,
+            end: None,
+            album: None,
+            album_artist: None,
+            disc_number: None,
+            track_number: None,
+            track_gain: None,
+            album_gain: None,
+            genre: None,
+            added: None,
+        };
+        library.playlists.push(Playlist {
+            id: 0,
+            title: "Album".to_string(),
+            tracks: vec![track("One"), track("Two"), track("Three")],
+            cover_source: None,
+            smart: false,
+        });
+        library
+    }
+
+    #[test]
+    fn ratings_are_kept_in_the_data_folder() {
+        let temp = TempDir::new("ratings");
+        let folder = &temp.path;
+        let mut library = library(folder);
+
+        let love = RatingChange { favourite: Some(true), ..RatingChange::default() };
+        library.rate_track(0, 2, &love).unwrap();
+        library.rate_track(0, 0, &love).unwrap();
+        library.rate_track(0, 0, &RatingChange { stars: Some(4), ..RatingChange::default() }).unwrap();
+        assert!(library.rate_track(0, 1, &RatingChange { stars: Some(6), ..RatingChange::default() }).is_err());
+        assert!(library.rate_track(0, 3, &love).is_err());
+        library.rate_playlist(0, &RatingChange { stars: Some(5), ..RatingChange::default() }).unwrap();
+
+        let mut library = self::library(folder);
+        assert_eq!(
+            library.ratings.track("/music/album/One.mp3"),
+            Rating { favourite: true, stars: Some(4) }
+        );
+        assert_eq!(library.ratings.playlist("Album").stars, Some(5));
+        let favourites = library.favourites();
+        assert_eq!(favourites.title, FAVOURITES_TITLE);
+        let titles: Vec<_> = favourites.tracks.iter().map(|track| track.display_title()).collect();
+        assert_eq!(titles, vec!["One", "Three"]);
+
+        // taking everything away forgets the track
+        library.rate_track(0, 0, &RatingChange { favourite: Some(false), stars: Some(0) }).unwrap();
+        assert_eq!(library.ratings.track("/music/album/One.mp3"), Rating::default());
+        assert!(!library.ratings.stored.tracks.contains_key("/music/album/One.mp3"));
+    }
+
+    #[test]
+    fn only_changed_stars_wait_to_be_written_to_tags() {
+        let mut library = library(Path::new("/nonexistent"));
+        library.ratings = Ratings { write_to_tags: true, ..Ratings::default() };
+        library.rate_track(0, 0, &RatingChange { favourite: Some(true), ..RatingChange::default() }).unwrap();
+        assert!(library.ratings.pending_tags().is_empty());
+        library.rate_track(0, 0, &RatingChange { stars: Some(3), ..RatingChange::default() }).unwrap();
+        library.rate_track(0, 0, &RatingChange { stars: Some(4), ..RatingChange::default() }).unwrap();
+        assert_eq!(library.ratings.pending_tags(), ["/music/album/One.mp3"]);
+        assert_eq!(library.track_position("/music/album/Two.mp3"), Some((0, 1)));
+    }
+}
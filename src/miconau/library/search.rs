@@ -0,0 +1,615 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use serde::Serialize;
+use super::{text, Library, Playlist, Track};
+
+/// What a word of a playlist or track was found in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Field {
+    Title,
+    Artist,
+    Album,
+    Genre,
+    /// The title of the playlist a track is in, or of the playlist itself.
+    Playlist,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Field> {
+        match name {
+            "title" => Some(Field::Title),
+            "artist" => Some(Field::Artist),
+            "album" => Some(Field::Album),
+            "genre" => Some(Field::Genre),
+            "playlist" => Some(Field::Playlist),
+            _ => None,
+        }
+    }
+
+    /// How much a match in this field counts towards a result's rank.
+    fn weight(self) -> u32 {
+        match self {
+            Field::Title => 4,
+            Field::Artist => 3,
+            Field::Album => 2,
+            Field::Genre | Field::Playlist => 1,
+        }
+    }
+}
+
+/// The words of a text folded for matching (see `text::fold`), with where
+/// each is in it. Anything that isn't a letter, a digit or an accent
+/// separates words.
+fn words(content: &str) -> Vec<(usize, usize, String)> {
+    let mut words = Vec::new();
+    let mut start: Option<usize> = None;
+    for (position, character) in content.char_indices().chain(std::iter::once((content.len(), ' '))) {
+        match (start, character.is_alphanumeric() || text::is_combining_mark(character)) {
+            (None, true) => start = Some(position),
+            (Some(from), false) => {
+                let word = text::fold(&content[from..position]);
+                if !word.is_empty() {
+                    words.push((from, position, word));
+                }
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    words
+}
+
+enum DocumentKind {
+    Playlist,
+    Track { location: String },
+}
+
+/// A playlist or a track as the index knows it.
+struct Document {
+    /// The id of the playlist, and its title to find it by in the library.
+    playlist: u64,
+    playlist_title: String,
+    kind: DocumentKind,
+    /// What was indexed, to take it out of the index again.
+    fields: Vec<(Field, String)>,
+}
+
+/// An inverted index of the words of the playlists and tracks, so a search
+/// only looks at what matches rather than at the whole library, which takes
+/// a while for a big one and holds up the player while it does. Kept up to
+/// date as playlists come and go, a playlist at a time.
+#[derive(Default)]
+pub struct SearchIndex {
+    /// Slots of documents. Slots of removed documents are None until reused.
+    documents: Vec<Option<Document>>,
+    free: Vec<usize>,
+    /// The documents each word is in. Sorted, so the words that start with
+    /// what was typed so far are a range.
+    words: BTreeMap<String, Vec<(usize, Field)>>,
+    /// The documents of each playlist, by its id.
+    by_playlist: HashMap<u64, Vec<usize>>,
+}
+
+impl SearchIndex {
+    /// Indexes a playlist and its tracks, in place of what was indexed for it
+    /// before. Of a smart playlist only the title goes in: its tracks are
+    /// copies of tracks indexed already.
+    pub fn index_playlist(&mut self, playlist: &Playlist) {
+        self.remove_playlist(playlist.id);
+        let mut fields = vec![(Field::Playlist, playlist.title.clone())];
+        if !playlist.smart {
+            // The artists and albums of its tracks, so "beatles revolver"
+            // finds the album titled just "Revolver".
+            for track in &playlist.tracks {
+                for field in [(Field::Artist, &track.artist), (Field::Artist, &track.album_artist), (Field::Album, &track.album)] {
+                    if let (field, Some(text)) = field {
+                        if !fields.iter().any(|(existing, known)| *existing == field && known == text) {
+                            fields.push((field, text.clone()));
+                        }
+                    }
+                }
+            }
+        }
+        let mut ids = vec![self.add(Document {
+            playlist: playlist.id,
+            playlist_title: playlist.title.clone(),
+            kind: DocumentKind::Playlist,
+            fields,
+        })];
+        if !playlist.smart {
+            for track in &playlist.tracks {
+                ids.push(self.add(Document {
+                    playlist: playlist.id,
+                    playlist_title: playlist.title.clone(),
+                    kind: DocumentKind::Track { location: track.location() },
+                    fields: track_fields(track, &playlist.title),
+                }));
+            }
+        }
+        self.by_playlist.insert(playlist.id, ids);
+    }
+
+    pub fn remove_playlist(&mut self, playlist: u64) {
+        for id in self.by_playlist.remove(&playlist).unwrap_or_default() {
+            let Some(document) = self.documents[id].take() else {
+                continue;
+            };
+            for (_, text) in &document.fields {
+                for (_, _, word) in words(text) {
+                    if let Some(postings) = self.words.get_mut(&word) {
+                        postings.retain(|(posting, _)| *posting != id);
+                        if postings.is_empty() {
+                            self.words.remove(&word);
+                        }
+                    }
+                }
+            }
+            self.free.push(id);
+        }
+    }
+
+    fn add(&mut self, document: Document) -> usize {
+        let id = self.free.pop().unwrap_or(self.documents.len());
+        for (field, text) in &document.fields {
+            for (_, _, word) in words(text) {
+                let postings = self.words.entry(word).or_default();
+                if !postings.contains(&(id, *field)) {
+                    postings.push((id, *field));
+                }
+            }
+        }
+        if id == self.documents.len() {
+            self.documents.push(Some(document));
+        } else {
+            self.documents[id] = Some(document);
+        }
+        id
+    }
+
+    /// The playlists whose words can match every word of a filter the way
+    /// `Playlist::filter_score` has it, found by going through the words of
+    /// the index once rather than folding every title in the library. A word
+    /// of the filter with punctuation in it may match across words of the
+    /// index, so it doesn't narrow anything down; neither does an empty
+    /// filter, for which this is None.
+    fn filter_candidates(&self, filter: &str, typos: bool) -> Option<HashSet<u64>> {
+        let mut candidates: Option<HashSet<u64>> = None;
+        for filter_word in filter.split_whitespace() {
+            let parts = words(filter_word);
+            let [(_, _, word)] = parts.as_slice() else {
+                continue;
+            };
+            if *word != text::fold(filter_word) {
+                continue;
+            }
+            let playlists: HashSet<u64> = self.words
+                .iter()
+                .filter(|(indexed, _)| indexed.contains(word.as_str()) || (typos && text::matches_with_typos(word, indexed)))
+                .flat_map(|(_, postings)| postings)
+                .filter_map(|(id, _)| self.documents[*id].as_ref().map(|document| document.playlist))
+                .collect();
+            candidates = Some(match candidates {
+                None => playlists,
+                Some(candidates) => candidates.intersection(&playlists).copied().collect(),
+            });
+        }
+        candidates
+    }
+
+    /// The documents that match every term, with their rank. A word matches
+    /// a term it starts with, and counts twice if it is the whole term.
+    fn search(&self, terms: &[QueryTerm]) -> Vec<(usize, u32)> {
+        let mut matches: Option<HashMap<usize, u32>> = None;
+        for term in terms {
+            let mut scores: HashMap<usize, u32> = HashMap::new();
+            for (word, postings) in self.words.range(term.text.clone()..) {
+                if !word.starts_with(&term.text) {
+                    break;
+                }
+                let exact = if *word == term.text { 2 } else { 1 };
+                for (id, field) in postings {
+                    if term.field.is_some_and(|wanted| wanted != *field) {
+                        continue;
+                    }
+                    let score = scores.entry(*id).or_default();
+                    *score = (*score).max(field.weight() * exact);
+                }
+            }
+            matches = Some(match matches {
+                None => scores,
+                Some(matches) => matches
+                    .into_iter()
+                    .filter_map(|(id, score)| scores.get(&id).map(|more| (id, score + more)))
+                    .collect(),
+            });
+        }
+        matches.unwrap_or_default().into_iter().collect()
+    }
+}
+
+fn track_fields(track: &Track, playlist_title: &str) -> Vec<(Field, String)> {
+    let mut fields = vec![(Field::Title, track.display_title()), (Field::Playlist, playlist_title.to_string())];
+    for (field, text) in [
+        (Field::Artist, &track.artist),
+        (Field::Artist, &track.album_artist),
+        (Field::Album, &track.album),
+        (Field::Genre, &track.genre),
+    ] {
+        if let Some(text) = text {
+            fields.push((field, text.clone()));
+        }
+    }
+    fields
+}
+
+/// A word of a query, and the field it has to be in if the query says.
+#[derive(Debug, PartialEq)]
+struct QueryTerm {
+    field: Option<Field>,
+    text: String,
+}
+
+/// Reads a query such as `taxman artist:beatles album:"with the beatles"`.
+/// A field that isn't known is taken as part of the text.
+fn parse_query(query: &str) -> Vec<QueryTerm> {
+    let mut terms = Vec::new();
+    let mut rest = query.trim_start();
+    while !rest.is_empty() {
+        let (field, value_start) = match rest.split_once(':') {
+            Some((name, _)) if !name.contains(char::is_whitespace) => match Field::parse(&name.to_lowercase()) {
+                Some(field) => (Some(field), &rest[name.len() + 1..]),
+                None => (None, rest),
+            },
+            _ => (None, rest),
+        };
+        let (value, remaining) = match value_start.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => value_start.split_once(char::is_whitespace).unwrap_or((value_start, "")),
+        };
+        for (_, _, text) in words(value) {
+            terms.push(QueryTerm { field, text });
+        }
+        rest = remaining.trim_start();
+    }
+    terms
+}
+
+/// A piece of a text, and whether it is what was searched for.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Segment {
+    pub text: String,
+    pub matched: bool,
+}
+
+/// Cuts a text into the words that match a term and what is in between.
+fn highlight(text: &str, field: Field, terms: &[QueryTerm]) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut push = |text: &str, matched: bool| {
+        if text.is_empty() {
+            return;
+        }
+        match segments.last_mut() {
+            Some(last) if last.matched == matched => last.text.push_str(text),
+            _ => segments.push(Segment { text: text.to_string(), matched }),
+        }
+    };
+    let mut end = 0;
+    for (from, to, word) in words(text) {
+        let matched = terms
+            .iter()
+            .any(|term| term.field.is_none_or(|wanted| wanted == field) && word.starts_with(&term.text));
+        if matched {
+            push(&text[end..from], false);
+            push(&text[from..to], true);
+            end = to;
+        }
+    }
+    push(&text[end..], false);
+    segments
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackResult {
+    pub playlist_index: usize,
+    pub track_index: usize,
+    pub title: Vec<Segment>,
+    pub artist: Option<Vec<Segment>>,
+    pub album: Option<Vec<Segment>>,
+    pub playlist_name: Vec<Segment>,
+    pub score: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NamedResult {
+    pub index: usize,
+    pub name: Vec<Segment>,
+    pub score: u32,
+}
+
+#[derive(Serialize, Default)]
+pub struct SearchResults {
+    pub tracks: Vec<TrackResult>,
+    pub playlists: Vec<NamedResult>,
+    pub streams: Vec<NamedResult>,
+}
+
+impl Library {
+    /// Where a playlist is in the library, found by its title the way the
+    /// library is sorted rather than by going through all of it. The id picks
+    /// it out from others of the same title.
+    fn playlist_index(&self, id: u64, title: &str) -> Option<usize> {
+        let end = self.sorted_position(title);
+        self.playlists[..end].iter().rposition(|playlist| playlist.id == id)
+    }
+
+    /// Indexes a playlist again for a change to its tracks.
+    pub(super) fn reindex_playlist(&mut self, index: usize) {
+        self.search_index.index_playlist(&self.playlists[index]);
+    }
+
+    /// The playlists that match a filter, by index and with their score (see
+    /// `Playlist::filter_score`). Only those the index has the words for are
+    /// scored, along with the smart playlists, whose tracks the index has
+    /// under the playlists they are from.
+    pub fn filter_playlists(&self, filter: &str, typos: bool) -> Vec<(u32, usize)> {
+        let candidates = self.search_index.filter_candidates(filter, typos);
+        self.playlists
+            .iter()
+            .enumerate()
+            .filter(|(_, playlist)| playlist.smart || candidates.as_ref().is_none_or(|ids| ids.contains(&playlist.id)))
+            .filter_map(|(index, playlist)| playlist.filter_score(filter, typos).map(|score| (score, index)))
+            .collect()
+    }
+
+    /// The tracks, playlists and streams that match a query, the best first,
+    /// at most `limit` of each. Streams are matched by their name, and only by
+    /// queries without fields; there are too few of them to need the index.
+    pub fn search(&self, query: &str, limit: usize) -> SearchResults {
+        let terms = parse_query(query);
+        if terms.is_empty() {
+            return SearchResults::default();
+        }
+        let mut ranked = self.search_index.search(&terms);
+        ranked.sort_by(|(a, a_score), (b, b_score)| b_score.cmp(a_score).then(a.cmp(b)));
+
+        let mut results = SearchResults::default();
+        for (id, score) in ranked {
+            let Some(document) = &self.search_index.documents[id] else {
+                continue;
+            };
+            let Some(playlist_index) = self.playlist_index(document.playlist, &document.playlist_title) else {
+                continue;
+            };
+            let playlist = &self.playlists[playlist_index];
+            match &document.kind {
+                DocumentKind::Playlist if results.playlists.len() < limit => {
+                    results.playlists.push(NamedResult {
+                        index: playlist_index,
+                        name: highlight(&playlist.title, Field::Playlist, &terms),
+                        score,
+                    });
+                }
+                DocumentKind::Track { location } if results.tracks.len() < limit => {
+                    let Some(track_index) = playlist.tracks.iter().position(|track| track.location() == *location) else {
+                        continue;
+                    };
+                    let track = &playlist.tracks[track_index];
+                    results.tracks.push(TrackResult {
+                        playlist_index,
+                        track_index,
+                        title: highlight(&track.display_title(), Field::Title, &terms),
+                        artist: track.artist.as_deref().map(|artist| highlight(artist, Field::Artist, &terms)),
+                        album: track.album.as_deref().map(|album| highlight(album, Field::Album, &terms)),
+                        playlist_name: highlight(&playlist.title, Field::Playlist, &terms),
+                        score,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        if terms.iter().all(|term| term.field.is_none()) {
+            let mut streams: Vec<NamedResult> = self.streams
+                .iter()
+                .enumerate()
+                .filter_map(|(index, stream)| {
+                    let name_words = words(&stream.name);
+                    let mut score = 0;
+                    for term in &terms {
+                        let word = name_words.iter().find(|(_, _, word)| word.starts_with(&term.text))?;
+                        score += Field::Title.weight() * if word.2 == term.text { 2 } else { 1 };
+                    }
+                    Some(NamedResult { index, name: highlight(&stream.name, Field::Title, &terms), score })
+                })
+                .collect();
+            streams.sort_by(|a, b| b.score.cmp(&a.score).then(a.index.cmp(&b.index)));
+            streams.truncate(limit);
+            results.streams = streams;
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+    use crate::library::{ScanConfig, Stream};
+
+    fn track(name: &str, artist: &str, album: &str) -> Track {
+        Track {
+            filename: PathBuf::from(format!("/music/{}/{}.mp3", album, name)),
+            artist: Some(artist.to_string()),
+            title: Some(name.to_string()),
+            has_cover_art: false,
+            start: None,
+            end: None,
+            album: Some(album.to_string()),
+            album_artist: None,
+            disc_number: None,
+            track_number: None,
+            track_gain: None,
+            album_gain: None,
+            genre: None,
+            added: None,
+        }
+    }
+
+    fn library() -> Library {
+        let mut library = Library::empty(Vec::new(), ScanConfig::default());
+        library.insert_playlist(Playlist {
+            id: 0,
+            title: "The Beatles/Revolver".to_string(),
+            tracks: vec![track("Taxman", "The Beatles", "Revolver"), track("Eleanor Rigby", "The Beatles", "Revolver")],
+            cover_source: None,
+            smart: false,
+        });
+        library.insert_playlist(Playlist {
+            id: 0,
+            title: "Abbey Road".to_string(),
+            tracks: vec![track("Something", "The Beatles", "Abbey Road"), track("Here Comes the Sun", "The Beatles", "Abbey Road")],
+            cover_source: None,
+            smart: false,
+        });
+        library.insert_playlist(Playlist {
+            id: 0,
+            title: "Sun Ra".to_string(),
+            tracks: vec![track("Space Is the Place", "Sun Ra", "Space Is the Place")],
+            cover_source: None,
+            smart: false,
+        });
+        library.streams.push(Stream {
+            name: "Sunshine Radio".to_string(),
+            url: "http://example.com/sun".to_string(),
+            logo_svg: None,
+            logo_file: None,
+            fallback_urls: Vec::new(),
+            up: None,
+        });
+        library
+    }
+
+    fn text(segments: &[Segment]) -> String {
+        segments.iter().map(|segment| segment.text.clone()).collect()
+    }
+
+    #[test]
+    fn reads_fielded_queries() {
+        assert_eq!(parse_query(r#"taxman Artist:beatles album:"abbey road" mood:happy"#), vec![
+            QueryTerm { field: None, text: "taxman".to_string() },
+            QueryTerm { field: Some(Field::Artist), text: "beatles".to_string() },
+            QueryTerm { field: Some(Field::Album), text: "abbey".to_string() },
+            QueryTerm { field: Some(Field::Album), text: "road".to_string() },
+            QueryTerm { field: None, text: "mood".to_string() },
+            QueryTerm { field: None, text: "happy".to_string() },
+        ]);
+        assert!(parse_query("  ").is_empty());
+    }
+
+    #[test]
+    fn ranks_tracks_playlists_and_streams() {
+        let library = library();
+        let results = library.search("sun", 10);
+        // a title beats a playlist name, and a whole word a word it starts
+        let titles: Vec<String> = results.tracks.iter().map(|track| text(&track.title)).collect();
+        assert_eq!(titles, vec!["Here Comes the Sun", "Space Is the Place"]);
+        assert_eq!(results.playlists.len(), 1);
+        assert_eq!(library.playlists[results.playlists[0].index].title, "Sun Ra");
+        assert_eq!(text(&results.streams[0].name), "Sunshine Radio");
+        assert_eq!(results.streams[0].name[0], Segment { text: "Sunshine".to_string(), matched: true });
+
+        // every word has to match, though not all in the same field
+        let results = library.search("beatles revolver", 10);
+        assert_eq!(results.tracks.len(), 2);
+        assert_eq!(library.playlists[results.playlists[0].index].title, "The Beatles/Revolver");
+        let track = &results.tracks[0];
+        assert_eq!(library.playlists[track.playlist_index].tracks[track.track_index].title, Some(text(&track.title)));
+        assert_eq!(track.artist.as_ref().unwrap(), &vec![
+            Segment { text: "The ".to_string(), matched: false },
+            Segment { text: "Beatles".to_string(), matched: true },
+        ]);
+
+        // fields narrow it down, and leave the streams out
+        let results = library.search("title:sun", 10);
+        assert_eq!(results.tracks.len(), 1);
+        assert!(results.playlists.is_empty());
+        assert!(results.streams.is_empty());
+        assert_eq!(library.search("album:abbey", 10).tracks.len(), 2);
+        assert_eq!(library.search("sun", 1).tracks.len(), 1);
+    }
+
+    #[test]
+    fn filters_only_score_the_playlists_with_matching_words() {
+        let library = library();
+        let titles = |filter: &str, typos: bool| -> Vec<String> {
+            library
+                .filter_playlists(filter, typos)
+                .into_iter()
+                .map(|(_, index)| library.playlists[index].title.clone())
+                .collect()
+        };
+        assert_eq!(library.search_index.filter_candidates("", false), None);
+        assert_eq!(titles("", false).len(), 3);
+        assert_eq!(titles("beatles sun", false), vec!["Abbey Road"]);
+        // inside a word, with typos, and across punctuation
+        assert_eq!(titles("axma", false), vec!["The Beatles/Revolver"]);
+        assert!(titles("tazman", false).is_empty());
+        assert_eq!(titles("tazman", true), vec!["The Beatles/Revolver"]);
+        assert_eq!(titles("beatles/revolver", false), vec!["The Beatles/Revolver"]);
+        assert_eq!(library.search_index.filter_candidates("sun", false).map(|ids| ids.len()), Some(2));
+    }
+
+    #[test]
+    fn index_follows_the_library() {
+        let mut library = library();
+        let index = library.playlists.iter().position(|playlist| playlist.title == "Sun Ra").unwrap();
+        library.remove_playlist_at(index);
+        assert!(library.search("space", 10).tracks.is_empty());
+
+        library.replace_playlists(vec![Playlist {
+            id: 0,
+            title: "Abbey Road".to_string(),
+            tracks: vec![track("Octopus's Garden", "The Beatles", "Abbey Road")],
+            cover_source: None,
+            smart: false,
+        }], Path::new("/music/Abbey Road"));
+        assert!(library.search("something", 10).tracks.is_empty());
+        let results = library.search("octopus", 10);
+        assert_eq!(results.tracks.len(), 1);
+        assert_eq!(library.playlists[results.tracks[0].playlist_index].title, "Abbey Road");
+        // the slots of removed documents are reused rather than piling up
+        assert_eq!(library.search_index.documents.len(), 8);
+    }
+
+    #[test]
+    fn tells_apart_playlists_of_the_same_title() {
+        // as two unlabelled roots with a folder of the same name give
+        let mut library = Library::empty(Vec::new(), ScanConfig::default());
+        for (name, album) in [("Taxman", "Revolver"), ("Something", "Abbey Road")] {
+            library.insert_playlist(Playlist {
+                id: 0,
+                title: "Live".to_string(),
+                tracks: vec![track(name, "The Beatles", album)],
+                cover_source: None,
+                smart: false,
+            });
+        }
+        let results = library.search("live", 10);
+        assert_eq!(results.playlists.len(), 2);
+        assert_ne!(results.playlists[0].index, results.playlists[1].index);
+        let found = library.search("something", 10);
+        assert_eq!(library.playlists[found.tracks[0].playlist_index].tracks[0].title, Some("Something".to_string()));
+
+        // indexing one again or taking it out leaves the other one be
+        library.reindex_playlist(0);
+        assert_eq!(library.search("taxman", 10).tracks.len(), 1);
+        assert_eq!(library.search("something", 10).tracks.len(), 1);
+        let index = library.playlists.iter().position(|playlist| playlist.tracks[0].title == Some("Taxman".to_string())).unwrap();
+        library.remove_playlist_at(index);
+        assert!(library.search("taxman", 10).tracks.is_empty());
+        let results = library.search("something", 10);
+        assert_eq!(results.tracks.len(), 1);
+        assert_eq!(results.tracks[0].playlist_index, 0);
+    }
+}
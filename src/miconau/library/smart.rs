@@ -0,0 +1,440 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::hash::{BuildHasher, RandomState};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use super::{Library, Playlist, Track};
+
+/// A comparison of a number, such as a rating or a play count.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    fn holds(self, value: usize, than: usize) -> bool {
+        let ordering = value.cmp(&than);
+        match self {
+            Comparison::Equal => ordering == Ordering::Equal,
+            Comparison::NotEqual => ordering != Ordering::Equal,
+            Comparison::Less => ordering == Ordering::Less,
+            Comparison::LessOrEqual => ordering != Ordering::Greater,
+            Comparison::Greater => ordering == Ordering::Greater,
+            Comparison::GreaterOrEqual => ordering != Ordering::Less,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TextField {
+    Genre,
+    /// The artist of the track or of its album, so "artist = X" finds the
+    /// tracks of X on compilations as well as X's own albums.
+    Artist,
+    Album,
+    Title,
+}
+
+impl TextField {
+    fn values(self, track: &Track) -> Vec<String> {
+        match self {
+            TextField::Genre => track.genre.iter().cloned().collect(),
+            TextField::Artist => track.artist.iter().chain(&track.album_artist).cloned().collect(),
+            TextField::Album => track.album.iter().cloned().collect(),
+            TextField::Title => vec![track.display_title()],
+        }
+    }
+}
+
+/// One rule of a smart playlist. A track has to match every rule.
+#[derive(Clone, Debug, PartialEq)]
+enum Rule {
+    /// A tag is, isn't or contains a text, ignoring case.
+    Is { field: TextField, value: String },
+    IsNot { field: TextField, value: String },
+    Contains { field: TextField, value: String },
+    /// The track is rated so many stars. Unrated tracks have 0.
+    Rating { comparison: Comparison, stars: usize },
+    Favourite,
+    /// The file came into the library this long ago at the most.
+    AddedWithin(Duration),
+    /// The track was played so many times, going by the play history.
+    Plays { comparison: Comparison, count: usize },
+}
+
+impl Rule {
+    fn matches(&self, track: &Track, library: &Library, play_counts: &HashMap<String, usize>, now: SystemTime) -> bool {
+        match self {
+            Rule::Is { field, value } => field.values(track).iter().any(|text| text.trim().to_lowercase() == *value),
+            Rule::IsNot { field, value } => !field.values(track).iter().any(|text| text.trim().to_lowercase() == *value),
+            Rule::Contains { field, value } => field.values(track).iter().any(|text| text.to_lowercase().contains(value)),
+            Rule::Rating { comparison, stars } => {
+                let rated = library.ratings.track(&track.location()).stars.unwrap_or(0);
+                comparison.holds(rated.into(), *stars)
+            }
+            Rule::Favourite => library.ratings.track(&track.location()).favourite,
+            Rule::AddedWithin(within) => track
+                .added
+                .and_then(|added| now.duration_since(added).ok())
+                .is_some_and(|age| age <= *within),
+            Rule::Plays { comparison, count } => {
+                let plays = play_counts.get(&track.location()).copied().unwrap_or(0);
+                comparison.holds(plays, *count)
+            }
+        }
+    }
+
+    /// Reads a rule such as `genre = Jazz`, `rating >= 4`, `never played` or
+    /// `added in last 30 days`.
+    fn parse(line: &str) -> Result<Rule, String> {
+        let lowercase = line.to_lowercase();
+        match lowercase.as_str() {
+            "never played" => return Ok(Rule::Plays { comparison: Comparison::Equal, count: 0 }),
+            "favourite" | "favorite" => return Ok(Rule::Favourite),
+            _ => {}
+        }
+        if let Some(days) = lowercase
+            .strip_prefix("added in last ")
+            .and_then(|rest| rest.strip_suffix(" days").or_else(|| rest.strip_suffix(" day")))
+        {
+            let days: u64 = days.trim().parse().map_err(|_| format!("Not a number of days: {}", line))?;
+            return Ok(Rule::AddedWithin(Duration::from_secs(days * 24 * 60 * 60)));
+        }
+
+        // The longer operators first, so ">=" isn't read as ">" and "= 4".
+        const OPERATORS: [&str; 7] = [">=", "<=", "!=", "=", ">", "<", " contains "];
+        let (position, operator) = OPERATORS
+            .iter()
+            .filter_map(|operator| lowercase.find(operator).map(|position| (position, *operator)))
+            .min_by_key(|(position, operator)| (*position, std::cmp::Reverse(operator.len())))
+            .ok_or_else(|| format!("Not a rule: {}", line))?;
+        let field = lowercase[..position].trim();
+        let value = lowercase[position + operator.len()..].trim().to_string();
+        if value.is_empty() {
+            return Err(format!("Nothing to compare with: {}", line));
+        }
+
+        let text_field = match field {
+            "genre" => Some(TextField::Genre),
+            "artist" => Some(TextField::Artist),
+            "album" => Some(TextField::Album),
+            "title" => Some(TextField::Title),
+            _ => None,
+        };
+        if let Some(field) = text_field {
+            return match operator {
+                "=" => Ok(Rule::Is { field, value }),
+                "!=" => Ok(Rule::IsNot { field, value }),
+                " contains " => Ok(Rule::Contains { field, value }),
+                _ => Err(format!("{} can only be compared with =, != or contains: {}", field_name(field), line)),
+            };
+        }
+
+        let comparison = match operator {
+            "=" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            "<=" => Comparison::LessOrEqual,
+            ">" => Comparison::Greater,
+            ">=" => Comparison::GreaterOrEqual,
+            _ => return Err(format!("Numbers can't contain anything: {}", line)),
+        };
+        let number: usize = value.parse().map_err(|_| format!("Not a number: {}", line))?;
+        match field {
+            "rating" => Ok(Rule::Rating { comparison, stars: number }),
+            "plays" => Ok(Rule::Plays { comparison, count: number }),
+            _ => Err(format!("Unknown field {:?}: {}", field, line)),
+        }
+    }
+}
+
+fn field_name(field: TextField) -> &'static str {
+    match field {
+        TextField::Genre => "genre",
+        TextField::Artist => "artist",
+        TextField::Album => "album",
+        TextField::Title => "title",
+    }
+}
+
+/// Which of the matching tracks make up the playlist.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Selection {
+    All,
+    /// The first so many, in the order of the library.
+    First(usize),
+    /// So many picked at random, anew whenever the library changes.
+    Random(usize),
+}
+
+/// A playlist made of the tracks that match its rules, rather than of the
+/// files of a folder.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SmartPlaylist {
+    pub title: String,
+    rules: Vec<Rule>,
+    selection: Selection,
+}
+
+impl SmartPlaylist {
+    /// Reads one block of the smart playlists file: the title, then one rule
+    /// per line, and optionally `limit 50` or `random 50`.
+    fn parse(block: &str) -> Result<SmartPlaylist, String> {
+        let mut lines = block.lines().map(str::trim).filter(|line| !line.is_empty());
+        let title = lines.next().ok_or("Smart playlist without a title")?.to_string();
+        let mut rules = Vec::new();
+        let mut selection = Selection::All;
+        for line in lines {
+            let lowercase = line.to_lowercase();
+            let count = |prefix: &str| -> Option<Result<usize, String>> {
+                lowercase.strip_prefix(prefix).map(|count| {
+                    count.trim().parse().map_err(|_| format!("Not a number of tracks: {}", line))
+                })
+            };
+            if let Some(count) = count("random ") {
+                selection = Selection::Random(count?);
+            } else if let Some(count) = count("limit ") {
+                selection = Selection::First(count?);
+            } else {
+                rules.push(Rule::parse(line).map_err(|error| format!("{}: {}", title, error))?);
+            }
+        }
+        Ok(SmartPlaylist { title, rules, selection })
+    }
+
+    /// The tracks of the other playlists that make up this one.
+    fn evaluate(&self, library: &Library, play_counts: &HashMap<String, usize>, now: SystemTime) -> Vec<Track> {
+        let mut tracks: Vec<Track> = Vec::new();
+        let mut locations: HashSet<String> = HashSet::new();
+        for track in library.playlists.iter().filter(|playlist| !playlist.smart).flat_map(|playlist| &playlist.tracks) {
+            // Grouping by tags can't put a track in two playlists, but the
+            // same file can be in two library roots that overlap.
+            let matches = self.rules.iter().all(|rule| rule.matches(track, library, play_counts, now));
+            if matches && locations.insert(track.location()) {
+                tracks.push(track.clone());
+            }
+        }
+        match self.selection {
+            Selection::All => {}
+            Selection::First(count) => tracks.truncate(count),
+            Selection::Random(count) => {
+                // Every `RandomState` hashes differently, which makes a
+                // shuffle without a dependency for it.
+                let random = RandomState::new();
+                let mut keyed: Vec<(u64, Track)> = tracks
+                    .into_iter()
+                    .enumerate()
+                    .map(|(position, track)| (random.hash_one(position), track))
+                    .collect();
+                keyed.sort_by_key(|(key, _)| *key);
+                tracks = keyed.into_iter().take(count).map(|(_, track)| track).collect();
+            }
+        }
+        tracks
+    }
+}
+
+/// Reads the smart playlists from a file of blocks separated by blank lines,
+/// like `streams.txt`. A block that can't be read is left out, and the rest
+/// still count.
+pub fn read_smart_playlists(file: &Path) -> Vec<SmartPlaylist> {
+    let content = match fs::read_to_string(file) {
+        Ok(content) => content,
+        Err(error) => {
+            println!("Could not read smart playlists from {:?}: {}", file, error);
+            return Vec::new();
+        }
+    };
+    parse_smart_playlists(&content)
+}
+
+fn parse_smart_playlists(content: &str) -> Vec<SmartPlaylist> {
+    content
+        .split("\n\n")
+        .filter(|block| !block.trim().is_empty())
+        .filter_map(|block| match SmartPlaylist::parse(block) {
+            Ok(playlist) => {
+                println!("Smart playlist {} found", playlist.title);
+                Some(playlist)
+            }
+            Err(error) => {
+                println!("Skipping a smart playlist: {}", error);
+                None
+            }
+        })
+        .collect()
+}
+
+impl Library {
+    /// Evaluates the smart playlists again, in place of what they were. Done
+    /// whenever the library changes, and cheap enough for that: rules only
+    /// look at what the scan already read. Every smart playlist stays in the
+    /// library even when nothing matches, so the keys of the others don't
+    /// shift around as tracks come and go.
+    pub fn refresh_smart_playlists(&mut self, play_counts: &HashMap<String, usize>) {
+        for playlist in self.playlists.iter().filter(|playlist| playlist.smart) {
+            self.search_index.remove_playlist(playlist.id);
+        }
+        self.playlists.retain(|playlist| !playlist.smart);
+        if self.smart_playlists.is_empty() {
+            return;
+        }
+        let now = SystemTime::now();
+        let evaluated: Vec<Playlist> = self.smart_playlists
+            .iter()
+            .filter(|smart| {
+                let taken = self.playlists.iter().any(|playlist| playlist.title == smart.title);
+                if taken {
+                    println!("Smart playlist {} has the title of a folder playlist, skipping it", smart.title);
+                }
+                !taken
+            })
+            .map(|smart| Playlist {
+                id: 0,
+                title: smart.title.clone(),
+                tracks: smart.evaluate(self, play_counts, now),
+                cover_source: None,
+                smart: true,
+            })
+            .collect();
+        for playlist in evaluated {
+            self.insert_playlist(playlist);
+        }
+    }
+
+    /// Refuses to change the files behind a smart playlist through it:
+    /// deleting one would take every track it picked out of its own folder.
+    pub fn ensure_not_smart(&self, index: usize) -> Result<(), String> {
+        if self.playlists[index].smart {
+            return Err(format!("{} is a smart playlist, made of the tracks of others", self.playlists[index].title));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::library::{RatingChange, ScanConfig, TagChanges};
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn track(name: &str, artist: &str, genre: &str, age_days: u64) -> Track {
+        Track {
+            filename: PathBuf::from(format!("/music/{}.mp3", name)),
+            artist: Some(artist.to_string()),
+            title: Some(name.to_string()),
+            has_cover_art: false,
+            start: None,
+            end: None,
+            album: None,
+            album_artist: None,
+            disc_number: None,
+            track_number: None,
+            track_gain: None,
+            album_gain: None,
+            genre: Some(genre.to_string()),
+            added: SystemTime::now().checked_sub(DAY * age_days as u32),
+        }
+    }
+
+    fn library(smart: &str) -> Library {
+        let mut library = Library::empty(Vec::new(), ScanConfig::default());
+        library.insert_playlist(Playlist {
+            id: 0,
+            title: "Kind of Blue".to_string(),
+            tracks: vec![
+                track("So What", "Miles Davis", "Jazz", 400),
+                track("Freddie Freeloader", "Miles Davis", "jazz ", 2),
+            ],
+            cover_source: None,
+            smart: false,
+        });
+        library.insert_playlist(Playlist {
+            id: 0,
+            title: "Nevermind".to_string(),
+            tracks: vec![track("Lithium", "Nirvana", "Grunge", 10)],
+            cover_source: None,
+            smart: false,
+        });
+        library.smart_playlists = parse_smart_playlists(smart);
+        library
+    }
+
+    fn titles(library: &Library, playlist: &str) -> Vec<String> {
+        library.playlists
+            .iter()
+            .find(|candidate| candidate.title == playlist)
+            .unwrap()
+            .tracks
+            .iter()
+            .map(Track::display_title)
+            .collect()
+    }
+
+    #[test]
+    fn reads_rules_and_skips_what_it_cant() {
+        let playlists = parse_smart_playlists(
+            "Jazz\ngenre = Jazz\n\nBest\nrating >= 4\nlimit 10\n\nBroken\nmood = happy\n\nNew\nadded in last 30 days\nartist contains Miles\nrandom 5\n",
+        );
+        assert_eq!(playlists.len(), 3);
+        assert_eq!(
+            playlists[1],
+            SmartPlaylist {
+                title: "Best".to_string(),
+                rules: vec![Rule::Rating { comparison: Comparison::GreaterOrEqual, stars: 4 }],
+                selection: Selection::First(10),
+            }
+        );
+        assert_eq!(playlists[2].rules, vec![
+            Rule::AddedWithin(DAY * 30),
+            Rule::Contains { field: TextField::Artist, value: "miles".to_string() },
+        ]);
+        assert_eq!(playlists[2].selection, Selection::Random(5));
+        assert!(Rule::parse("rating contains 4").is_err());
+        assert!(Rule::parse("genre > Jazz").is_err());
+        assert!(Rule::parse("added in last many days").is_err());
+    }
+
+    #[test]
+    fn smart_playlists_follow_the_library_and_the_history() {
+        let mut library = library(
+            "Jazz\ngenre = jazz\n\nFresh\nadded in last 30 days\n\nUnheard\nnever played\n\nTop\nrating >= 4\n\nMiles\nartist = Miles Davis\nrandom 1\n",
+        );
+        let mut play_counts = HashMap::new();
+        play_counts.insert("/music/So What.mp3".to_string(), 3);
+        library.refresh_smart_playlists(&play_counts);
+
+        assert_eq!(library.playlists.len(), 7);
+        assert_eq!(titles(&library, "Jazz"), vec!["So What", "Freddie Freeloader"]);
+        assert_eq!(titles(&library, "Fresh"), vec!["Freddie Freeloader", "Lithium"]);
+        assert_eq!(titles(&library, "Unheard"), vec!["Freddie Freeloader", "Lithium"]);
+        assert!(titles(&library, "Top").is_empty());
+        assert_eq!(titles(&library, "Miles").len(), 1);
+        let jazz = library.playlists.iter().position(|playlist| playlist.title == "Jazz").unwrap();
+        assert!(library.ensure_not_smart(jazz).is_err());
+        // the tags of its tracks are written through the playlists they are from
+        let changes = TagChanges { album: Some("Jazz".to_string()), ..TagChanges::default() };
+        assert!(library.write_playlist_tags(jazz, &changes).is_err());
+        assert!(library.tag_playlist_from_filenames(jazz, "%a - %t").is_err());
+        assert!(library.write_covers(jazz, &[0], b"cover", "image/png").is_err());
+
+        // rated, and evaluated again rather than piling up
+        let nevermind = library.playlists.iter().position(|playlist| playlist.title == "Nevermind").unwrap();
+        library.rate_track(nevermind, 0, &RatingChange { stars: Some(5), ..RatingChange::default() }).unwrap();
+        library.refresh_smart_playlists(&play_counts);
+        assert_eq!(library.playlists.len(), 7);
+        assert_eq!(titles(&library, "Top"), vec!["Lithium"]);
+        // copies in smart playlists are neither rated nor favourites twice
+        assert_eq!(library.track_position("/music/Lithium.mp3"), Some((nevermind, 0)));
+        library.rate_track(nevermind, 0, &RatingChange { favourite: Some(true), ..RatingChange::default() }).unwrap();
+        assert_eq!(library.favourites().tracks.len(), 1);
+    }
+}
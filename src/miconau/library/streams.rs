@@ -0,0 +1,375 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::{fs, path::Path, time::Duration};
+use super::{Library, Stream};
+
+/// How long a health check waits for a station to answer.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Writes streams in the format `read_streams` reads: one block per stream of
+/// name, URL, logo file and fallback URLs, separated by blank lines. The file
+/// is replaced in one go, so a crash halfway through never leaves half a list
+/// behind.
+pub fn write_streams(streams_folder: &Path, streams: &[Stream]) -> Result<(), String> {
+    let content: Vec<String> = streams
+        .iter()
+        .map(|stream| {
+            let mut block = format!("{}\n{}\n", stream.name, stream.url);
+            if let Some(logo_file) = &stream.logo_file {
+                block.push_str(&format!("{}\n", logo_file));
+            }
+            for url in &stream.fallback_urls {
+                block.push_str(&format!("{}\n", url));
+            }
+            block
+        })
+        .collect();
+    let streams_file = streams_folder.join("streams.txt");
+    let temporary = streams_folder.join(".streams.txt.tmp");
+    fs::create_dir_all(streams_folder)
+        .and_then(|_| fs::write(&temporary, content.join("\n")))
+        .and_then(|_| fs::rename(&temporary, &streams_file))
+        .map_err(|error| format!("Could not write {:?}: {}", streams_file, error))
+}
+
+/// Checks a name or URL before it goes into `streams.txt`. Lines are what
+/// separates the parts of a stream there, so neither may span more than one.
+fn check_line(value: &str, what: &str) -> Result<String, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err(format!("The {} of a stream can't be empty", what));
+    }
+    if value.contains(['\n', '\r']) {
+        return Err(format!("The {} of a stream has to be a single line", what));
+    }
+    Ok(value.to_string())
+}
+
+/// Checks a URL like `check_line`. `read_streams` tells fallback URLs from
+/// the logo file by their `://`, so a URL without a scheme would come back
+/// as a logo.
+fn check_url(url: &str) -> Result<String, String> {
+    let url = check_line(url, "URL")?;
+    if !url.contains("://") {
+        return Err(format!("{} is not a URL; it needs a scheme such as http://", url));
+    }
+    Ok(url)
+}
+
+fn check_urls(urls: &[String]) -> Result<Vec<String>, String> {
+    urls.iter().map(|url| check_url(url)).collect()
+}
+
+/// Whether a station answers at a URL. Only the response headers are read:
+/// the body of a stream never ends.
+pub fn probe_stream(url: &str) -> bool {
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(PROBE_TIMEOUT)
+        .timeout_read(PROBE_TIMEOUT)
+        .build();
+    match agent.get(url).call() {
+        Ok(_) => true,
+        Err(ureq::Error::Transport(error)) if error.kind() == ureq::ErrorKind::BadStatus => match probe_icy(url) {
+            Ok(()) => true,
+            Err(error) => {
+                println!("Stream {} is down: {}", url, error);
+                false
+            }
+        },
+        Err(error) => {
+            println!("Stream {} is down: {}", url, error);
+            false
+        }
+    }
+}
+
+/// Asks a SHOUTcast server by hand whether it is up. Version 1 answers with
+/// `ICY 200 OK` where HTTP has its status line, which ureq takes for a
+/// broken response. It only speaks plain HTTP.
+fn probe_icy(url: &str) -> Result<(), String> {
+    let rest = url.strip_prefix("http://").ok_or("not an ICY response")?;
+    let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    let path = if path.is_empty() { "/" } else { path };
+    let has_port = authority.rsplit_once(':').is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+    let address = if has_port { authority.to_string() } else { format!("{}:80", authority) };
+    let address = address
+        .to_socket_addrs()
+        .map_err(|error| error.to_string())?
+        .next()
+        .ok_or_else(|| format!("{} has no address", authority))?;
+
+    let mut connection = TcpStream::connect_timeout(&address, PROBE_TIMEOUT).map_err(|error| error.to_string())?;
+    connection.set_read_timeout(Some(PROBE_TIMEOUT)).map_err(|error| error.to_string())?;
+    let request = format!("GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: miconau\r\n\r\n", path, authority);
+    connection.write_all(request.as_bytes()).map_err(|error| error.to_string())?;
+
+    let mut status_line = Vec::new();
+    BufReader::new(connection.take(1024))
+        .read_until(b'\n', &mut status_line)
+        .map_err(|error| error.to_string())?;
+    let status_line = String::from_utf8_lossy(&status_line);
+    let status = status_line.strip_prefix("ICY ").and_then(|rest| rest.get(..3));
+    match status {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(format!("answered {:?}", status_line.trim_end())),
+    }
+}
+
+/// The name of the logo file of a stream: its name with everything but
+/// letters and digits turned into dashes, so it is a safe file name.
+fn logo_file_name(stream_name: &str) -> String {
+    let name: String = stream_name
+        .to_lowercase()
+        .chars()
+        .map(|character| if character.is_alphanumeric() { character } else { '-' })
+        .collect();
+    let name = name.split('-').filter(|part| !part.is_empty()).collect::<Vec<_>>().join("-");
+    format!("{}.svg", if name.is_empty() { "stream" } else { &name })
+}
+
+impl Library {
+    /// Changes the streams and writes them back to `streams.txt`. The change
+    /// is made to a copy first and only kept once it is on disk, so the list
+    /// the player has never differs from the file.
+    fn change_streams(&mut self, change: impl FnOnce(&mut Vec<Stream>) -> Result<(), String>) -> Result<(), String> {
+        let streams_folder = self.streams_folder
+            .clone()
+            .ok_or("No streams folder is configured; start with --streams-folder to manage streams")?;
+        let mut streams = self.streams.clone();
+        change(&mut streams)?;
+        if let Some((index, _)) = streams
+            .iter()
+            .enumerate()
+            .find(|(index, stream)| streams[..*index].iter().any(|other| other.name == stream.name))
+        {
+            // Logos are looked up by name.
+            return Err(format!("There is already a stream called {}", streams[index].name));
+        }
+        write_streams(&streams_folder, &streams)?;
+        self.streams = streams;
+        Ok(())
+    }
+
+    /// Adds a stream after the others and returns its index.
+    pub fn add_stream(&mut self, name: &str, url: &str, fallback_urls: &[String]) -> Result<usize, String> {
+        let stream = Stream {
+            name: check_line(name, "name")?,
+            url: check_url(url)?,
+            logo_svg: None,
+            logo_file: None,
+            fallback_urls: check_urls(fallback_urls)?,
+            up: None,
+        };
+        self.change_streams(|streams| {
+            streams.push(stream);
+            Ok(())
+        })?;
+        Ok(self.streams.len() - 1)
+    }
+
+    /// Changes the name and URLs of a stream.
+    pub fn update_stream(&mut self, index: usize, name: &str, url: &str, fallback_urls: &[String]) -> Result<(), String> {
+        let name = check_line(name, "name")?;
+        let url = check_url(url)?;
+        let fallback_urls = check_urls(fallback_urls)?;
+        self.change_streams(|streams| {
+            let stream = streams.get_mut(index).ok_or(format!("Stream {} not found", index))?;
+            if stream.url != url || stream.fallback_urls != fallback_urls {
+                stream.up = None;
+            }
+            stream.name = name;
+            stream.url = url;
+            stream.fallback_urls = fallback_urls;
+            Ok(())
+        })
+    }
+
+    /// Records the outcome of a health check. Streams are matched by URL, as
+    /// they may have been changed or moved while the check ran. Returns
+    /// whether anything changed.
+    pub fn set_stream_up(&mut self, url: &str, up: bool) -> bool {
+        let mut changed = false;
+        for stream in self.streams.iter_mut().filter(|stream| stream.url == url) {
+            changed |= stream.up != Some(up);
+            stream.up = Some(up);
+        }
+        changed
+    }
+
+    /// Removes a stream. Its logo stays in `logos/`, where another stream may
+    /// be using it too.
+    pub fn delete_stream(&mut self, index: usize) -> Result<(), String> {
+        self.change_streams(|streams| {
+            if index >= streams.len() {
+                return Err(format!("Stream {} not found", index));
+            }
+            streams.remove(index);
+            Ok(())
+        })
+    }
+
+    /// Moves a stream to another place in the list. Streams are on the white
+    /// keys in the order of the list, so this is how a station gets another
+    /// key.
+    pub fn move_stream(&mut self, from: usize, to: usize) -> Result<(), String> {
+        self.change_streams(|streams| {
+            if from >= streams.len() || to >= streams.len() {
+                return Err(format!("Stream {} not found", from.max(to)));
+            }
+            let stream = streams.remove(from);
+            streams.insert(to, stream);
+            Ok(())
+        })
+    }
+
+    /// A logo file name for a stream that no other stream uses yet. Names
+    /// that differ only in case or punctuation make the same file name, so
+    /// later ones get a number.
+    fn unused_logo_file_name(&self, stream_name: &str) -> String {
+        let name = logo_file_name(stream_name);
+        let used = |file: &str| self.streams.iter().any(|stream| stream.logo_file.as_deref() == Some(file));
+        if !used(&name) {
+            return name;
+        }
+        let stem = name.trim_end_matches(".svg");
+        (2..)
+            .map(|number| format!("{}-{}.svg", stem, number))
+            .find(|file| !used(file))
+            .unwrap()
+    }
+
+    /// Stores an SVG as the logo of a stream, in `logos/` under a name made
+    /// from the stream's, and points the stream at it.
+    pub fn set_stream_logo(&mut self, index: usize, svg: &str) -> Result<(), String> {
+        if !svg.contains("<svg") {
+            return Err("The logo has to be an SVG image".to_string());
+        }
+        let stream = self.streams.get(index).ok_or(format!("Stream {} not found", index))?;
+        let logo_file = match &stream.logo_file {
+            Some(logo_file) => logo_file.clone(),
+            None => self.unused_logo_file_name(&stream.name),
+        };
+        let logos_folder = self.streams_folder
+            .as_ref()
+            .ok_or("No streams folder is configured; start with --streams-folder to manage streams")?
+            .join("logos");
+        let logo_path = logos_folder.join(&logo_file);
+        fs::create_dir_all(&logos_folder)
+            .and_then(|_| fs::write(&logo_path, svg))
+            .map_err(|error| format!("Could not write {:?}: {}", logo_path, error))?;
+        self.change_streams(|streams| {
+            streams[index].logo_file = Some(logo_file);
+            streams[index].logo_svg = Some(svg.to_string());
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::{read_streams, ScanConfig};
+    use crate::utils::TempDir;
+
+    fn library(folder: &TempDir) -> Library {
+        let mut library = Library::empty(Vec::new(), ScanConfig::default());
+        library.streams = read_streams(folder.path.to_str().unwrap());
+        library.streams_folder = Some(folder.path.clone());
+        library
+    }
+
+    fn names_on_disk(folder: &TempDir) -> Vec<String> {
+        read_streams(folder.path.to_str().unwrap())
+            .into_iter()
+            .map(|stream| stream.name)
+            .collect()
+    }
+
+    #[test]
+    fn changes_to_streams_are_written_back() {
+        let folder = TempDir::new("manage-streams");
+        fs::create_dir_all(folder.path.join("logos")).unwrap();
+        fs::write(folder.path.join("logos/a.svg"), "<svg/>").unwrap();
+        fs::write(
+            folder.path.join("streams.txt"),
+            "A\nhttp://example.com/a\na.svg\n\nB\nhttp://example.com/b",
+        ).unwrap();
+        let mut library = library(&folder);
+
+        assert_eq!(library.add_stream("C Radio", "http://example.com/c", &[]).unwrap(), 2);
+        library.move_stream(2, 0).unwrap();
+        let fallbacks = vec!["http://backup.example.com/bee".to_string()];
+        library.update_stream(2, "Bee", "http://example.com/bee", &fallbacks).unwrap();
+        library.delete_stream(1).unwrap();
+        library.set_stream_logo(0, "<svg id=\"c\"/>").unwrap();
+
+        assert_eq!(names_on_disk(&folder), vec!["C Radio", "Bee"]);
+        let streams = read_streams(folder.path.to_str().unwrap());
+        assert_eq!(streams[0].logo_svg.as_deref(), Some("<svg id=\"c\"/>"));
+        assert_eq!(streams[0].logo_file.as_deref(), Some("c-radio.svg"));
+        assert_eq!(streams[1].url, "http://example.com/bee");
+        assert_eq!(streams[1].fallback_urls, fallbacks);
+        assert_eq!(library.streams.len(), 2);
+
+        library.add_stream("c-radio", "http://example.com/c2", &[]).unwrap();
+        library.set_stream_logo(2, "<svg id=\"c2\"/>").unwrap();
+        let streams = read_streams(folder.path.to_str().unwrap());
+        assert_eq!(streams[0].logo_svg.as_deref(), Some("<svg id=\"c\"/>"));
+        assert_eq!(streams[2].logo_file.as_deref(), Some("c-radio-2.svg"));
+        assert_eq!(streams[2].logo_svg.as_deref(), Some("<svg id=\"c2\"/>"));
+    }
+
+    #[test]
+    fn invalid_changes_leave_the_streams_alone() {
+        let folder = TempDir::new("manage-streams-invalid");
+        fs::write(folder.path.join("streams.txt"), "A\nhttp://example.com/a").unwrap();
+        let mut library = library(&folder);
+
+        assert!(library.add_stream("A", "http://example.com/other", &[]).is_err());
+        assert!(library.add_stream("Two\nLines", "http://example.com/b", &[]).is_err());
+        assert!(library.add_stream("B", " ", &[]).is_err());
+        assert!(library.add_stream("B", "http://example.com/b", &["".to_string()]).is_err());
+        assert!(library.add_stream("B", "example.com/b", &[]).is_err());
+        assert!(library.add_stream("B", "http://example.com/b", &["radio.example.com/low".to_string()]).is_err());
+        assert!(library.update_stream(0, "A", "http://example.com/a", &["radio.example.com/low".to_string()]).is_err());
+        assert!(library.move_stream(0, 1).is_err());
+        assert!(library.set_stream_logo(0, "not an image").is_err());
+
+        assert_eq!(names_on_disk(&folder), vec!["A"]);
+        assert_eq!(library.streams.len(), 1);
+
+        library.streams_folder = None;
+        assert!(library.add_stream("B", "http://example.com/b", &[]).is_err());
+    }
+
+    /// Answers every connection with `status_line`, for as many connections
+    /// as are made.
+    fn serve(status_line: &'static str) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for mut connection in listener.incoming().flatten() {
+                let mut request = [0u8; 1024];
+                let _ = std::io::Read::read(&mut connection, &mut request);
+                let response = format!("{}\r\nContent-Type: audio/mpeg\r\n\r\n", status_line);
+                let _ = std::io::Write::write_all(&mut connection, response.as_bytes());
+            }
+        });
+        format!("http://{}/stream", address)
+    }
+
+    #[test]
+    fn probes_tell_live_stations_from_dead_ones() {
+        assert!(probe_stream(&serve("HTTP/1.1 200 OK")));
+        assert!(!probe_stream(&serve("HTTP/1.1 404 Not Found")));
+        // SHOUTcast 1 has a status line of its own
+        assert!(probe_stream(&serve("ICY 200 OK")));
+        assert!(!probe_stream(&serve("ICY 401 Service Unavailable")));
+
+        // nothing listens on a port that was just given up
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+        assert!(!probe_stream(&format!("http://{}/stream", address)));
+    }
+}
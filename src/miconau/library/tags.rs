@@ -0,0 +1,447 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use lofty::config::WriteOptions;
+use lofty::picture::{MimeType, Picture, PictureType};
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use lofty::config::ParseOptions;
+use lofty::file::FileType;
+use lofty::id3::v2::{Frame, PopularimeterFrame};
+use lofty::mpeg::MpegFile;
+use lofty::tag::{ItemKey, ItemValue, Tag, TagItem};
+use serde::{Deserialize, Serialize};
+use super::{cover::CoverSource, Library, Track};
+
+/// Tags to write to a file. A field that is None is left as it is, and an
+/// empty text removes the tag.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TagChanges {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
+}
+
+/// Who the POPM frames of miconau are from. Each player keeps its own.
+const POPULARIMETER_EMAIL: &str = "miconau";
+
+/// The description of the TXXX frame FMPS_RATING goes into in ID3v2.
+const FMPS_ID3V2_DESCRIPTION: &str = "FMPS_Rating";
+
+/// None for an empty text, which is how a tag is removed.
+fn non_empty(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// Opens the tag of a file to change it, creating one of the kind the format
+/// prefers if the file has none yet, and saves it once `change` is done.
+fn edit_tag(file: &Path, change: impl FnOnce(&mut Tag)) -> Result<(), String> {
+    let mut tagged_file = Probe::open(file)
+        .and_then(|probe| probe.read())
+        .map_err(|error| format!("Could not read {:?}: {}", file, error))?;
+    if tagged_file.primary_tag().is_none() {
+        let tag_type = tagged_file.primary_tag_type();
+        tagged_file.insert_tag(Tag::new(tag_type));
+    }
+    let tag = tagged_file.primary_tag_mut().expect("a tag was just inserted");
+    change(tag);
+    tag.save_to_path(file, WriteOptions::default())
+        .map_err(|error| format!("Could not write the tags of {:?}: {}", file, error))
+}
+
+/// Writes tags to a file.
+pub fn write_tags(file: &Path, changes: &TagChanges) -> Result<(), String> {
+    edit_tag(file, |tag| {
+        if let Some(title) = &changes.title {
+            match non_empty(title) {
+                Some(title) => tag.set_title(title),
+                None => tag.remove_title(),
+            }
+        }
+        if let Some(artist) = &changes.artist {
+            match non_empty(artist) {
+                Some(artist) => tag.set_artist(artist),
+                None => tag.remove_artist(),
+            }
+        }
+        if let Some(album) = &changes.album {
+            match non_empty(album) {
+                Some(album) => tag.set_album(album),
+                None => tag.remove_album(),
+            }
+        }
+        if let Some(track_number) = changes.track_number {
+            tag.set_track(track_number);
+        }
+    })
+}
+
+/// Embeds an image as the front cover of a file, in place of the one it had.
+pub fn write_cover(file: &Path, data: &[u8], mime_type: &str) -> Result<(), String> {
+    let picture = Picture::new_unchecked(
+        PictureType::CoverFront,
+        Some(MimeType::from_str(mime_type)),
+        None,
+        data.to_vec(),
+    );
+    edit_tag(file, |tag| {
+        tag.remove_picture_type(PictureType::CoverFront);
+        tag.push_picture(picture);
+    })
+}
+
+/// The POPM rating, from 1 to 255, that players such as Windows Media
+/// Player and foobar2000 show as the same number of stars.
+fn popularimeter_rating(stars: u8) -> u8 {
+    match stars {
+        1 => 1,
+        2 => 64,
+        3 => 128,
+        4 => 196,
+        _ => 255,
+    }
+}
+
+/// Writes a star rating from 1 to 5 to a file, or removes it for None. It
+/// goes into FMPS_RATING, as a fraction of 1, which most players on Linux
+/// read; MP3s get a POPM frame too.
+pub fn write_rating(file: &Path, stars: Option<u8>) -> Result<(), String> {
+    let file_type = Probe::open(file).ok().and_then(|probe| probe.file_type());
+    if file_type == Some(FileType::Mpeg) {
+        return write_id3v2_rating(file, stars);
+    }
+    edit_tag(file, |tag| {
+        let key = ItemKey::Unknown("FMPS_RATING".to_string());
+        tag.remove_key(&key);
+        if let Some(stars) = stars {
+            // Unknown keys are only written as they are by `insert_unchecked`.
+            tag.insert_unchecked(TagItem::new(key, ItemValue::Text(fmps_rating(stars))));
+        }
+    })
+}
+
+fn fmps_rating(stars: u8) -> String {
+    format!("{}", f64::from(stars) / 5.0)
+}
+
+/// Writes a rating to the ID3v2 tag of an MP3 itself. lofty keeps POPM
+/// frames out of the tag it reads for any format, so through that one an
+/// old rating could be added to but never taken away. The POPM frames of
+/// other players stay as they are.
+fn write_id3v2_rating(file: &Path, stars: Option<u8>) -> Result<(), String> {
+    let mut mpeg_file = fs::File::open(file)
+        .map_err(lofty::error::LoftyError::from)
+        .and_then(|mut opened| MpegFile::read_from(&mut opened, ParseOptions::new()))
+        .map_err(|error| format!("Could not read {:?}: {}", file, error))?;
+    let mut tag = mpeg_file.remove_id3v2().unwrap_or_default();
+    tag.retain(|frame| {
+        !matches!(frame, Frame::Popularimeter(popularimeter) if popularimeter.email == POPULARIMETER_EMAIL)
+    });
+    tag.remove_user_text(FMPS_ID3V2_DESCRIPTION);
+    if let Some(stars) = stars {
+        tag.insert(Frame::Popularimeter(PopularimeterFrame::new(
+            POPULARIMETER_EMAIL.to_string(),
+            popularimeter_rating(stars),
+            0,
+        )));
+        tag.insert_user_text(FMPS_ID3V2_DESCRIPTION.to_string(), fmps_rating(stars));
+    }
+    tag.save_to_path(file, WriteOptions::default())
+        .map_err(|error| format!("Could not write the tags of {:?}: {}", file, error))
+}
+
+/// Reads tags out of a file name by a pattern such as `%n - %t`: `%n` is the
+/// track number, `%t` the title, `%a` the artist and `%b` the album, `%%` a
+/// percent sign, and everything else has to be there as it is. None if the
+/// name doesn't fit the pattern.
+pub fn tags_from_filename(pattern: &str, name: &str) -> Option<TagChanges> {
+    enum Part {
+        Text(String),
+        Field(char),
+    }
+
+    let mut parts: Vec<Part> = Vec::new();
+    let mut characters = pattern.chars();
+    while let Some(character) = characters.next() {
+        let literal = match character {
+            '%' => match characters.next()? {
+                '%' => '%',
+                field @ ('n' | 't' | 'a' | 'b') => {
+                    parts.push(Part::Field(field));
+                    continue;
+                }
+                _ => return None,
+            },
+            character => character,
+        };
+        match parts.last_mut() {
+            Some(Part::Text(text)) => text.push(literal),
+            _ => parts.push(Part::Text(literal.to_string())),
+        }
+    }
+
+    // Fields take as little as they can, so in `%n - %t` the number ends at
+    // the first ` - ` and the title gets whatever dashes follow.
+    fn matches(parts: &[Part], name: &str, changes: &mut TagChanges) -> bool {
+        let Some((part, rest)) = parts.split_first() else {
+            return name.is_empty();
+        };
+        match part {
+            Part::Text(text) => name
+                .strip_prefix(text.as_str())
+                .is_some_and(|name| matches(rest, name, changes)),
+            Part::Field(field) => {
+                let ends = name
+                    .char_indices()
+                    .map(|(index, _)| index)
+                    .skip(1)
+                    .chain(std::iter::once(name.len()));
+                for end in ends {
+                    let value = name[..end].trim();
+                    if value.is_empty() {
+                        continue;
+                    }
+                    let fits = match field {
+                        'n' => value.parse::<u32>().map(|number| changes.track_number = Some(number)).is_ok(),
+                        't' => { changes.title = Some(value.to_string()); true }
+                        'a' => { changes.artist = Some(value.to_string()); true }
+                        _ => { changes.album = Some(value.to_string()); true }
+                    };
+                    if fits && matches(rest, &name[end..], changes) {
+                        return true;
+                    }
+                }
+                false
+            }
+        }
+    }
+
+    let mut changes = TagChanges::default();
+    matches(&parts, name, &mut changes).then_some(changes)
+}
+
+fn apply_to_track(track: &mut Track, changes: &TagChanges) {
+    if let Some(title) = &changes.title {
+        track.title = non_empty(title);
+    }
+    if let Some(artist) = &changes.artist {
+        track.artist = non_empty(artist);
+    }
+    if let Some(album) = &changes.album {
+        track.album = non_empty(album);
+    }
+    if let Some(track_number) = changes.track_number {
+        track.track_number = Some(track_number);
+    }
+}
+
+impl Library {
+    /// Writes tags to the file of a track and updates the track to match, so
+    /// the change shows without a scan. When grouping by tags, a changed
+    /// album only moves the track to another playlist at the next scan.
+    pub fn write_track_tags(&mut self, playlist_index: usize, track_index: usize, changes: &TagChanges) -> Result<(), String> {
+        let track = &mut self.playlists[playlist_index].tracks[track_index];
+        if track.start.is_some() {
+            return Err("The track is cut from a single file by a CUE sheet, whose tags are in the sheet".to_string());
+        }
+        write_tags(&track.filename, changes)?;
+        // Smart playlists hold copies of the track, which show the change too.
+        let file = track.filename.clone();
+        for index in 0..self.playlists.len() {
+            let mut changed = false;
+            for track in self.playlists[index].tracks.iter_mut().filter(|track| track.filename == file) {
+                apply_to_track(track, changes);
+                changed = true;
+            }
+            if changed {
+                self.reindex_playlist(index);
+            }
+        }
+        Ok(())
+    }
+
+    /// Turns down changing the tags of a whole playlist with a track of a CUE
+    /// sheet in it before any file is written, rather than at that track with
+    /// the files before it already changed.
+    fn ensure_no_cue_tracks(&self, playlist_index: usize) -> Result<(), String> {
+        if self.playlists[playlist_index].tracks.iter().any(|track| track.start.is_some()) {
+            return Err("The playlist has tracks cut from a single file by a CUE sheet, whose tags are in the sheet".to_string());
+        }
+        Ok(())
+    }
+
+    /// Writes the same tags to every track of a playlist, as for setting the
+    /// album or the artist of a whole album at once.
+    pub fn write_playlist_tags(&mut self, playlist_index: usize, changes: &TagChanges) -> Result<(), String> {
+        self.ensure_not_smart(playlist_index)?;
+        self.ensure_no_cue_tracks(playlist_index)?;
+        for track_index in 0..self.playlists[playlist_index].tracks.len() {
+            self.write_track_tags(playlist_index, track_index, changes)?;
+        }
+        Ok(())
+    }
+
+    /// Fills in the tags of every track of a playlist from its file name, by a
+    /// pattern as `tags_from_filename` takes it. Returns how many tracks had a
+    /// name that fit; the others are left alone.
+    pub fn tag_playlist_from_filenames(&mut self, playlist_index: usize, pattern: &str) -> Result<usize, String> {
+        self.ensure_not_smart(playlist_index)?;
+        self.ensure_no_cue_tracks(playlist_index)?;
+        let mut tagged = 0;
+        for track_index in 0..self.playlists[playlist_index].tracks.len() {
+            let track = &self.playlists[playlist_index].tracks[track_index];
+            let Some(stem) = track.filename.file_stem().map(|stem| stem.to_string_lossy().to_string()) else {
+                continue;
+            };
+            let Some(changes) = tags_from_filename(pattern, &stem) else {
+                continue;
+            };
+            self.write_track_tags(playlist_index, track_index, &changes)?;
+            tagged += 1;
+        }
+        Ok(tagged)
+    }
+
+    /// Embeds a cover into the files of the given tracks of a playlist. The
+    /// first track's artwork stands for the playlist, so setting it there
+    /// makes it the playlist's cover.
+    pub fn write_covers(&mut self, playlist_index: usize, track_indices: &[usize], data: &[u8], mime_type: &str) -> Result<(), String> {
+        self.ensure_not_smart(playlist_index)?;
+        let playlist = &mut self.playlists[playlist_index];
+        let mut files: Vec<PathBuf> = track_indices
+            .iter()
+            .map(|&track_index| playlist.tracks[track_index].filename.clone())
+            .collect();
+        files.dedup();
+        for file in &files {
+            write_cover(file, data, mime_type)?;
+        }
+        // Tracks cut from the same file by a CUE sheet share its artwork.
+        for track in playlist.tracks.iter_mut().filter(|track| files.contains(&track.filename)) {
+            track.has_cover_art = true;
+        }
+        if let Some(first) = playlist.tracks.first().filter(|track| track.has_cover_art) {
+            playlist.cover_source = Some(CoverSource::Embedded(first.filename.clone()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::{LibraryRoot, ScanConfig};
+    use crate::utils::TempDir;
+    use lofty::id3::v2::FrameId;
+
+    #[test]
+    fn reads_tags_from_file_names() {
+        assert_eq!(
+            tags_from_filename("%n - %t", "03 - Under Pressure - Live"),
+            Some(TagChanges {
+                title: Some("Under Pressure - Live".to_string()),
+                track_number: Some(3),
+                ..TagChanges::default()
+            })
+        );
+        assert_eq!(
+            tags_from_filename("%a_%n_%t", "Queen_12_Bicycle Race"),
+            Some(TagChanges {
+                title: Some("Bicycle Race".to_string()),
+                artist: Some("Queen".to_string()),
+                track_number: Some(12),
+                ..TagChanges::default()
+            })
+        );
+        assert_eq!(
+            tags_from_filename("100%% %b", "100% Hits").and_then(|changes| changes.album),
+            Some("Hits".to_string())
+        );
+    }
+
+    #[test]
+    fn names_that_dont_fit_the_pattern_give_nothing() {
+        assert_eq!(tags_from_filename("%n - %t", "Intro"), None);
+        assert_eq!(tags_from_filename("%n - %t", "A - Intro"), None);
+        assert_eq!(tags_from_filename("%n - %t", "01 - "), None);
+        assert_eq!(tags_from_filename("%x", "Intro"), None);
+    }
+
+    #[test]
+    fn playlists_with_cue_tracks_are_left_as_they_are() {
+        let temp = TempDir::new("write-tags-cue");
+        let mut frame = vec![0xFF, 0xFB, 0x90, 0x00];
+        frame.resize(417, 0);
+        // sorts before the tracks of the sheet, so it would be written first
+        let bonus = temp.file("Album/a-bonus.mp3", frame.repeat(8));
+        temp.file("Album/album.flac", "");
+        temp.file("Album/album.cue", "FILE \"album.flac\" WAVE\nTRACK 01 AUDIO\nINDEX 01 00:00:00\n");
+        let mut library = Library::new(vec![LibraryRoot::new(temp.path.to_str().unwrap())], ScanConfig::default());
+        let before = fs::read(&bonus).unwrap();
+
+        let changes = TagChanges { album: Some("Album".to_string()), ..TagChanges::default() };
+        assert!(library.write_playlist_tags(0, &changes).is_err());
+        assert!(library.tag_playlist_from_filenames(0, "%t").is_err());
+        assert_eq!(fs::read(&bonus).unwrap(), before);
+    }
+
+    #[test]
+    fn tags_and_covers_are_written_to_the_file() {
+        let temp = TempDir::new("write-tags");
+        let folder = &temp.path;
+        let file = folder.join("01.mp3");
+        // silent MPEG frames, see the scan tests
+        let mut frame = vec![0xFF, 0xFB, 0x90, 0x00];
+        frame.resize(417, 0);
+        fs::write(&file, frame.repeat(8)).unwrap();
+
+        write_tags(&file, &TagChanges {
+            title: Some("Intro".to_string()),
+            artist: Some("The Band".to_string()),
+            track_number: Some(1),
+            ..TagChanges::default()
+        }).unwrap();
+        write_tags(&file, &TagChanges { artist: Some(String::new()), ..TagChanges::default() }).unwrap();
+        write_cover(&file, b"image", "image/png").unwrap();
+
+        let track = super::super::read_track(file.clone());
+        assert_eq!(track.title, Some("Intro".to_string()));
+        assert_eq!(track.artist, None);
+        assert_eq!(track.track_number, Some(1));
+        assert!(track.has_cover_art);
+        assert_eq!(
+            super::super::read_cover(&CoverSource::Embedded(file)),
+            Some((b"image".to_vec(), "image/png".to_string()))
+        );
+    }
+
+    #[test]
+    fn ratings_are_written_to_the_file() {
+        let temp = TempDir::new("write-rating");
+        let folder = &temp.path;
+        let file = folder.join("01.mp3");
+        let mut frame = vec![0xFF, 0xFB, 0x90, 0x00];
+        frame.resize(417, 0);
+        fs::write(&file, frame.repeat(8)).unwrap();
+
+        let read_tag = || {
+            let mpeg = MpegFile::read_from(&mut fs::File::open(&file).unwrap(), ParseOptions::new()).unwrap();
+            mpeg.id3v2().cloned().unwrap_or_default()
+        };
+        write_rating(&file, Some(4)).unwrap();
+        let tag = read_tag();
+        assert_eq!(tag.get_user_text(FMPS_ID3V2_DESCRIPTION), Some("0.8"));
+        let Some(Frame::Popularimeter(popularimeter)) = tag.get(&FrameId::Valid("POPM".into())) else {
+            panic!("no POPM frame");
+        };
+        assert_eq!(popularimeter.rating, 196);
+        assert_eq!(popularimeter.email, POPULARIMETER_EMAIL);
+
+        write_rating(&file, None).unwrap();
+        let tag = read_tag();
+        assert_eq!(tag.get_user_text(FMPS_ID3V2_DESCRIPTION), None);
+        assert!(tag.get(&FrameId::Valid("POPM".into())).is_none());
+    }
+}
@@ -0,0 +1,217 @@
+use unicode_normalization::char::decompose_compatible;
+use unicode_normalization::UnicodeNormalization;
+
+/// Whether a character is an accent that goes on the one before it, which
+/// text that is already decomposed has apart from its letter.
+pub use unicode_normalization::char::is_combining_mark;
+
+/// Folds text for matching: lowercase, without accents, compatibility forms
+/// such as ligatures, fullwidth letters and circled digits spelled out, and
+/// Greek and Cyrillic written in Latin letters. "Beyoncé" is then "beyonce"
+/// and "Мумий Тролль" is "mumiy troll", as people type them.
+///
+/// The text is composed first, so letters that are transliterated as a
+/// whole, such as й, are found even when they come decomposed. Everything
+/// else is decomposed for compatibility (NFKD) and its marks dropped.
+pub fn fold(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    for character in text.nfc().flat_map(char::to_lowercase) {
+        match transliterate(character) {
+            Some(replacement) => folded.push_str(replacement),
+            None => decompose_compatible(character, |part| {
+                for part in part.to_lowercase() {
+                    match transliterate(part) {
+                        Some(replacement) => folded.push_str(replacement),
+                        None if is_combining_mark(part) => {}
+                        None => folded.push(part),
+                    }
+                }
+            }),
+        }
+    }
+    folded
+}
+
+/// How a lowercase letter is written in Latin letters, for those that
+/// decomposing leaves as they are: other scripts, and Latin letters whose
+/// stroke or ligature is part of the letter rather than a mark on it.
+fn transliterate(character: char) -> Option<&'static str> {
+    let replacement = match character {
+        'đ' | 'ð' => "d",
+        'ħ' => "h",
+        'ı' => "i",
+        'ŀ' | 'ł' => "l",
+        'ŉ' => "n",
+        'ø' => "o",
+        'ŧ' => "t",
+        'æ' => "ae",
+        'œ' => "oe",
+        'ß' => "ss",
+        'þ' => "th",
+        // Greek
+        'α' => "a",
+        'β' => "v",
+        'γ' => "g",
+        'δ' => "d",
+        'ε' => "e",
+        'ζ' => "z",
+        'η' => "i",
+        'θ' => "th",
+        'ι' => "i",
+        'κ' => "k",
+        'λ' => "l",
+        'μ' => "m",
+        'ν' => "n",
+        'ξ' => "x",
+        'ο' => "o",
+        'π' => "p",
+        'ρ' => "r",
+        'σ' | 'ς' => "s",
+        'τ' => "t",
+        'υ' => "y",
+        'φ' => "f",
+        'χ' => "ch",
+        'ψ' => "ps",
+        'ω' => "o",
+        // Cyrillic, as Russian, Ukrainian, Belarusian and Serbian are
+        // usually written in Latin
+        'а' => "a",
+        'б' => "b",
+        'в' => "v",
+        'г' | 'ґ' => "g",
+        'д' => "d",
+        'ђ' => "dj",
+        'е' | 'ё' | 'э' => "e",
+        'є' => "ye",
+        'ж' => "zh",
+        'з' => "z",
+        'и' | 'і' => "i",
+        'ї' => "yi",
+        'й' | 'ы' => "y",
+        'ј' => "j",
+        'к' => "k",
+        'л' => "l",
+        'љ' => "lj",
+        'м' => "m",
+        'н' => "n",
+        'њ' => "nj",
+        'о' => "o",
+        'п' => "p",
+        'р' => "r",
+        'с' => "s",
+        'т' => "t",
+        'ћ' => "c",
+        'у' | 'ў' => "u",
+        'ф' => "f",
+        'х' => "kh",
+        'ц' => "ts",
+        'ч' => "ch",
+        'џ' => "dz",
+        'ш' => "sh",
+        'щ' => "shch",
+        'ъ' | 'ь' => "",
+        'ю' => "yu",
+        'я' => "ya",
+        _ => return None,
+    };
+    Some(replacement)
+}
+
+/// How many typos a word of this many characters may have and still match.
+/// Short words have none: with one, "abba" would find every four letter word
+/// that differs by a letter.
+pub fn allowed_typos(length: usize) -> usize {
+    match length {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+/// Whether a folded word is a typo or two away from another, or from as
+/// much of its start as the word is long, for a word still being typed.
+pub fn matches_with_typos(word: &str, other: &str) -> bool {
+    let length = word.chars().count();
+    let allowed = allowed_typos(length);
+    if allowed == 0 {
+        return false;
+    }
+    let start: String = other.chars().take(length).collect();
+    typo_distance(word, other, allowed).is_some() || typo_distance(word, &start, allowed).is_some()
+}
+
+/// The number of letters to add, remove, change or swap with the next one to
+/// turn one word into the other, if it is at most `max`. Stops early once a
+/// row of the table is all over `max`, as most words are nothing alike.
+pub fn typo_distance(a: &str, b: &str, max: usize) -> Option<usize> {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+    let mut before_previous: Vec<usize> = Vec::new();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut current = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            current[j] = (previous[j] + 1).min(current[j - 1] + 1).min(previous[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(before_previous[j - 2] + 1);
+            }
+        }
+        if current.iter().all(|&distance| distance > max) {
+            return None;
+        }
+        before_previous = std::mem::replace(&mut previous, current);
+    }
+    Some(previous[b.len()]).filter(|&distance| distance <= max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folds_accents_ligatures_and_other_scripts() {
+        assert_eq!(fold("Beyoncé"), "beyonce");
+        assert_eq!(fold("Sigur Rós"), "sigur ros");
+        assert_eq!(fold("Motörhead"), "motorhead");
+        assert_eq!(fold("Straße"), "strasse");
+        assert_eq!(fold("Œuvre ﬁnale"), "oeuvre finale");
+        // already decomposed: an e and an acute accent of its own
+        assert_eq!(fold("Beyonce\u{301}"), "beyonce");
+        assert_eq!(fold("ＡＢＢＡ"), "abba");
+        assert_eq!(fold("Мумий Тролль"), "mumiy troll");
+        assert_eq!(fold("Βαγγέλης"), "vaggelis");
+        assert_eq!(fold("坂本龍一"), "坂本龍一");
+    }
+
+    #[test]
+    fn folds_what_is_not_in_the_table() {
+        // letters with marks below and carons
+        assert_eq!(fold("Ṣaḥṭǧ"), "sahtg");
+        assert_eq!(fold("Ђорђе Јовановић"), "djordje jovanovic");
+        assert_eq!(fold("Љубав њена џез"), "ljubav njena dzez");
+        assert_eq!(fold("Ў"), "u");
+        // й decomposed by whoever typed it is still a letter of its own
+        assert_eq!(fold("Мумии\u{306}"), "mumiy");
+        assert_eq!(fold("ﾐｺﾅｳ"), "ミコナウ");
+        assert_eq!(fold("①²"), "12");
+        assert_eq!(fold("Song™"), "songtm");
+    }
+
+    #[test]
+    fn typos_are_counted_up_to_a_limit() {
+        assert_eq!(typo_distance("radiohead", "radiohead", 2), Some(0));
+        assert_eq!(typo_distance("radiohed", "radiohead", 2), Some(1));
+        // a swap of two letters is one typo
+        assert_eq!(typo_distance("raidohead", "radiohead", 1), Some(1));
+        assert_eq!(typo_distance("beatls", "beatles", 1), Some(1));
+        assert_eq!(typo_distance("zappa", "abba", 1), None);
+        assert_eq!(typo_distance("radio", "radiohead", 2), None);
+        assert_eq!(allowed_typos(3), 0);
+        assert_eq!(allowed_typos(7), 1);
+        assert_eq!(allowed_typos(9), 2);
+    }
+}
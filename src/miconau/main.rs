@@ -1,18 +1,24 @@
 extern crate midir;
+mod alarms;
 mod args;
 mod library;
 mod midi_listener;
 mod player;
+mod scrobbler;
 mod utils;
 mod web;
+use alarms::{spawn_alarm_scheduler, Alarms};
 use args::get_args;
-use library::Library;
+use library::{Library, LibraryRoot, Ratings, ScanConfig};
+use library::loudness::{self, LoudnessStore};
 use midi_listener::listen;
-use player::Player;
+use player::{FadeConfig, PlayHistory, Player, TICK_INTERVAL};
 use player::spawn_mpv_event_listener;
+use scrobbler::{Client, ListenQueue, Scrobbler};
 use tokio::spawn;
 use tokio::sync::Mutex;
 use std::error::Error;
+use std::path::{Path, PathBuf};
 use std::process::exit;
 use std::sync::{mpsc, Arc};
 use std::thread::{self, park};
@@ -29,16 +35,153 @@ pub enum MainThreadEvent {
 /// coalesced rather than sent per playlist.
 const SCAN_NOTIFY_INTERVAL: Duration = Duration::from_secs(2);
 
+/// How often a library root that wasn't there at startup is looked for again.
+/// Plugging in the drive it is on is then enough to get its playlists.
+const MISSING_ROOT_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How many files the loudness analysis measures between writes of its
+/// results, so little is lost when it is interrupted.
+const LOUDNESS_SAVE_INTERVAL: usize = 20;
+
+/// Measures the loudness of the files of a library root that have no
+/// ReplayGain tags, handing each result to the player as soon as it is there.
+/// Files measured on an earlier run are taken from the store of the root.
+fn analyze_root_loudness(root: &LibraryRoot, player: &Arc<Mutex<Player>>) {
+    let mut files: Vec<PathBuf> = {
+        let player = player.blocking_lock();
+        player.library.playlists
+            .iter()
+            .flat_map(|playlist| playlist.tracks.iter())
+            .filter(|track| !loudness::has_gain_tags(track))
+            .map(|track| track.filename.clone())
+            .filter(|file| file.starts_with(&root.folder))
+            .collect()
+    };
+    // Tracks of a CUE sheet share their file, which is measured once.
+    files.sort();
+    files.dedup();
+
+    let mut store = LoudnessStore::load(root);
+    let mut unsaved = 0;
+    for file in files {
+        let measured = store.get(&file).or_else(|| {
+            let measured = loudness::analyze(&file)?;
+            store.insert(&file, measured);
+            unsaved += 1;
+            Some(measured)
+        });
+        if let Some(measured) = measured {
+            player.blocking_lock().library.loudness.insert(file, measured);
+        }
+        if unsaved >= LOUDNESS_SAVE_INTERVAL {
+            unsaved = 0;
+            if let Err(error) = store.save() {
+                println!("Could not save loudness of {}: {}", root.name(), error);
+            }
+        }
+    }
+    if unsaved > 0 {
+        if let Err(error) = store.save() {
+            println!("Could not save loudness of {}: {}", root.name(), error);
+        }
+    }
+}
+
+/// Starts the loudness analysis, which takes the library roots to analyse
+/// once they are scanned. One root after the other, on a thread of its own:
+/// measuring decodes every file in full, which takes far longer than the scan
+/// and shouldn't hold up anything else, nor compete with playback for more
+/// than one core.
+fn spawn_loudness_analysis(player: Arc<Mutex<Player>>) -> mpsc::Sender<LibraryRoot> {
+    let (sender, receiver) = mpsc::channel::<LibraryRoot>();
+    thread::spawn(move || {
+        if !loudness::ffmpeg_available() {
+            println!("ffmpeg not found, loudness analysis is disabled.");
+            return;
+        }
+        for root in receiver {
+            let started = Instant::now();
+            println!("Analysing loudness of {}.", root.name());
+            analyze_root_loudness(&root, &player);
+            println!(
+                "Loudness of {} analysed after {}.",
+                root.name(),
+                format_duration(started.elapsed()),
+            );
+        }
+    });
+    sender
+}
+
+/// Checks every `interval` whether the streams answer, marking them up or
+/// down. A station whose own URL is down but that has a fallback that
+/// answers is up: pressing its key plays it. The player is only locked to
+/// copy the URLs and to store the results, never while waiting for a station.
+fn spawn_stream_checks(player: Arc<Mutex<Player>>, interval: Duration) {
+    thread::spawn(move || loop {
+        let streams: Vec<(String, Vec<String>)> = player
+            .blocking_lock()
+            .library.streams
+            .iter()
+            .map(|stream| (stream.url.clone(), stream.fallback_urls.clone()))
+            .collect();
+        for (url, fallback_urls) in streams {
+            let up = std::iter::once(&url)
+                .chain(&fallback_urls)
+                .any(|url| library::probe_stream(url));
+            let mut player = player.blocking_lock();
+            if player.library.set_stream_up(&url, up) {
+                player.notify_library_updated();
+            }
+        }
+        thread::sleep(interval);
+    });
+}
+
+/// Ticks the player for the fades and the sleep timer, which change the
+/// volume and stop playback in their own time rather than on any event.
+fn spawn_player_ticks(player: Arc<Mutex<Player>>) {
+    thread::spawn(move || loop {
+        thread::sleep(TICK_INTERVAL);
+        player.blocking_lock().tick();
+    });
+}
+
+/// Scans one library root into the player. Returns false if the root isn't
+/// available.
+fn scan_root_into_player(
+    root: &LibraryRoot,
+    scan_config: &ScanConfig,
+    player: &Arc<Mutex<Player>>,
+) -> bool {
+    let mut last_notification = Instant::now();
+    library::scan_playlists(root, scan_config, &mut |playlist| {
+        // The lock is only held for the insert, never for the file reads,
+        // so playback and the web server stay responsive throughout.
+        let mut player = player.blocking_lock();
+        player.library.insert_playlist(playlist);
+        if last_notification.elapsed() >= SCAN_NOTIFY_INTERVAL {
+            last_notification = Instant::now();
+            player.notify_library_updated();
+        }
+    })
+}
+
 /// Scans the library in the background, adding each playlist to the player as
 /// soon as it is found. Everything scanned so far is immediately playable, both
 /// by MIDI key and from the web UI, while the rest is still being read.
 ///
 /// This is a plain OS thread rather than a tokio task: the scan is long and
 /// fully blocking, and main parks its own thread when no MIDI device is found.
+///
+/// Roots that aren't available are skipped, and the thread stays around to
+/// scan them once they turn up.
 fn spawn_library_scan(
-    library_folder: String,
+    roots: Vec<LibraryRoot>,
+    scan_config: ScanConfig,
     streams_folder: Option<String>,
     player: Arc<Mutex<Player>>,
+    loudness_analysis: Option<mpsc::Sender<LibraryRoot>>,
 ) {
     thread::spawn(move || {
         // Timed from here rather than from inside the scan, so the reported
@@ -58,27 +201,48 @@ fn spawn_library_scan(
             println!("No streams folder given, playing albums only.");
         }
 
-        let mut last_notification = Instant::now();
-        library::scan_playlists(&library_folder, &mut |playlist| {
-            // The lock is only held for the insert, never for the file reads,
-            // so playback and the web server stay responsive throughout.
-            let mut player = player.blocking_lock();
-            player.library.insert_playlist(playlist);
-            if last_notification.elapsed() >= SCAN_NOTIFY_INTERVAL {
-                last_notification = Instant::now();
-                player.notify_library_updated();
+        let analyze = |root: &LibraryRoot| {
+            if let Some(loudness_analysis) = &loudness_analysis {
+                let _ = loudness_analysis.send(root.clone());
             }
-        });
+        };
 
-        let player = player.blocking_lock();
-        player.library.log_playlists();
-        player.notify_library_updated();
-        println!(
-            "Library is ready after {}: {} playlists, {} streams.",
-            format_duration(started.elapsed()),
-            player.library.playlists.len(),
-            player.library.streams.len(),
-        );
+        let mut missing_roots: Vec<LibraryRoot> = Vec::new();
+        for root in roots {
+            if scan_root_into_player(&root, &scan_config, &player) {
+                analyze(&root);
+            } else {
+                missing_roots.push(root);
+            }
+        }
+
+        {
+            let mut player = player.blocking_lock();
+            player.library.log_playlists();
+            player.notify_library_updated();
+            println!(
+                "Library is ready after {}: {} playlists, {} streams.",
+                format_duration(started.elapsed()),
+                player.library.playlists.len(),
+                player.library.streams.len(),
+            );
+        }
+
+        while !missing_roots.is_empty() {
+            thread::sleep(MISSING_ROOT_RETRY_INTERVAL);
+            missing_roots.retain(|root| {
+                if !root.is_available() {
+                    return true;
+                }
+                println!("Library root {} is available now.", root.name());
+                let scanned = scan_root_into_player(root, &scan_config, &player);
+                player.blocking_lock().notify_library_updated();
+                if scanned {
+                    analyze(root);
+                }
+                !scanned
+            });
+        }
     });
 }
 
@@ -90,40 +254,82 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // Start out with an empty library so mpv, the web server and MIDI come up
     // immediately. Scanning a large library takes minutes and would otherwise
     // block all of it.
-    let library = Library::empty(args.library_folder.clone());
+    let mut scan_config = ScanConfig::new(args.audio_extensions);
+    scan_config.grouping = args.grouping;
+    let roots: Vec<LibraryRoot> = args.library_folder
+        .iter()
+        .map(|argument| LibraryRoot::parse(argument))
+        .collect();
+    let mut library = Library::empty(roots.clone(), scan_config.clone());
+    library.streams_folder = args.streams_folder.as_ref().map(PathBuf::from);
+    let data_folder = args.data_folder.as_ref().map(PathBuf::from);
+    library.ratings = Ratings::load(data_folder.as_deref());
+    library.ratings.write_to_tags = args.write_ratings_to_tags;
+    if let Some(file) = &args.smart_playlists {
+        library.smart_playlists = library::read_smart_playlists(Path::new(file));
+    }
     let (
         main_thread_sender,
         rx
     ) = mpsc::channel::<MainThreadEvent>();
 
     let socket_path = args.mpv_socket.clone();
-    let player = Arc::new(
-        Mutex::new(
-            Player::new(library, args.output_device, args.mpv_socket).await
-        )
-    );
+    let mut player = Player::new(library, args.output_device, args.mpv_socket, args.replaygain).await;
+    player.play_history = PlayHistory::load(data_folder.as_deref());
+    if let Some(token) = &args.listenbrainz_token {
+        println!("Scrobbling to {}", args.listenbrainz_url);
+        player.scrobbler = Some(Scrobbler::spawn(
+            Client::new(&args.listenbrainz_url, token),
+            ListenQueue::load(data_folder.as_deref()),
+        ));
+    }
+    player.fades = FadeConfig {
+        source_change: Duration::from_secs_f64(args.fade.max(0.0)),
+        between_tracks: Duration::from_secs_f64(args.crossfade.max(0.0)),
+    };
+    let player = Arc::new(Mutex::new(player));
     println!("Player module initialized");
 
     // Spawn mpv event listener to sync queue when tracks advance
     spawn_mpv_event_listener(socket_path, player.clone());
 
+    let alarms = Arc::new(Mutex::new(Alarms::load(data_folder.as_deref())));
+    spawn_alarm_scheduler(player.clone(), alarms.clone());
+
     if args.address.is_some() {
         let address = args.address.unwrap();
         println!("Starting webserver on {}", address);
         // Start web server in a separate thread
         let player_for_web = player.clone();
+        let alarms_for_web = alarms.clone();
 
         spawn(async move {
             let _ = web::start_server(
                 player_for_web,
+                alarms_for_web,
                 address,
+                args.max_upload_size,
             ).await;
         });
     } else {
         println!("Web server disabled");
     }
 
-    spawn_library_scan(args.library_folder, args.streams_folder, player.clone());
+    spawn_player_ticks(player.clone());
+
+    if let Some(interval) = args.stream_check_interval {
+        spawn_stream_checks(player.clone(), Duration::from_secs(interval.max(1)));
+    }
+
+    let loudness_analysis = args.loudness_analysis
+        .then(|| spawn_loudness_analysis(player.clone()));
+    spawn_library_scan(
+        roots,
+        scan_config,
+        args.streams_folder,
+        player.clone(),
+        loudness_analysis,
+    );
 
     if args.midi_device_index.is_some() {
         println!(
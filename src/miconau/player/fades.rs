@@ -0,0 +1,141 @@
+use std::time::{Duration, Instant};
+
+/// How often a fade changes the volume. Small enough steps not to be heard
+/// as steps.
+pub const FADE_STEP: Duration = Duration::from_millis(50);
+
+/// How long fades take. Zero turns a fade off, which is the default: mpv
+/// cuts straight from one file to the next.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FadeConfig {
+    /// Fading out what plays when a key changes or stops it, and fading in
+    /// what it changes to.
+    pub source_change: Duration,
+    /// Fading out the end of a track and fading in the next one.
+    pub between_tracks: Duration,
+}
+
+/// A fade in progress. mpv plays one file at a time, so there is no real
+/// overlap between two files: the old one fades out, the new one in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fade {
+    /// Fading out the end of a track, with `remaining` to go until silence.
+    Out { remaining: Duration, duration: Duration },
+    /// Fading out what plays for a change to something else, which waits
+    /// until the fade is done.
+    Changing { started: Instant, duration: Duration },
+    /// Silent until the audio of the file just loaded starts, which for a
+    /// stream can take a while, and then fading in over `duration`.
+    Waiting { duration: Duration },
+    In { started: Instant, duration: Duration },
+}
+
+impl Fade {
+    /// How much of the volume plays at `now`, from 0 to 1.
+    pub fn factor(&self, now: Instant) -> f64 {
+        match *self {
+            Fade::Out { remaining, duration } => fade_volume(remaining, duration, 1.0),
+            Fade::Changing { started, duration } => {
+                fade_volume(duration.saturating_sub(now.saturating_duration_since(started)), duration, 1.0)
+            }
+            Fade::Waiting { .. } => 0.0,
+            Fade::In { started, duration } => {
+                fade_volume(now.saturating_duration_since(started), duration, 1.0)
+            }
+        }
+    }
+
+    pub fn is_done(&self, now: Instant) -> bool {
+        match *self {
+            Fade::In { started, duration } | Fade::Changing { started, duration } => {
+                now.saturating_duration_since(started) >= duration
+            }
+            _ => false,
+        }
+    }
+}
+
+/// An alarm raising the volume from silence, driven by the player's ticks
+/// like the fades, so that anything else done to playback can stop it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VolumeRamp {
+    pub started: Instant,
+    pub duration: Duration,
+}
+
+impl VolumeRamp {
+    /// The volume at `now`, rising to `full`.
+    pub fn volume(&self, now: Instant, full: f64) -> f64 {
+        fade_volume(now.saturating_duration_since(self.started), self.duration, full)
+    }
+
+    pub fn is_done(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.started) >= self.duration
+    }
+}
+
+/// The volume `from_silence` away from the silent end of a fade that takes
+/// `fade`, rising linearly to `full`. Works both ways: for a fade out it is
+/// the time left, for a fade in the time gone.
+pub fn fade_volume(from_silence: Duration, fade: Duration, full: f64) -> f64 {
+    if fade.is_zero() || from_silence >= fade {
+        return full;
+    }
+    full * from_silence.as_secs_f64() / fade.as_secs_f64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fades_out_over_the_last_part() {
+        let fade = Duration::from_secs(30);
+        assert_eq!(fade_volume(Duration::from_secs(600), fade, 100.0), 100.0);
+        assert_eq!(fade_volume(Duration::from_secs(30), fade, 100.0), 100.0);
+        assert_eq!(fade_volume(Duration::from_secs(15), fade, 100.0), 50.0);
+        assert_eq!(fade_volume(Duration::ZERO, fade, 100.0), 0.0);
+        // no fade at all plays at full volume to the end
+        assert_eq!(fade_volume(Duration::ZERO, Duration::ZERO, 100.0), 100.0);
+    }
+
+    #[test]
+    fn fades_in_once_the_audio_starts() {
+        let duration = Duration::from_secs(2);
+        let started = Instant::now();
+        assert_eq!(Fade::Waiting { duration }.factor(started), 0.0);
+
+        let fade = Fade::In { started, duration };
+        assert_eq!(fade.factor(started), 0.0);
+        assert_eq!(fade.factor(started + Duration::from_secs(1)), 0.5);
+        assert!(!fade.is_done(started + Duration::from_secs(1)));
+        assert_eq!(fade.factor(started + Duration::from_secs(3)), 1.0);
+        assert!(fade.is_done(started + Duration::from_secs(3)));
+
+        let fade = Fade::Out { remaining: Duration::from_millis(500), duration };
+        assert_eq!(fade.factor(started), 0.25);
+        assert!(!fade.is_done(started));
+    }
+
+    #[test]
+    fn fades_out_for_a_change_over_its_duration() {
+        let started = Instant::now();
+        let fade = Fade::Changing { started, duration: Duration::from_secs(2) };
+        assert_eq!(fade.factor(started), 1.0);
+        assert_eq!(fade.factor(started + Duration::from_millis(1500)), 0.25);
+        assert!(!fade.is_done(started + Duration::from_millis(1500)));
+        assert_eq!(fade.factor(started + Duration::from_secs(3)), 0.0);
+        assert!(fade.is_done(started + Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn ramps_rise_to_full_volume() {
+        let started = Instant::now();
+        let ramp = VolumeRamp { started, duration: Duration::from_secs(60) };
+        assert_eq!(ramp.volume(started, 100.0), 0.0);
+        assert_eq!(ramp.volume(started + Duration::from_secs(15), 100.0), 25.0);
+        assert!(!ramp.is_done(started + Duration::from_secs(15)));
+        assert_eq!(ramp.volume(started + Duration::from_secs(61), 100.0), 100.0);
+        assert!(ramp.is_done(started + Duration::from_secs(61)));
+    }
+}
@@ -1,13 +1,32 @@
+mod fades;
+mod mpv_events;
 mod mpv_process;
-
+mod play_history;
+mod sleep_timer;
+mod stream_history;
+
+pub use fades::FadeConfig;
+use fades::{Fade, VolumeRamp, FADE_STEP};
+pub use play_history::{Play, PlayHistory, RecentSource};
+use play_history::{PlaySource, PlayedTrack};
+pub use sleep_timer::{SleepMode, DEFAULT_FADE};
+use sleep_timer::{SleepTimer, SleepTimerState, SLEEP_TIMER_MARGIN};
+pub use stream_history::HeardSong;
+use stream_history::StreamHistory;
+use mpv_events::{read_events, EndReason, MpvEvent};
 use mpv_process::*;
-use mpvipc::{Event, Mpv, MpvCommand, NumberChangeOptions, PlaylistAddOptions};
+use mpvipc::{Mpv, MpvCommand, NumberChangeOptions, PlaylistAddOptions};
 use tokio::sync::{broadcast};
 
-use crate::library::{Library};
+use crate::library::{Library, Rating, RatingChange, Stream, Track};
+use crate::scrobbler::{counts_as_listen, Scrobbler, TrackMetadata};
+use crate::library::loudness::{self, ReplayGainMode};
 use std::env;
+use std::io::{BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
 use std::ops::Deref;
-use std::path::Path;
+use std::time::{Duration, Instant};
 use std::process::Child;
 use serde::Serialize;
 
@@ -16,7 +35,23 @@ pub struct QueueItem {
     pub playlist_name: String,
     pub track_title: String,
     pub track_artist: Option<String>,
+    /// The location mpv plays the track from, as `Track::location` gives it.
+    /// Not always a plain path: tracks of a CUE sheet share one file.
     pub file_path: String,
+    /// Whether this is a stream, whose URL `file_path` then is and whose
+    /// name `track_title` and `playlist_name` are.
+    pub stream: bool,
+}
+
+/// A change to what plays, waiting for what plays now to fade out.
+enum SourceChange {
+    /// Tracks, the first to play right away and the rest to queue.
+    Items(QueueItem, Vec<QueueItem>),
+    Item(QueueItem),
+    Stream(Stream),
+    Stop,
+    /// A queue item, by its location, as the queue moves on while fading.
+    QueueItem(String),
 }
 
 #[derive(Serialize, Clone, Debug)]
@@ -28,26 +63,65 @@ pub enum AppEvent {
     LibraryUpdated,
     #[serde(rename = "queueUpdated")]
     QueueUpdated { queue: Vec<QueueItem> },
+    /// How far an upload has got, for the web UI to show. `total_bytes` is
+    /// the size of the whole request, when the client gave it.
+    #[serde(rename = "uploadProgress", rename_all = "camelCase")]
+    UploadProgress {
+        upload_id: String,
+        stage: UploadStage,
+        received_bytes: u64,
+        total_bytes: Option<u64>,
+    },
+    /// A file or stream could not be played: it is unreadable, corrupt, or
+    /// the connection to it failed.
+    #[serde(rename = "playbackError")]
+    PlaybackError { file: String, message: String },
 }
 
 #[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum UploadStage {
+    Receiving,
+    Unpacking,
+    Scanning,
+    Done,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
 enum PlayerMode {
     Paused,
     Playing,
+    #[default]
     Stopped,
+    /// Playback came to an end because the last file failed to play.
+    Error,
 }
 
 #[derive(Serialize, Clone, Debug)]
 enum SourceInfo {
-    Stream { stream_name: String },
+    /// `now_playing` is the song the station says it is playing, for the
+    /// stations that say.
+    Stream { stream_name: String, now_playing: Option<String> },
     Track { track_title: String, artist: Option<String>, playlist_name: String },
 }
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Clone, Debug, Default)]
 pub struct PlayerState {
     source_info: Option<SourceInfo>,
     mode: PlayerMode,
+    /// The sleep timer, if one is set. Filled in by `set_state` from the
+    /// player's own timer, so a state never reports a stale one.
+    sleep_timer: Option<SleepTimerState>,
+    /// The rating of the track playing, filled in by `set_state` as well.
+    rating: Option<Rating>,
 }
 
+/// How often the player is to be ticked, for the fades and the sleep timer.
+pub const TICK_INTERVAL: Duration = FADE_STEP;
+
+/// The volume mpv plays at, unless an alarm asks for less while it rises.
+/// Fades and the sleep timer play a part of it.
+pub const FULL_VOLUME: f64 = 100.0;
+
 pub struct Player {
     pub library: Library,
     mpv_process: Child,
@@ -56,6 +130,30 @@ pub struct Player {
     pub event_transmitter: broadcast::Sender<AppEvent>,
     _event_receiver: broadcast::Receiver<AppEvent>,
     pub queue: Vec<QueueItem>,
+    replaygain: ReplayGainMode,
+    /// The fallback URLs of the stream that is playing that haven't been
+    /// tried yet.
+    stream_fallbacks: Vec<String>,
+    pub stream_history: StreamHistory,
+    pub play_history: PlayHistory,
+    /// Submits what is played, when scrobbling is set up.
+    pub scrobbler: Option<Scrobbler>,
+    /// The playlist entry id and location of the file mpv started last, to
+    /// tell which file an `end-file` event is about.
+    current_entry: Option<(u64, String)>,
+    /// Whether the last file mpv finished ended in an error, which decides
+    /// whether running out of files is a plain stop or an error.
+    last_file_failed: bool,
+    sleep_timer: Option<SleepTimer>,
+    pub fades: FadeConfig,
+    fade: Option<Fade>,
+    /// What plays next once `Fade::Changing` is done.
+    pending_change: Option<SourceChange>,
+    /// The volume asked for, before fades and the sleep timer.
+    volume: f64,
+    /// The rise in volume of an alarm that is ringing. Stopping, a key, the
+    /// sleep timer or a fade out ends it at full volume.
+    volume_ramp: Option<VolumeRamp>,
 }
 
 impl Player {
@@ -63,13 +161,14 @@ impl Player {
         library: Library,
         output_device_name: Option<String>,
         socket_path: String,
+        replaygain: ReplayGainMode,
     ) -> Player {
-        let mpv_process = launch_mpv(output_device_name, socket_path.clone()).await;
+        let mpv_process = launch_mpv(output_device_name, socket_path.clone(), replaygain).await;
         println!("MPV process initialized");
 
         let mpv_controller = Mpv::connect(&socket_path).unwrap();
         mpv_controller.set_volume(
-            100.0,
+            FULL_VOLUME,
             NumberChangeOptions::Absolute,
         ).unwrap();
 
@@ -78,6 +177,7 @@ impl Player {
         let initial_state = PlayerState {
             source_info: None,
             mode: PlayerMode::Stopped,
+            ..Default::default()
         };
 
         return Player {
@@ -88,10 +188,28 @@ impl Player {
             event_transmitter,
             _event_receiver, // we need to keep the receiver to avoid dropping the channel
             queue: Vec::new(),
+            replaygain,
+            stream_fallbacks: Vec::new(),
+            stream_history: StreamHistory::default(),
+            play_history: PlayHistory::default(),
+            scrobbler: None,
+            current_entry: None,
+            last_file_failed: false,
+            sleep_timer: None,
+            fades: FadeConfig::default(),
+            fade: None,
+            pending_change: None,
+            volume: FULL_VOLUME,
+            volume_ramp: None,
         };
     }
 
-    fn set_state(&mut self, state: PlayerState) {
+    fn set_state(&mut self, mut state: PlayerState) {
+        state.sleep_timer = self.sleep_timer.as_ref().map(SleepTimer::state);
+        state.rating = match state.source_info {
+            Some(SourceInfo::Track { .. }) => self.current_location().map(|location| self.library.ratings.track(location)),
+            _ => None,
+        };
         self.state = state;
 
         match self.event_transmitter.send(AppEvent::PlayerState(self.state.clone())) {
@@ -100,7 +218,63 @@ impl Player {
         }
     }
 
-    pub fn notify_library_updated(&self) {
+    /// The location of the track playing. Until a new track has started,
+    /// which only takes a moment, this is the one before.
+    fn current_location(&self) -> Option<&str> {
+        if !matches!(self.state.source_info, Some(SourceInfo::Track { .. })) {
+            return None;
+        }
+        self.current_entry.as_ref().map(|(_, location)| location.as_str())
+    }
+
+    /// Sends the state again, for a change to the rating of the track
+    /// playing.
+    pub fn notify_rating_changed(&mut self) {
+        self.set_state(self.state.clone());
+    }
+
+    /// Toggles whether the track playing is a favourite.
+    pub fn love_current_track(&mut self) -> Result<Rating, String> {
+        let location = self.current_location()
+            .ok_or("No track is playing")?
+            .to_string();
+        let (playlist_index, track_index) = self.library
+            .track_position(&location)
+            .ok_or(format!("{} is not in the library", location))?;
+        let favourite = !self.library.ratings.track(&location).favourite;
+        let rating = self.library.rate_track(playlist_index, track_index, &RatingChange {
+            favourite: Some(favourite),
+            ..RatingChange::default()
+        })?;
+        println!("{} {}", if favourite { "Loved" } else { "Unloved" }, location);
+        self.notify_rating_changed();
+        self.notify_library_updated();
+        Ok(rating)
+    }
+
+    /// Writes the star ratings that are waiting to the tags of their files,
+    /// except the file playing: rewriting it could trip up mpv halfway
+    /// through. It is written once the next track starts or playback ends.
+    pub fn write_pending_rating_tags(&mut self) {
+        let playing = self.current_entry
+            .as_ref()
+            .filter(|_| matches!(self.state.mode, PlayerMode::Playing | PlayerMode::Paused))
+            .map(|(_, location)| Library::location_file(location));
+        let pending: Vec<String> = self.library.ratings
+            .pending_tags()
+            .iter()
+            .filter(|location| Some(Library::location_file(location)) != playing)
+            .cloned()
+            .collect();
+        for location in pending {
+            self.library.write_rating_tags(&location);
+        }
+    }
+
+    /// Lets the web UI know the library changed, after evaluating the smart
+    /// playlists again for the change.
+    pub fn notify_library_updated(&mut self) {
+        self.library.refresh_smart_playlists(&self.play_history.play_counts());
         match self.event_transmitter.send(AppEvent::LibraryUpdated) {
             Ok(_) => println!("Library updated notification sent"),
             Err(e) => println!("Error sending library update: {}", e),
@@ -114,56 +288,59 @@ impl Player {
     }
 
     pub fn play_playlist(&mut self, playlist_index: usize) {
-        // Bounds are checked up front so the reference to the playlist below
-        // does not keep the library borrowed while the error path needs
-        // `&mut self`. A missing playlist has no tracks, so the one check
-        // covers a bad playlist index as well.
-        let track_count = self.library.playlists
+        let items = self.library.playlists
             .get(playlist_index)
-            .map_or(0, |playlist| playlist.tracks.len());
-
-        if track_count == 0 {
+            .map(|playlist| playlist_items(&playlist.title, &playlist.tracks))
+            .unwrap_or_default();
+        if items.is_empty() {
             println!("Playlist with index {} not found. Playing error sound.", playlist_index);
+        }
+        self.play_items(items);
+    }
+
+    /// Plays the favourite tracks, like any other playlist.
+    pub fn play_favourites(&mut self) {
+        let favourites = self.library.favourites();
+        if favourites.tracks.is_empty() {
+            println!("There are no favourites. Playing error sound.");
+        }
+        self.play_items(playlist_items(&favourites.title, &favourites.tracks));
+    }
+
+    /// Plays tracks, the first right away and the rest lined up in mpv and
+    /// the queue. No tracks, from a playlist that doesn't exist or is empty,
+    /// play the error sound.
+    fn play_items(&mut self, items: Vec<QueueItem>) {
+        let mut items = items.into_iter();
+        let Some(first) = items.next() else {
             self.play_error();
             self.set_state(PlayerState {
                 source_info: None,
                 mode: PlayerMode::Stopped,
+                ..Default::default()
             });
             return;
-        }
-
-        let playlist = self.library.playlists.get(playlist_index).unwrap();
-        let playlist_name = playlist.title.clone();
-        println!("Playing playlist {}", playlist_name);
-
-        let first_track = playlist.tracks.first().unwrap();
-        let track_title = first_track.display_title();
-        let artist = first_track.artist.clone();
-        let first_path = first_track.filename.to_string_lossy().to_string();
-
+        };
+        println!("Playing playlist {}", first.playlist_name);
         // The tracks that follow, as the queue will mirror them.
-        let rest: Vec<QueueItem> = playlist.tracks
-            .iter()
-            .skip(1)
-            .map(|track| QueueItem {
-                playlist_name: playlist_name.clone(),
-                track_title: track.display_title(),
-                track_artist: track.artist.clone(),
-                file_path: track.filename.to_string_lossy().to_string(),
-            })
-            .collect();
+        let rest: Vec<QueueItem> = items.collect();
+        self.change_source(SourceChange::Items(first, rest));
+    }
 
+    fn load_items(&mut self, first: QueueItem, rest: Vec<QueueItem>) {
         // mpv is handed the tracks one by one rather than the playlist folder.
         // Given a folder, mpv enumerates it itself and plays everything it
         // considers playable, which includes the file types the scan filtered
         // out and leaves mpv's playlist out of step with `queue`.
         self.mpv_controller.run_command(
             MpvCommand::LoadFile {
-                file: first_path,
+                file: first.file_path,
                 option: PlaylistAddOptions::Replace,
             }
         ).unwrap();
 
+        self.fade_in_when_started(self.fades.source_change);
+
         for item in &rest {
             self.mpv_controller.run_command(
                 MpvCommand::LoadFile {
@@ -186,11 +363,12 @@ impl Player {
 
         self.set_state(PlayerState {
             source_info: Some(SourceInfo::Track {
-                track_title,
-                artist,
-                playlist_name,
+                track_title: first.track_title,
+                artist: first.track_artist,
+                playlist_name: first.playlist_name,
             }),
             mode: PlayerMode::Playing,
+            ..Default::default()
         });
     }
 
@@ -199,117 +377,143 @@ impl Player {
         playlist_index: usize,
         track_index: usize,
     ) {
-        // Bounds are checked up front so the reference to the playlist below
-        // does not keep the library borrowed while the error path needs
-        // `&mut self`. A missing playlist has no tracks, so the one check
-        // covers a bad playlist index as well.
-        let track_count = self.library.playlists
+        let item = self.library.playlists
             .get(playlist_index)
-            .map_or(0, |playlist| playlist.tracks.len());
-
-        if track_index < track_count {
-            let playlist = self.library.playlists
-                .get(playlist_index).unwrap();
-            let playlist_name = playlist.title.clone();
-            let track = playlist.tracks
-                .get(track_index).unwrap();
-            let track_path = &track.filename;
-            let track_title = track.title.clone().unwrap_or_else(|| {
-                track_path
-                    .file_stem()
-                    .map(|s| s.to_string_lossy().to_string())
-                    .unwrap_or_else(|| "Unknown".to_string())
+            .and_then(|playlist| {
+                playlist.tracks.get(track_index).map(|track| queue_item(&playlist.title, track))
             });
-            let artist = track.artist.clone();
-            println!("Playing track {}", track_path.clone().to_string_lossy());
-            self.mpv_controller.run_command(
-                MpvCommand::LoadFile {
-                    file: track_path.to_string_lossy().to_string(),
-                    option: PlaylistAddOptions::Replace,
-                }
-            ).unwrap();
-
-            self.mpv_controller.set_property(
-                "loop-playlist",
-                String::from("no"),
-            ).unwrap();
-
-            self.mpv_controller.set_property("pause", false)
-                .expect("Error setting pause property to false");
-
-            // Clear queue since we replaced the playlist with a single track
-            self.queue.clear();
-            self.notify_queue_updated();
-
-            self.set_state(PlayerState {
-                source_info: Some(SourceInfo::Track {
-                    track_title,
-                    artist,
-                    playlist_name,
-                }),
-                mode: PlayerMode::Playing,
-            });
-        } else {
+        if item.is_none() {
             println!(
                 "Track {} of playlist {} not found. Playing error sound.",
                 track_index, playlist_index,
             );
+        }
+        self.play_item(item);
+    }
+
+    pub fn play_favourites_track(&mut self, track_index: usize) {
+        let favourites = self.library.favourites();
+        let item = favourites.tracks.get(track_index).map(|track| queue_item(&favourites.title, track));
+        if item.is_none() {
+            println!("Favourite track {} not found. Playing error sound.", track_index);
+        }
+        self.play_item(item);
+    }
+
+    /// Plays a single track in place of everything, or the error sound for
+    /// a track that wasn't found.
+    fn play_item(&mut self, item: Option<QueueItem>) {
+        let Some(item) = item else {
             self.play_error();
             self.set_state(PlayerState {
                 source_info: None,
                 mode: PlayerMode::Stopped,
+                ..Default::default()
             });
-        }
+            return;
+        };
+        println!("Playing track {}", item.file_path);
+        self.change_source(SourceChange::Item(item));
     }
 
-    pub fn play_stream(&mut self, stream_index: usize) {
-        if stream_index < self.library.streams.len() {
-            let stream = self.library.streams.get(stream_index).unwrap();
-            println!("Playing stream {}", &stream.url);
-            self.mpv_controller.run_command(
-                MpvCommand::LoadFile {
-                    file: stream.url.clone(),
-                    option: PlaylistAddOptions::Replace,
-                }
-            ).unwrap();
+    fn load_item(&mut self, item: QueueItem) {
+        self.mpv_controller.run_command(
+            MpvCommand::LoadFile {
+                file: item.file_path,
+                option: PlaylistAddOptions::Replace,
+            }
+        ).unwrap();
+        self.fade_in_when_started(self.fades.source_change);
 
-            self.mpv_controller.set_property(
-                "loop-playlist",
-                String::from("no"),
-            ).unwrap();
+        self.mpv_controller.set_property(
+            "loop-playlist",
+            String::from("no"),
+        ).unwrap();
 
-            self.mpv_controller.set_property("pause", false)
-                .expect("Error setting pause property to false");
+        self.mpv_controller.set_property("pause", false)
+            .expect("Error setting pause property to false");
 
-            // Clear queue since we replaced the playlist with a stream
-            self.queue.clear();
-            self.notify_queue_updated();
+        // Clear queue since we replaced the playlist with a single track
+        self.queue.clear();
+        self.notify_queue_updated();
 
-            self.set_state(PlayerState {
-                source_info: Some(SourceInfo::Stream {
-                    stream_name: stream.name.clone(),
-                }),
-                mode: PlayerMode::Playing,
-            });
+        self.set_state(PlayerState {
+            source_info: Some(SourceInfo::Track {
+                track_title: item.track_title,
+                artist: item.track_artist,
+                playlist_name: item.playlist_name,
+            }),
+            mode: PlayerMode::Playing,
+            ..Default::default()
+        });
+    }
+
+    pub fn play_stream(&mut self, stream_index: usize) {
+        if stream_index < self.library.streams.len() {
+            let stream = self.library.streams[stream_index].clone();
+            println!("Playing stream {}", &stream.url);
+            self.change_source(SourceChange::Stream(stream));
         } else {
             println!("Stream with index {} not found. Playing error sound.", stream_index);
             self.play_error();
             self.set_state(PlayerState {
                 source_info: None,
                 mode: PlayerMode::Stopped,
+                ..Default::default()
             });
         }
     }
 
-    pub fn play_error(&mut self) {
+    fn load_stream(&mut self, stream: Stream) {
+        self.mpv_controller.run_command(
+            MpvCommand::LoadFile {
+                file: stream.url.clone(),
+                option: PlaylistAddOptions::Replace,
+            }
+        ).unwrap();
+        self.fade_in_when_started(self.fades.source_change);
+
+        self.mpv_controller.set_property(
+            "loop-playlist",
+            String::from("no"),
+        ).unwrap();
+
+        self.mpv_controller.set_property("pause", false)
+            .expect("Error setting pause property to false");
+
+        // Clear queue since we replaced the playlist with a stream
+        self.queue.clear();
+        self.notify_queue_updated();
+        self.stream_fallbacks = stream.fallback_urls;
+
+        self.set_state(PlayerState {
+            source_info: Some(SourceInfo::Stream {
+                stream_name: stream.name,
+                now_playing: None,
+            }),
+            mode: PlayerMode::Playing,
+            ..Default::default()
+        });
+    }
+
+    fn error_sound() -> String {
         let mut dir = env::current_exe().unwrap();
         dir.pop();
         dir.pop();
         dir.pop();
         dir.push("assets");
         dir.push("error.wav");
-        let dir_str = dir.to_string_lossy().deref().to_string();
+        dir.to_string_lossy().deref().to_string()
+    }
+
+    pub fn play_error(&mut self) {
+        let dir_str = Player::error_sound();
 
+        // The error sound is always heard, whatever was fading, and takes the
+        // place of any change that was waiting for a fade.
+        self.fade = None;
+        self.pending_change = None;
+        self.apply_volume();
         self.mpv_controller.run_command(
             MpvCommand::LoadFile {
                 file: dir_str,
@@ -322,15 +526,70 @@ impl Player {
         self.notify_queue_updated();
     }
 
+    /// Called when mpv could not play a stream, or lost it. The error sound
+    /// tells whoever pressed the key, and then the next fallback URL of the
+    /// station is tried, right behind the sound. Once there are none
+    /// left, playback stops.
+    pub fn on_stream_failed(&mut self, error: &str) {
+        let Some(SourceInfo::Stream { stream_name, .. }) = self.state.source_info.clone() else {
+            return;
+        };
+        println!("Stream {} failed: {}", stream_name, error);
+        self.play_error();
+
+        if self.stream_fallbacks.is_empty() {
+            println!("No fallback left for stream {}", stream_name);
+            self.set_state(PlayerState {
+                source_info: None,
+                mode: PlayerMode::Error,
+                ..Default::default()
+            });
+            return;
+        }
+        let url = self.stream_fallbacks.remove(0);
+        println!("Trying fallback {} for stream {}", url, stream_name);
+        let after_sound = self.mpv_queue_index(0);
+        if let Err(error) = self.insert_into_mpv_playlist(&url, after_sound) {
+            println!("Could not load fallback: {}", error);
+        }
+    }
+
+    /// Called when the title a stream sends changes. Shown as what the
+    /// station is playing, and remembered in its history.
+    pub fn on_stream_title(&mut self, title: Option<String>) {
+        let Some(SourceInfo::Stream { stream_name, now_playing }) = &self.state.source_info else {
+            return;
+        };
+        if *now_playing == title {
+            return;
+        }
+        let stream_name = stream_name.clone();
+        if let Some(title) = &title {
+            println!("Stream {} is playing {}", stream_name, title);
+            self.stream_history.record(&stream_name, title);
+        }
+        self.set_state(PlayerState {
+            source_info: Some(SourceInfo::Stream { stream_name, now_playing: title }),
+            mode: self.state.mode.clone(),
+            ..Default::default()
+        });
+    }
+
     pub fn play_pause(&mut self) {
         let is_paused: bool = self.mpv_controller.get_property("pause").unwrap();
         println!("setting is paused: {:?}", !is_paused);
         self.mpv_controller.set_property("pause", !is_paused)
             .expect("Error pausing");
+        if is_paused {
+            self.play_history.resume();
+        } else {
+            self.play_history.pause();
+        }
 
         self.set_state(PlayerState {
             source_info: self.state.source_info.clone(),
             mode: if is_paused { PlayerMode::Playing } else { PlayerMode::Paused },
+            ..Default::default()
         });
     }
 
@@ -361,6 +620,7 @@ impl Player {
                     track_title,
                     track_artist,
                     file_path,
+                    stream: false,
                 });
                 self.notify_queue_updated();
             }
@@ -383,7 +643,7 @@ impl Player {
     /// Returns None for anything the library doesn't know, such as a stream or
     /// the error sound.
     fn source_info_for_file(&self, file_path: &str) -> Option<SourceInfo> {
-        let (playlist, track) = self.library.find_track(Path::new(file_path))?;
+        let (playlist, track) = self.library.find_track(file_path)?;
         Some(SourceInfo::Track {
             track_title: track.display_title(),
             artist: track.artist.clone(),
@@ -391,67 +651,199 @@ impl Player {
         })
     }
 
+    /// Sets the gain mpv falls back to for a file without ReplayGain tags to
+    /// the one the loudness analysis found for it. mpv applies a change of
+    /// the fallback right away, so setting it as the track starts is enough.
+    /// Tracks with tags, or not analysed (yet), play at the fallback of 0 dB.
+    fn apply_loudness_fallback(&self, file_path: &str) {
+        if self.replaygain == ReplayGainMode::Off {
+            return;
+        }
+        let gain = self
+            .library
+            .find_track(file_path)
+            .and_then(|(playlist, track)| {
+                loudness::fallback_gain(self.replaygain, playlist, track, &self.library.loudness)
+            })
+            .unwrap_or(0.0);
+        if let Err(error) = self.mpv_controller.set_property("replaygain-fallback", gain) {
+            println!("Could not set the ReplayGain fallback: {:?}", error);
+        }
+    }
+
     pub fn stop(&mut self) {
+        self.change_source(SourceChange::Stop);
+    }
+
+    /// Stops without a fade, in place of any change waiting for one.
+    fn stop_now(&mut self) {
+        self.pending_change = None;
         self.mpv_controller.run_command_raw(
             "stop",
             &[&"keep-playlist"],
         ).unwrap();
+        self.fade = None;
+        self.apply_volume();
 
         self.set_state(PlayerState {
             source_info: None,
             mode: PlayerMode::Stopped,
+            ..Default::default()
         });
+        self.write_pending_rating_tags();
     }
 
-    pub fn add_to_queue(&mut self, playlist_index: usize, track_index: usize) -> Result<(), String> {
-        if playlist_index >= self.library.playlists.len() {
-            return Err("Playlist not found".to_string());
-        }
-        let playlist = &self.library.playlists[playlist_index];
-        if track_index >= playlist.tracks.len() {
-            return Err("Track not found".to_string());
+    pub fn add_to_queue(&mut self, playlist_index: usize, track_index: usize, next: bool) -> Result<(), String> {
+        let playlist = self.library.playlists.get(playlist_index).ok_or("Playlist not found")?;
+        let track = playlist.tracks.get(track_index).ok_or("Track not found")?;
+        let item = queue_item(&playlist.title, track);
+        self.enqueue(vec![item], next)
+    }
+
+    /// Queues all tracks of a playlist, in their order.
+    pub fn add_playlist_to_queue(&mut self, playlist_index: usize, next: bool) -> Result<(), String> {
+        let playlist = self.library.playlists.get(playlist_index).ok_or("Playlist not found")?;
+        let items = playlist_items(&playlist.title, &playlist.tracks);
+        self.enqueue(items, next)
+    }
+
+    /// Queues a stream. It plays until it is skipped, as a stream doesn't end.
+    pub fn add_stream_to_queue(&mut self, stream_index: usize, next: bool) -> Result<(), String> {
+        let stream = self.library.streams.get(stream_index).ok_or("Stream not found")?;
+        let item = QueueItem {
+            playlist_name: stream.name.clone(),
+            track_title: stream.name.clone(),
+            track_artist: None,
+            file_path: stream.url.clone(),
+            stream: true,
+        };
+        self.enqueue(vec![item], next)
+    }
+
+    /// Where a queue item is in mpv's playlist: the queue is what comes after
+    /// the file playing.
+    fn mpv_queue_index(&self, index: usize) -> usize {
+        let current_pos: usize = self.mpv_controller
+            .get_property("playlist-pos")
+            .unwrap_or(0);
+        current_pos + 1 + index
+    }
+
+    /// Puts a file into mpv's playlist at `index`. It is appended and then
+    /// moved there, as `loadfile` only takes an index to insert at from mpv
+    /// 0.38 on.
+    fn insert_into_mpv_playlist(&mut self, file: &str, index: usize) -> Result<(), String> {
+        self.mpv_controller.run_command(MpvCommand::LoadFile {
+            file: file.to_string(),
+            option: PlaylistAddOptions::Append,
+        }).map_err(|e| e.to_string())?;
+        let last = self.mpv_controller
+            .get_property::<usize>("playlist-count")
+            .map_err(|e| e.to_string())?
+            .saturating_sub(1);
+        if index >= last {
+            return Ok(());
         }
-        let track = &playlist.tracks[track_index];
-        let track_title = track.title.clone().unwrap_or_else(|| {
-            track.filename
-                .file_stem()
-                .map(|s| s.to_string_lossy().to_string())
-                .unwrap_or_else(|| "Unknown".to_string())
-        });
-        let track_artist = track.artist.clone();
-        let file_path = track.filename.to_string_lossy().to_string();
+        self.mpv_controller.run_command_raw(
+            "playlist-move",
+            &[&last.to_string(), &playlist_move_target(last, index).to_string()],
+        ).map_err(|e| e.to_string())
+    }
 
-        // Append to mpv's internal playlist
-        self.mpv_controller.run_command(
-            MpvCommand::LoadFile {
-                file: file_path.clone(),
-                option: PlaylistAddOptions::Append,
+    /// Adds items to the end of the queue or, for `next`, to its head, and
+    /// to mpv's playlist at the same place, so the two stay in step.
+    fn enqueue(&mut self, items: Vec<QueueItem>, next: bool) -> Result<(), String> {
+        let position = if next { 0 } else { self.queue.len() };
+        let mpv_index = self.mpv_queue_index(position);
+        for (offset, item) in items.iter().enumerate() {
+            let result = if next {
+                self.insert_into_mpv_playlist(&item.file_path, mpv_index + offset)
+            } else {
+                self.mpv_controller.run_command(
+                    MpvCommand::LoadFile {
+                        file: item.file_path.clone(),
+                        option: PlaylistAddOptions::Append,
+                    }
+                ).map_err(|e| e.to_string())
+            };
+            if let Err(e) = result {
+                // What mpv took is queued, so the queue still mirrors it.
+                self.queue.splice(position..position, items.into_iter().take(offset));
+                self.notify_queue_updated();
+                return Err(format!("Failed to add to mpv playlist: {}", e));
             }
-        ).map_err(|e| format!("Failed to append to mpv playlist: {}", e))?;
+        }
+        self.queue.splice(position..position, items);
+        self.notify_queue_updated();
+        Ok(())
+    }
 
-        // Add to our queue for UI display
-        self.queue.push(QueueItem {
-            playlist_name: playlist.title.clone(),
-            track_title,
-            track_artist,
-            file_path,
-        });
+    /// Moves a queue item to another position in the queue, and in mpv's
+    /// playlist along with it.
+    pub fn move_in_queue(&mut self, from: usize, to: usize) -> Result<(), String> {
+        if from >= self.queue.len() || to >= self.queue.len() {
+            return Err("Queue item not found".to_string());
+        }
+        if from == to {
+            return Ok(());
+        }
+        let mpv_from = self.mpv_queue_index(from);
+        let mpv_to = self.mpv_queue_index(to);
+        self.mpv_controller.run_command_raw(
+            "playlist-move",
+            &[&mpv_from.to_string(), &playlist_move_target(mpv_from, mpv_to).to_string()],
+        ).map_err(|e| format!("Failed to move in mpv playlist: {}", e))?;
+
+        let item = self.queue.remove(from);
+        self.queue.insert(to, item);
         self.notify_queue_updated();
         Ok(())
     }
 
+    /// Plays a queue item right away. The items before it are skipped: they
+    /// leave the queue, but stay in mpv's playlist behind the item, where
+    /// going back gets to them.
+    pub fn jump_to_queue_item(&mut self, index: usize) -> Result<(), String> {
+        if index >= self.queue.len() {
+            return Err("Queue item not found".to_string());
+        }
+        self.change_source(SourceChange::QueueItem(self.queue[index].file_path.clone()));
+        Ok(())
+    }
+
+    fn load_queue_item(&mut self, file_path: &str) {
+        // Gone from the queue while fading out: taken out, or played already.
+        let Some(index) = self.queue.iter().position(|item| item.file_path == file_path) else {
+            self.fade = None;
+            self.apply_volume();
+            return;
+        };
+        let mpv_index = self.mpv_queue_index(index);
+        if let Err(e) = self.mpv_controller.run_command_raw(
+            "playlist-play-index",
+            &[&mpv_index.to_string()],
+        ) {
+            println!("Failed to jump in mpv playlist: {}", e);
+            self.fade = None;
+            self.apply_volume();
+            return;
+        }
+        self.fade_in_when_started(self.fades.source_change);
+        self.mpv_controller.set_property("pause", false)
+            .expect("Error setting pause property to false");
+
+        // The item itself leaves the queue once mpv starts it, as any other.
+        self.queue.drain(..index);
+        self.notify_queue_updated();
+    }
+
     pub fn remove_from_queue(&mut self, index: usize) -> Result<(), String> {
         if index >= self.queue.len() {
             return Err("Queue item not found".to_string());
         }
         
-        // Get current playlist position to calculate the correct mpv playlist index
-        // Queue items are appended after the current playlist, so we need to offset
-        let current_pos: usize = self.mpv_controller
-            .get_property("playlist-pos")
-            .unwrap_or(0);
-        let mpv_index = current_pos + 1 + index;
-        
+        let mpv_index = self.mpv_queue_index(index);
+
         // Remove from mpv's playlist
         let _ = self.mpv_controller.run_command_raw(
             "playlist-remove",
@@ -488,6 +880,25 @@ impl Player {
         self.notify_queue_updated();
     }
 
+    /// Whether any of `paths`, files or folders, holds a file that is playing,
+    /// or lined up to play next in mpv or the queue. Files in use must not be
+    /// moved away from under mpv, which would fail the track mid-play.
+    pub fn is_in_use(&self, paths: &[PathBuf]) -> bool {
+        let mut locations: Vec<String> = self.queue
+            .iter()
+            .map(|item| item.file_path.clone())
+            .collect();
+        if !matches!(self.state.mode, PlayerMode::Stopped | PlayerMode::Error) {
+            if let Ok(playlist) = self.mpv_controller.get_playlist() {
+                locations.extend(playlist.0.into_iter().map(|entry| entry.filename));
+            }
+        }
+        locations.iter().any(|location| {
+            let file = Library::location_file(location);
+            paths.iter().any(|path| file.starts_with(path))
+        })
+    }
+
     fn notify_queue_updated(&self) {
         match self.event_transmitter.send(AppEvent::QueueUpdated { queue: self.queue.clone() }) {
             Ok(_) => println!("Queue updated notification sent"),
@@ -499,12 +910,23 @@ impl Player {
     /// in step with what mpv is actually playing, which is the file mpv
     /// reports rather than a position: going back leaves the queue where it
     /// is, so a position alone cannot tell the two directions apart.
-    pub fn on_track_started(&mut self) {
+    pub fn on_track_started(&mut self, entry_id: Option<u64>) {
         let current_file: String = match self.mpv_controller.get_property("path") {
             Ok(path) => path,
             Err(_) => return, // Can't tell what is playing, don't update state
         };
+        self.current_entry = entry_id.map(|id| (id, current_file.clone()));
+        self.apply_loudness_fallback(&current_file);
+        // The track that faded out ended, the next one fades in.
+        if matches!(self.fade, Some(Fade::Out { .. })) {
+            self.fade_in_when_started(self.fades.between_tracks);
+        }
+        self.sync_with_started_file(&current_file);
+        self.record_play_start(&current_file);
+        self.write_pending_rating_tags();
+    }
 
+    fn sync_with_started_file(&mut self, current_file: &str) {
         // Playing on: the file that started is the one at the head of the
         // queue, so it moves out of the queue and into the display.
         let plays_head_of_queue = self.queue
@@ -514,13 +936,24 @@ impl Player {
             let item = self.queue.remove(0);
             println!("Playing queued track: {} - {}", item.playlist_name, item.track_title);
 
-            self.set_state(PlayerState {
-                source_info: Some(SourceInfo::Track {
+            let source_info = if item.stream {
+                self.stream_fallbacks = self.library.streams
+                    .iter()
+                    .find(|stream| stream.url == item.file_path)
+                    .map(|stream| stream.fallback_urls.clone())
+                    .unwrap_or_default();
+                SourceInfo::Stream { stream_name: item.playlist_name, now_playing: None }
+            } else {
+                SourceInfo::Track {
                     track_title: item.track_title,
                     artist: item.track_artist,
                     playlist_name: item.playlist_name,
-                }),
+                }
+            };
+            self.set_state(PlayerState {
+                source_info: Some(source_info),
                 mode: PlayerMode::Playing,
+                ..Default::default()
             });
 
             self.notify_queue_updated();
@@ -529,15 +962,419 @@ impl Player {
 
         // Anything else - going back, or the first track of a playlist - keeps
         // the queue and takes what to display from the library.
-        if let Some(source_info) = self.source_info_for_file(&current_file) {
+        if let Some(source_info) = self.source_info_for_file(current_file) {
             self.set_state(PlayerState {
                 source_info: Some(source_info),
                 mode: PlayerMode::Playing,
+                ..Default::default()
+            });
+        }
+    }
+
+    /// Starts a play in the history for the file mpv started, which by now
+    /// is what the state shows. The error sound is no play.
+    fn record_play_start(&mut self, current_file: &str) {
+        if current_file == Player::error_sound() {
+            return;
+        }
+        let (source, track) = match &self.state.source_info {
+            Some(SourceInfo::Stream { stream_name, .. }) => {
+                (PlaySource::Stream { name: stream_name.clone() }, None)
+            }
+            Some(SourceInfo::Track { track_title, artist, playlist_name }) => (
+                PlaySource::Playlist { name: playlist_name.clone() },
+                Some(PlayedTrack {
+                    title: track_title.clone(),
+                    artist: artist.clone(),
+                    file: current_file.to_string(),
+                    duration_seconds: None,
+                }),
+            ),
+            None => return,
+        };
+        self.finish_play(false);
+        self.play_history.start(source, track);
+        if let (Some(scrobbler), Some(metadata)) = (&self.scrobbler, self.scrobble_metadata(current_file, None)) {
+            scrobbler.playing_now(metadata);
+        }
+    }
+
+    /// Records the end of the play going on in the history, and submits it
+    /// as a listen if it played long enough.
+    fn finish_play(&mut self, stopped_early: bool) {
+        let Some(play) = self.play_history.finish(stopped_early) else {
+            return;
+        };
+        let (Some(scrobbler), Some(track)) = (&self.scrobbler, &play.track) else {
+            return;
+        };
+        let duration = track.duration_seconds.map(Duration::from_secs_f64);
+        if !counts_as_listen(Duration::from_secs(play.played_seconds), duration) {
+            return;
+        }
+        if let Some(metadata) = self.scrobble_metadata(&track.file, duration) {
+            scrobbler.listen(play.started_at, metadata);
+        }
+    }
+
+    /// What to submit for a track, from its tags. Tracks without an artist
+    /// and title tag are left out: a file name is no use to anyone's stats.
+    fn scrobble_metadata(&self, location: &str, duration: Option<Duration>) -> Option<TrackMetadata> {
+        let (_, track) = self.library.find_track(location)?;
+        Some(TrackMetadata::new(
+            track.artist.clone()?,
+            track.title.clone()?,
+            track.album.clone(),
+            track.track_number,
+            duration,
+        ))
+    }
+
+    /// Called when mpv is done with a file, for whatever reason. A file that
+    /// failed is reported to the web UI. mpv goes on to the next file by
+    /// itself; a stream tries its fallbacks.
+    pub fn on_file_ended(&mut self, entry_id: Option<u64>, reason: EndReason, error: Option<String>) {
+        self.last_file_failed = reason == EndReason::Error;
+        if reason != EndReason::Error {
+            self.finish_play(reason == EndReason::Stop);
+            return;
+        }
+        self.play_history.discard();
+
+        let file = self.current_entry
+            .as_ref()
+            .filter(|(id, _)| Some(*id) == entry_id)
+            .map(|(_, file)| file.clone())
+            .unwrap_or_else(|| "unknown file".to_string());
+        let message = error.unwrap_or_else(|| "unknown error".to_string());
+        println!("Could not play {}: {}", file, message);
+        if let Err(e) = self.event_transmitter.send(AppEvent::PlaybackError {
+            file,
+            message: message.clone(),
+        }) {
+            println!("Error sending playback error: {}", e);
+        }
+
+        if matches!(self.state.source_info, Some(SourceInfo::Stream { .. })) {
+            self.on_stream_failed(&message);
+        }
+    }
+
+    /// Called when mpv has nothing left to play. Unless playback was stopped
+    /// on purpose, that is the end of the playlist, and the state follows:
+    /// stopped, or an error with the error sound if the last file failed.
+    pub fn on_idle(&mut self) {
+        // What played ran out while fading out for a change, which can be
+        // made right away.
+        if self.pending_change.is_some() {
+            self.finish_source_change();
+            return;
+        }
+        // Nothing is left to fade in.
+        if self.fade.take().is_some() {
+            self.apply_volume();
+        }
+        if !matches!(self.state.mode, PlayerMode::Playing | PlayerMode::Paused) {
+            return;
+        }
+        let mode = if self.last_file_failed {
+            self.play_error();
+            PlayerMode::Error
+        } else {
+            PlayerMode::Stopped
+        };
+        self.set_state(PlayerState {
+            source_info: None,
+            mode,
+            ..Default::default()
+        });
+        self.write_pending_rating_tags();
+    }
+
+    /// Sets a sleep timer, in place of any that is running: after `minutes`
+    /// for a set time, or at the end of the track or the playlist.
+    pub fn set_sleep_timer(&mut self, mode: SleepMode, minutes: Option<u64>, fade: Duration) -> Result<(), String> {
+        let timer = match (mode, minutes) {
+            (SleepMode::Time, Some(minutes)) if minutes > 0 => SleepTimer::after_minutes(minutes, fade),
+            (SleepMode::Time, _) => return Err("A sleep timer for a set time needs the minutes".to_string()),
+            (mode, _) => SleepTimer::at_end_of(mode, fade),
+        };
+        println!("Sleep timer set: {:?}", timer.state());
+        // The timer fades from full volume, not from where an alarm got to.
+        self.cancel_volume_ramp();
+        self.sleep_timer = Some(timer);
+        self.apply_volume();
+        self.set_state(self.state.clone());
+        Ok(())
+    }
+
+    pub fn cancel_sleep_timer(&mut self) {
+        if self.sleep_timer.take().is_some() {
+            println!("Sleep timer cancelled");
+            self.apply_volume();
+            self.set_state(self.state.clone());
+        }
+    }
+
+    /// What the sleep key does: each press sets the next longer time, and
+    /// the press after the longest turns the timer off.
+    pub fn step_sleep_timer(&mut self) {
+        match SleepTimer::next_key_step(self.sleep_timer.as_ref()) {
+            Some(minutes) => {
+                let _ = self.set_sleep_timer(SleepMode::Time, Some(minutes), DEFAULT_FADE);
+            }
+            None => self.cancel_sleep_timer(),
+        }
+    }
+
+    /// Sets the volume to play at, from 0 to `FULL_VOLUME`. Fades and the
+    /// sleep timer play a part of it.
+    fn set_volume(&mut self, volume: f64) {
+        self.volume = volume;
+        self.apply_volume();
+    }
+
+    /// Raises the volume from silence to full over `duration`, for an alarm.
+    pub fn ramp_volume(&mut self, duration: Duration) {
+        // Starting from silence, there is nothing to fade out for.
+        if matches!(self.fade, Some(Fade::Changing { .. })) {
+            self.finish_source_change();
+        }
+        self.volume_ramp = Some(VolumeRamp { started: Instant::now(), duration });
+        self.set_volume(0.0);
+    }
+
+    /// Ends the rise in volume of an alarm, if there is one, at full volume:
+    /// whoever it woke is up and doing something else with the player.
+    pub fn cancel_volume_ramp(&mut self) {
+        if self.volume_ramp.take().is_some() {
+            println!("Volume ramp cancelled");
+            self.set_volume(FULL_VOLUME);
+        }
+    }
+
+    fn tick_volume_ramp(&mut self) {
+        let Some(ramp) = self.volume_ramp else {
+            return;
+        };
+        let now = Instant::now();
+        if ramp.is_done(now) {
+            self.volume_ramp = None;
+        }
+        self.set_volume(ramp.volume(now, FULL_VOLUME));
+    }
+
+    /// Sets mpv's volume to the one asked for, as far as fades and the sleep
+    /// timer let it play right now.
+    fn apply_volume(&self) {
+        let fade = self.fade.as_ref().map_or(1.0, |fade| fade.factor(Instant::now()));
+        let sleep = self.sleep_timer.as_ref().map_or(1.0, SleepTimer::volume_factor);
+        let volume = self.volume * fade * sleep;
+        if let Err(error) = self.mpv_controller.set_volume(volume, NumberChangeOptions::Absolute) {
+            println!("Could not set the volume: {:?}", error);
+        }
+    }
+
+    /// Changes what plays once what plays now has faded out, or right away
+    /// when there is no fade or nothing playing. The ticks drive the fade,
+    /// so nothing waits for it with the player locked; a change asked for
+    /// while one is fading out takes its place, and the fade goes on.
+    fn change_source(&mut self, change: SourceChange) {
+        let duration = self.fades.source_change;
+        let fading = matches!(self.fade, Some(Fade::Changing { .. }));
+        if !fading && (duration.is_zero() || !matches!(self.state.mode, PlayerMode::Playing)) {
+            self.apply_source_change(change);
+            return;
+        }
+        if !fading {
+            self.fade = Some(Fade::Changing { started: Instant::now(), duration });
+        }
+        self.pending_change = Some(change);
+    }
+
+    /// Makes the change that waited for the fade out.
+    fn finish_source_change(&mut self) {
+        match self.pending_change.take() {
+            Some(change) => self.apply_source_change(change),
+            None => {
+                self.fade = None;
+                self.apply_volume();
+            }
+        }
+    }
+
+    fn apply_source_change(&mut self, change: SourceChange) {
+        match change {
+            SourceChange::Items(first, rest) => self.load_items(first, rest),
+            SourceChange::Item(item) => self.load_item(item),
+            SourceChange::Stream(stream) => self.load_stream(stream),
+            SourceChange::Stop => self.stop_now(),
+            SourceChange::QueueItem(file_path) => self.load_queue_item(&file_path),
+        }
+        // Silent until what comes next starts, so it starts at full volume
+        // unheard.
+        self.cancel_volume_ramp();
+    }
+
+    /// Keeps the file just loaded silent until its audio starts, then fades
+    /// it in, or plays it right away without a fade.
+    fn fade_in_when_started(&mut self, duration: Duration) {
+        self.fade = (!duration.is_zero()).then_some(Fade::Waiting { duration });
+        self.apply_volume();
+    }
+
+    /// Called when mpv starts playing audio, after loading a file or a seek.
+    /// By then mpv knows how long the track is.
+    pub fn on_playback_restart(&mut self) {
+        if self.play_history.needs_duration() {
+            if let Ok(duration) = self.mpv_controller.get_property::<f64>("duration") {
+                self.play_history.set_duration(duration);
+            }
+        }
+        if let Some(Fade::Waiting { duration }) = self.fade {
+            self.fade = Some(Fade::In { started: Instant::now(), duration });
+        }
+    }
+
+    /// Ticks the fades and the sleep timer. Called every `TICK_INTERVAL`.
+    pub fn tick(&mut self) {
+        self.tick_volume_ramp();
+        self.tick_fade();
+        self.tick_sleep_timer();
+    }
+
+    /// Moves a fade in along, and fades out the end of a track when there
+    /// is a fade between tracks. Tracks cut from one file by a CUE sheet or
+    /// of a gapless album are one continuous recording, so those play on
+    /// without one.
+    fn tick_fade(&mut self) {
+        match self.fade {
+            Some(fade @ Fade::In { .. }) => {
+                if fade.is_done(Instant::now()) {
+                    self.fade = None;
+                }
+                self.apply_volume();
+                return;
+            }
+            Some(fade @ Fade::Changing { .. }) => {
+                if fade.is_done(Instant::now()) {
+                    self.finish_source_change();
+                } else {
+                    self.apply_volume();
+                }
+                return;
+            }
+            Some(Fade::Waiting { .. }) => return,
+            Some(Fade::Out { .. }) | None => {}
+        }
+
+        let duration = self.fades.between_tracks;
+        let current_file = self.current_entry.as_ref().map(|(_, file)| Library::location_file(file));
+        let next_file = self.queue.first().map(|item| Library::location_file(&item.file_path));
+        let fades_out = !duration.is_zero()
+            && matches!(self.state.mode, PlayerMode::Playing)
+            && matches!(self.state.source_info, Some(SourceInfo::Track { .. }))
+            && next_file.is_some();
+        // Whether the album is gapless is only looked up near the end.
+        let remaining = fades_out
+            .then(|| self.mpv_controller.get_property::<f64>("time-remaining").ok())
+            .flatten()
+            .map(|seconds| Duration::from_secs_f64(seconds.max(0.0)))
+            .filter(|remaining| *remaining < duration)
+            .filter(|_| match (&current_file, &next_file) {
+                (Some(current), Some(next)) => !Library::plays_gapless(current, next),
+                _ => true,
             });
+        match remaining {
+            Some(remaining) => {
+                self.fade = Some(Fade::Out { remaining, duration });
+                self.apply_volume();
+            }
+            // Paused, skipped back, or the queue changed.
+            None if self.fade.take().is_some() => self.apply_volume(),
+            None => {}
+        }
+    }
+
+    /// Fades the volume as the end of a sleep timer comes closer, and stops
+    /// playback once it is there. The end of a track or playlist is taken
+    /// from mpv's time remaining in the track; for a playlist that is only
+    /// once its last track plays.
+    fn tick_sleep_timer(&mut self) {
+        let Some(timer) = &self.sleep_timer else {
+            return;
+        };
+        let playing = matches!(self.state.mode, PlayerMode::Playing | PlayerMode::Paused);
+        let remaining = match timer.mode {
+            SleepMode::Time => timer.remaining_at(Instant::now()),
+            _ if !playing => {
+                // Stopped some other way: there is no end to wait for.
+                self.cancel_sleep_timer();
+                return;
+            }
+            mode => (mode == SleepMode::EndOfTrack || self.queue.is_empty())
+                .then(|| self.mpv_controller.get_property::<f64>("time-remaining").ok())
+                .flatten()
+                .map(|seconds| Duration::from_secs_f64(seconds.max(0.0))),
+        };
+        // Once a minute is enough for the web UI to count down.
+        let whole_minutes = |remaining: Option<Duration>| remaining.map(|remaining| remaining.as_secs() / 60);
+        let report = whole_minutes(timer.remaining) != whole_minutes(remaining);
+        if let Some(timer) = &mut self.sleep_timer {
+            timer.remaining = remaining;
+        }
+
+        let Some(remaining) = remaining else {
+            if report {
+                self.set_state(self.state.clone());
+            }
+            return;
+        };
+        // The next track starts right where this one ends, so stop a little
+        // early rather than risk its first second. The fade is all but
+        // silent by then.
+        if remaining < SLEEP_TIMER_MARGIN {
+            println!("Sleep timer ran out, stopping playback");
+            // Stopped while the timer still holds the volume down, so it
+            // doesn't come back for a moment in between.
+            if playing {
+                self.stop_now();
+            }
+            self.sleep_timer = None;
+            self.apply_volume();
+            self.set_state(self.state.clone());
+            return;
+        }
+        if playing {
+            self.apply_volume();
+        }
+        if report {
+            self.set_state(self.state.clone());
         }
     }
 }
 
+fn queue_item(playlist_name: &str, track: &Track) -> QueueItem {
+    QueueItem {
+        playlist_name: playlist_name.to_string(),
+        track_title: track.display_title(),
+        track_artist: track.artist.clone(),
+        file_path: track.location(),
+        stream: false,
+    }
+}
+
+/// The position `playlist-move` takes to move an entry of mpv's playlist
+/// from `from` to end up at `to`. mpv puts the entry before the one at the
+/// position it is given, which for a move down is the one after `to`.
+fn playlist_move_target(from: usize, to: usize) -> usize {
+    if to > from { to + 1 } else { to }
+}
+
+fn playlist_items(playlist_name: &str, tracks: &[Track]) -> Vec<QueueItem> {
+    tracks.iter().map(|track| queue_item(playlist_name, track)).collect()
+}
+
 /// Spawns a background task that listens for mpv events and syncs the queue.
 /// This should be called after creating the Player.
 pub fn spawn_mpv_event_listener(
@@ -545,27 +1382,39 @@ pub fn spawn_mpv_event_listener(
     player: std::sync::Arc<tokio::sync::Mutex<Player>>,
 ) {
     std::thread::spawn(move || {
-        // Create a separate mpv connection for event listening
-        let mut event_mpv = match Mpv::connect(&socket_path) {
-            Ok(mpv) => mpv,
+        // A connection of its own, read line by line (see `read_events`).
+        let mut socket = match UnixStream::connect(&socket_path) {
+            Ok(socket) => socket,
             Err(e) => {
                 eprintln!("Failed to connect event listener to mpv: {}", e);
                 return;
             }
         };
-        
+        let reader = match socket.try_clone() {
+            Ok(reader) => BufReader::new(reader),
+            Err(e) => {
+                eprintln!("Failed to connect event listener to mpv: {}", e);
+                return;
+            }
+        };
+
+        // Stations send the title of the song they play as metadata.
+        if let Err(e) = socket.write_all(b"{\"command\":[\"observe_property\",1,\"metadata\"]}\n") {
+            eprintln!("Failed to observe mpv metadata: {}", e);
+        }
+
         println!("MPV event listener started");
-        
-        loop {
-            match event_mpv.event_listen() {
-                Ok(Event::StartFile) => {
+
+        read_events(reader, |event| {
+            match event {
+                MpvEvent::StartFile { entry_id } => {
                     println!("MPV: StartFile event received");
                     // A new file started - sync queue and update status
                     // Use a blocking approach with retry
                     loop {
                         match player.try_lock() {
                             Ok(mut player_guard) => {
-                                player_guard.on_track_started();
+                                player_guard.on_track_started(entry_id);
                                 break;
                             }
                             Err(_) => {
@@ -574,23 +1423,31 @@ pub fn spawn_mpv_event_listener(
                         }
                     }
                 }
-                Ok(Event::Idle) => {
+                MpvEvent::EndFile { entry_id, reason, error } => {
+                    println!("MPV: EndFile event received ({:?})", reason);
+                    player.blocking_lock().on_file_ended(entry_id, reason, error);
+                }
+                MpvEvent::StreamTitle(title) => {
+                    player.blocking_lock().on_stream_title(title);
+                }
+                MpvEvent::PlaybackRestart => {
+                    player.blocking_lock().on_playback_restart();
+                }
+                MpvEvent::Idle => {
                     println!("MPV: Idle event received");
+                    player.blocking_lock().on_idle();
                 }
-                Ok(Event::Shutdown) => {
+                MpvEvent::Shutdown => {
                     println!("MPV: Shutdown event received");
-                    break;
+                    return false;
                 }
-                Ok(_) => {
+                MpvEvent::Other => {
                     // Ignore other events
                 }
-                Err(e) => {
-                    eprintln!("MPV event listener error: {}", e);
-                    break;
-                }
             }
-        }
-        
+            true
+        });
+
         println!("MPV event listener stopped");
     });
 }
@@ -604,6 +1461,7 @@ mod tests {
         let state = PlayerState {
             source_info: None,
             mode: PlayerMode::Stopped,
+            ..Default::default()
         };
         let json = serde_json::to_string(&state).unwrap();
         assert!(json.contains("\"mode\":\"Stopped\""));
@@ -615,13 +1473,16 @@ mod tests {
         let state = PlayerState {
             source_info: Some(SourceInfo::Stream {
                 stream_name: "Test Radio".to_string(),
+                now_playing: Some("Artist - Song".to_string()),
             }),
             mode: PlayerMode::Playing,
+            ..Default::default()
         };
         let json = serde_json::to_string(&state).unwrap();
         assert!(json.contains("\"mode\":\"Playing\""));
         assert!(json.contains("\"Stream\""));
         assert!(json.contains("\"stream_name\":\"Test Radio\""));
+        assert!(json.contains("\"now_playing\":\"Artist - Song\""));
     }
 
     #[test]
@@ -633,6 +1494,7 @@ mod tests {
                 playlist_name: "My Playlist".to_string(),
             }),
             mode: PlayerMode::Playing,
+            ..Default::default()
         };
         let json = serde_json::to_string(&state).unwrap();
         assert!(json.contains("\"Track\""));
@@ -650,6 +1512,7 @@ mod tests {
                 playlist_name: "Untitled".to_string(),
             }),
             mode: PlayerMode::Paused,
+            ..Default::default()
         };
         let json = serde_json::to_string(&state).unwrap();
         assert!(json.contains("\"mode\":\"Paused\""));
@@ -665,6 +1528,7 @@ mod tests {
                 playlist_name: "Source Playlist".to_string(),
             }),
             mode: PlayerMode::Playing,
+            ..Default::default()
         };
         let json = serde_json::to_string(&state).unwrap();
         assert!(json.contains("\"Track\""));
@@ -676,11 +1540,25 @@ mod tests {
         let event = AppEvent::PlayerState(PlayerState {
             source_info: None,
             mode: PlayerMode::Stopped,
+            ..Default::default()
         });
         let json = serde_json::to_string(&event).unwrap();
         assert!(json.contains("\"type\":\"playerState\""));
     }
 
+    #[test]
+    fn app_event_playback_error_serializes_correctly() {
+        let event = AppEvent::PlaybackError {
+            file: "/music/broken.mp3".to_string(),
+            message: "unrecognized file format".to_string(),
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(
+            json,
+            "{\"type\":\"playbackError\",\"file\":\"/music/broken.mp3\",\"message\":\"unrecognized file format\"}"
+        );
+    }
+
     #[test]
     fn app_event_library_updated_serializes_correctly() {
         let event = AppEvent::LibraryUpdated;
@@ -697,6 +1575,7 @@ mod tests {
                     track_title: "Test Track".to_string(),
                     track_artist: Some("Test Artist".to_string()),
                     file_path: "/path/to/file.flac".to_string(),
+                    stream: false,
                 }
             ],
         };
@@ -713,11 +1592,21 @@ mod tests {
             track_title: "Song".to_string(),
             track_artist: None,
             file_path: "/music/song.flac".to_string(),
+            stream: false,
         };
         let json = serde_json::to_string(&item).unwrap();
         assert!(json.contains("\"playlist_name\":\"Album\""));
         assert!(json.contains("\"track_title\":\"Song\""));
         assert!(json.contains("\"track_artist\":null"));
         assert!(json.contains("\"file_path\":\"/music/song.flac\""));
+        assert!(json.contains("\"stream\":false"));
+    }
+
+    #[test]
+    fn playlist_move_targets_the_entry_after_a_move_down() {
+        // [a, b, c, d]: a to where c is gives [b, c, a, d]
+        assert_eq!(playlist_move_target(0, 2), 3);
+        // d to where b is gives [a, d, b, c]
+        assert_eq!(playlist_move_target(3, 1), 1);
     }
 }
@@ -0,0 +1,195 @@
+use std::io::{BufRead, ErrorKind};
+use serde_json::Value;
+
+/// The mpv events the player reacts to. mpvipc's own `Event` drops the
+/// details of `end-file`, such as why the file ended, and its reading panics
+/// on a line that isn't UTF-8, so the event listener reads the socket itself
+/// and parses the lines here.
+#[derive(Debug, PartialEq)]
+pub enum MpvEvent {
+    /// `entry_id` is the id mpv gave the file's playlist entry, which is
+    /// how the `EndFile` that follows is matched up with it.
+    StartFile { entry_id: Option<u64> },
+    EndFile {
+        entry_id: Option<u64>,
+        reason: EndReason,
+        /// What went wrong, when the reason is an error.
+        error: Option<String>,
+    },
+    /// The title a stream sends along with the audio (ICY `StreamTitle`)
+    /// changed. None when the file playing has no such title.
+    StreamTitle(Option<String>),
+    /// Audio started playing, after loading a file or after a seek.
+    PlaybackRestart,
+    Idle,
+    Shutdown,
+    /// Anything else, including lines that are replies rather than events.
+    Other,
+}
+
+/// Why mpv stopped playing a file.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum EndReason {
+    /// It played to the end.
+    Eof,
+    /// It was stopped, or replaced by another file.
+    Stop,
+    /// mpv is quitting.
+    Quit,
+    /// It could not be opened or decoded, or the connection was lost.
+    Error,
+    /// It was a playlist or redirect, which mpv replaced with its entries.
+    Redirect,
+    Unknown,
+}
+
+impl EndReason {
+    fn parse(reason: &str) -> EndReason {
+        match reason {
+            "eof" => EndReason::Eof,
+            "stop" => EndReason::Stop,
+            "quit" => EndReason::Quit,
+            "error" => EndReason::Error,
+            "redirect" => EndReason::Redirect,
+            _ => EndReason::Unknown,
+        }
+    }
+}
+
+/// Parses one line of mpv's IPC output.
+pub fn parse_event(line: &str) -> MpvEvent {
+    let Ok(message) = serde_json::from_str::<Value>(line) else {
+        return MpvEvent::Other;
+    };
+    match message["event"].as_str() {
+        Some("start-file") => MpvEvent::StartFile {
+            entry_id: message["playlist_entry_id"].as_u64(),
+        },
+        Some("end-file") => MpvEvent::EndFile {
+            entry_id: message["playlist_entry_id"].as_u64(),
+            reason: EndReason::parse(message["reason"].as_str().unwrap_or_default()),
+            error: message["file_error"].as_str().map(|error| error.to_string()),
+        },
+        Some("property-change") if message["name"] == "metadata" => {
+            // Key case differs between servers and mpv versions.
+            let title = message["data"]
+                .as_object()
+                .and_then(|metadata| {
+                    metadata
+                        .iter()
+                        .find(|(key, _)| key.eq_ignore_ascii_case("icy-title"))
+                })
+                .and_then(|(_, title)| title.as_str())
+                .map(|title| title.trim().to_string())
+                .filter(|title| !title.is_empty());
+            MpvEvent::StreamTitle(title)
+        }
+        Some("playback-restart") => MpvEvent::PlaybackRestart,
+        Some("idle") => MpvEvent::Idle,
+        Some("shutdown") => MpvEvent::Shutdown,
+        _ => MpvEvent::Other,
+    }
+}
+
+/// Reads mpv's events, one JSON object per line, and hands each to `handle`
+/// until it returns false, the socket closes or reading from it fails. mpv
+/// passes on text as a stream sends it, so a line may not be UTF-8; it is
+/// read lossily rather than ending the listener.
+pub fn read_events(mut reader: impl BufRead, mut handle: impl FnMut(MpvEvent) -> bool) {
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            // The socket closing: mpv has gone away.
+            Ok(0) => return,
+            Ok(_) => {}
+            Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+            Err(error) => {
+                println!("Could not read mpv's events: {}", error);
+                return;
+            }
+        }
+        if !handle(parse_event(&String::from_utf8_lossy(&line))) {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_reason_a_file_ended() {
+        assert_eq!(
+            parse_event(r#"{"event":"end-file","reason":"error","playlist_entry_id":2,"file_error":"loading failed"}"#),
+            MpvEvent::EndFile {
+                entry_id: Some(2),
+                reason: EndReason::Error,
+                error: Some("loading failed".to_string()),
+            }
+        );
+        assert_eq!(
+            parse_event(r#"{"event":"end-file","reason":"eof","playlist_entry_id":1}"#),
+            MpvEvent::EndFile { entry_id: Some(1), reason: EndReason::Eof, error: None }
+        );
+        assert_eq!(
+            parse_event(r#"{"event":"end-file","reason":"stop"}"#),
+            MpvEvent::EndFile { entry_id: None, reason: EndReason::Stop, error: None }
+        );
+        assert_eq!(
+            parse_event(r#"{"event":"start-file","playlist_entry_id":1}"#),
+            MpvEvent::StartFile { entry_id: Some(1) }
+        );
+        assert_eq!(parse_event(r#"{"event":"playback-restart"}"#), MpvEvent::PlaybackRestart);
+        assert_eq!(parse_event(r#"{"data":null,"request_id":0,"error":"success"}"#), MpvEvent::Other);
+        assert_eq!(parse_event("not json"), MpvEvent::Other);
+    }
+
+    #[test]
+    fn parses_stream_titles_from_metadata() {
+        assert_eq!(
+            parse_event(r#"{"event":"property-change","id":1,"name":"metadata","data":{"icy-name":"Radio","icy-title":"Artist - Song"}}"#),
+            MpvEvent::StreamTitle(Some("Artist - Song".to_string()))
+        );
+        assert_eq!(
+            parse_event(r#"{"event":"property-change","id":1,"name":"metadata","data":{"ICY-Title":" "}}"#),
+            MpvEvent::StreamTitle(None)
+        );
+        assert_eq!(
+            parse_event(r#"{"event":"property-change","id":1,"name":"metadata","data":null}"#),
+            MpvEvent::StreamTitle(None)
+        );
+    }
+
+    #[test]
+    fn reads_events_until_the_socket_fails() {
+        struct Failing;
+        impl std::io::Read for Failing {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::new(ErrorKind::ConnectionReset, "connection reset"))
+            }
+        }
+
+        let mut input = b"{\"event\":\"idle\"}\n".to_vec();
+        input.extend_from_slice(b"{\"event\":\"property-change\",\"id\":1,\"name\":\"metadata\",\"data\":{\"icy-title\":\"Caf\xe9\"}}\n");
+        input.extend_from_slice(b"{\"event\":\"playback-restart\"}\n");
+        let mut events = Vec::new();
+        read_events(std::io::BufReader::new(std::io::Read::chain(input.as_slice(), Failing)), |event| {
+            events.push(event);
+            true
+        });
+        assert_eq!(events, vec![
+            MpvEvent::Idle,
+            MpvEvent::StreamTitle(Some("Caf\u{FFFD}".to_string())),
+            MpvEvent::PlaybackRestart,
+        ]);
+
+        let mut events = 0;
+        read_events(input.as_slice(), |_| {
+            events += 1;
+            false
+        });
+        assert_eq!(events, 1);
+    }
+}
@@ -2,9 +2,14 @@ use std::io::BufReader;
 use std::process::{Child, Command, Stdio};
 use std::io::BufRead;
 use std::thread;
+use crate::library::loudness::ReplayGainMode;
 
 
-pub async fn launch_mpv(output_device: Option<String>, socket_path: String) -> Child {
+pub async fn launch_mpv(
+  output_device: Option<String>,
+  socket_path: String,
+  replaygain: ReplayGainMode,
+) -> Child {
   let mut args = vec![
     "-v".to_string(),
     "--idle".to_string(),
@@ -16,6 +21,11 @@ pub async fn launch_mpv(output_device: Option<String>, socket_path: String) -> C
     // --no-config means mpv's own defaults (weak, no) apply otherwise.
     "--gapless-audio=yes".to_string(),
     "--prefetch-playlist=yes".to_string(),
+    // mpv reads the ReplayGain tags itself. Files without them get the gain
+    // of the loudness analysis through replaygain-fallback, if there is one.
+    format!("--replaygain={}", replaygain.mpv_value()),
+    // Rather lower a loud track than let its peaks clip.
+    "--replaygain-clip=yes".to_string(),
   ];
   
   let mut socket_arg = "--input-ipc-server=".to_owned();
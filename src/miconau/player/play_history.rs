@@ -0,0 +1,385 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+
+const HISTORY_FILE: &str = "history.jsonl";
+
+/// How often the tracks of plays taken out of the history were played.
+const EARLIER_PLAY_COUNTS_FILE: &str = "earlier_play_counts.json";
+
+/// How many plays the history keeps. Years of listening, and still quick to
+/// load and to go through for the recently played playlists.
+const MAX_PLAYS: usize = 20_000;
+
+/// How many plays over `MAX_PLAYS` the history takes before it drops the
+/// oldest, so the file is written anew now and then rather than every play.
+const EXTRA_PLAYS: usize = 1_000;
+
+/// Where a play came from.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum PlaySource {
+    Stream { name: String },
+    Playlist { name: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PlayedTrack {
+    pub title: String,
+    pub artist: Option<String>,
+    /// The location mpv played it from.
+    pub file: String,
+    /// How long the track is, once mpv knows.
+    #[serde(default)]
+    pub duration_seconds: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Play {
+    /// When it started, in seconds since the epoch.
+    pub started_at: u64,
+    pub source: PlaySource,
+    /// The track, for a play from a playlist.
+    pub track: Option<PlayedTrack>,
+    /// How long it played, not counting pauses.
+    pub played_seconds: u64,
+    /// Whether the track was stopped or skipped before its end. Streams
+    /// have no end, so they never are.
+    pub skipped: bool,
+}
+
+/// A playlist or station and when it was last played, for the "recently
+/// played" view.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RecentSource {
+    pub source: PlaySource,
+    pub last_played_at: u64,
+    pub plays: usize,
+}
+
+impl RecentSource {
+    pub fn is_playlist(&self, title: &str) -> bool {
+        matches!(&self.source, PlaySource::Playlist { name } if name == title)
+    }
+}
+
+/// The play going on, which goes into the history once it ends and its
+/// length is known.
+struct CurrentPlay {
+    play: Play,
+    started: Instant,
+    paused: Duration,
+    paused_since: Option<Instant>,
+}
+
+/// The latest plays, oldest first. Kept in `history.jsonl` in the data
+/// folder, one play per line, so recording a play only appends a line.
+/// Without a data folder it only lasts until miconau stops.
+///
+/// Past `MAX_PLAYS` the oldest plays are dropped, so neither the file nor
+/// what is loaded of it grows for as long as miconau is used. Only how often
+/// their tracks were played is kept, for the smart playlists.
+#[derive(Default)]
+pub struct PlayHistory {
+    file: Option<PathBuf>,
+    plays: Vec<Play>,
+    earlier_play_counts: HashMap<String, usize>,
+    current: Option<CurrentPlay>,
+}
+
+fn now_seconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+impl PlayHistory {
+    /// Loads the history from the data folder. Lines that can't be read,
+    /// such as the last one after a crash halfway through writing it, are
+    /// left out.
+    pub fn load(data_folder: Option<&Path>) -> PlayHistory {
+        let file = data_folder.map(|folder| folder.join(HISTORY_FILE));
+        let plays = file
+            .as_ref()
+            .and_then(|file| fs::read_to_string(file).ok())
+            .map(|content| {
+                content
+                    .lines()
+                    .filter_map(|line| serde_json::from_str(line).ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let earlier_play_counts = file
+            .as_ref()
+            .and_then(|file| fs::read_to_string(file.with_file_name(EARLIER_PLAY_COUNTS_FILE)).ok())
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        let mut history = PlayHistory { file, plays, earlier_play_counts, current: None };
+        history.drop_oldest();
+        history
+    }
+
+    /// Notes that something started playing. Anything still going on is
+    /// recorded first, as having played to its end.
+    pub fn start(&mut self, source: PlaySource, track: Option<PlayedTrack>) {
+        self.finish(false);
+        self.current = Some(CurrentPlay {
+            play: Play {
+                started_at: now_seconds(),
+                source,
+                track,
+                played_seconds: 0,
+                skipped: false,
+            },
+            started: Instant::now(),
+            paused: Duration::ZERO,
+            paused_since: None,
+        });
+    }
+
+    pub fn pause(&mut self) {
+        if let Some(current) = &mut self.current {
+            current.paused_since.get_or_insert_with(Instant::now);
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if let Some(current) = &mut self.current {
+            if let Some(since) = current.paused_since.take() {
+                current.paused += since.elapsed();
+            }
+        }
+    }
+
+    /// Notes how long the track playing is.
+    pub fn set_duration(&mut self, duration_seconds: f64) {
+        if let Some(track) = self.current.as_mut().and_then(|current| current.play.track.as_mut()) {
+            track.duration_seconds = Some(duration_seconds);
+        }
+    }
+
+    /// Whether the track playing still needs its length.
+    pub fn needs_duration(&self) -> bool {
+        self.current
+            .as_ref()
+            .and_then(|current| current.play.track.as_ref())
+            .is_some_and(|track| track.duration_seconds.is_none())
+    }
+
+    /// Records the play going on, if any, as ended, and returns it.
+    /// `stopped_early` is whether it ended by being stopped or replaced
+    /// rather than playing out.
+    pub fn finish(&mut self, stopped_early: bool) -> Option<Play> {
+        let current = self.current.take()?;
+        let mut play = current.play;
+        let paused = current.paused + current.paused_since.map_or(Duration::ZERO, |since| since.elapsed());
+        play.played_seconds = current.started.elapsed().saturating_sub(paused).as_secs();
+        play.skipped = stopped_early && play.track.is_some();
+        if let Some(file) = &self.file {
+            if let Err(error) = append(file, &play) {
+                println!("Could not write the play history to {:?}: {}", file, error);
+            }
+        }
+        self.plays.push(play.clone());
+        self.drop_oldest();
+        Some(play)
+    }
+
+    /// Drops the oldest plays once there are more than the history keeps,
+    /// counting them into the earlier play counts, and writes both files
+    /// anew.
+    fn drop_oldest(&mut self) {
+        if self.plays.len() <= MAX_PLAYS + EXTRA_PLAYS {
+            return;
+        }
+        let dropped: Vec<Play> = self.plays.drain(..self.plays.len() - MAX_PLAYS).collect();
+        for track in dropped.iter().filter_map(|play| play.track.as_ref()) {
+            *self.earlier_play_counts.entry(track.file.clone()).or_insert(0) += 1;
+        }
+        let Some(file) = &self.file else {
+            return;
+        };
+        let counts_file = file.with_file_name(EARLIER_PLAY_COUNTS_FILE);
+        let counts: BTreeMap<&String, &usize> = self.earlier_play_counts.iter().collect();
+        let mut lines = String::new();
+        for play in &self.plays {
+            if let Ok(line) = serde_json::to_string(play) {
+                lines.push_str(&line);
+                lines.push('\n');
+            }
+        }
+        // The counts go first: if writing the history fails, the dropped
+        // plays are counted twice when they are dropped again, which is
+        // better than not at all.
+        let written = serde_json::to_string_pretty(&counts)
+            .map_err(std::io::Error::from)
+            .and_then(|content| replace_file(&counts_file, &content))
+            .and_then(|_| replace_file(file, &lines));
+        if let Err(error) = written {
+            println!("Could not drop the oldest plays from {:?}: {}", file, error);
+        }
+    }
+
+    /// Forgets the play going on: it failed, and wasn't a play at all.
+    pub fn discard(&mut self) {
+        self.current = None;
+    }
+
+    /// A page of plays, the latest first, and how many there are in all.
+    pub fn page(&self, offset: usize, limit: usize) -> (usize, Vec<Play>) {
+        let plays = self.plays.iter().rev().skip(offset).take(limit).cloned().collect();
+        (self.plays.len(), plays)
+    }
+
+    /// How often each track was played, by its location. Plays that were
+    /// skipped count too: they still were played.
+    pub fn play_counts(&self) -> HashMap<String, usize> {
+        let mut counts = self.earlier_play_counts.clone();
+        for track in self.plays.iter().filter_map(|play| play.track.as_ref()) {
+            *counts.entry(track.file.clone()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// The playlists played, the last played first.
+    pub fn recent_playlists(&self) -> Vec<RecentSource> {
+        let mut recent: Vec<RecentSource> = Vec::new();
+        for play in self.plays.iter().rev() {
+            if !matches!(play.source, PlaySource::Playlist { .. }) {
+                continue;
+            }
+            match recent.iter_mut().find(|source| source.source == play.source) {
+                Some(source) => source.plays += 1,
+                None => recent.push(RecentSource {
+                    source: play.source.clone(),
+                    last_played_at: play.started_at,
+                    plays: 1,
+                }),
+            }
+        }
+        recent
+    }
+}
+
+/// Writes a file by way of a temporary one, so it is never left half written.
+fn replace_file(file: &Path, content: &str) -> std::io::Result<()> {
+    let temporary = file.with_extension("tmp");
+    fs::write(&temporary, content)?;
+    fs::rename(&temporary, file)
+}
+
+fn append(file: &Path, play: &Play) -> std::io::Result<()> {
+    if let Some(folder) = file.parent() {
+        fs::create_dir_all(folder)?;
+    }
+    let mut line = serde_json::to_string(play)?;
+    line.push('\n');
+    OpenOptions::new().create(true).append(true).open(file)?.write_all(line.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+
+    fn playlist(name: &str) -> PlaySource {
+        PlaySource::Playlist { name: name.to_string() }
+    }
+
+    fn track(title: &str) -> Option<PlayedTrack> {
+        Some(PlayedTrack {
+            title: title.to_string(),
+            artist: None,
+            file: format!("/music/{}.mp3", title),
+            duration_seconds: None,
+        })
+    }
+
+    #[test]
+    fn plays_are_kept_in_the_data_folder() {
+        let temp = TempDir::new("history");
+        let folder = &temp.path;
+
+        let mut history = PlayHistory::load(Some(folder));
+        history.start(playlist("Album"), track("One"));
+        assert!(history.needs_duration());
+        history.set_duration(180.5);
+        assert!(!history.needs_duration());
+        history.pause();
+        history.resume();
+        history.finish(true);
+        history.start(PlaySource::Stream { name: "Radio".to_string() }, None);
+        history.finish(true);
+        history.start(playlist("Album"), track("Broken"));
+        history.discard();
+        history.finish(false);
+
+        let reloaded = PlayHistory::load(Some(folder));
+        let (total, plays) = reloaded.page(0, 10);
+        assert_eq!(total, 2);
+        assert_eq!(plays[0].source, PlaySource::Stream { name: "Radio".to_string() });
+        assert!(!plays[0].skipped);
+        assert_eq!(plays[1].track.as_ref().unwrap().title, "One");
+        assert_eq!(plays[1].track.as_ref().unwrap().duration_seconds, Some(180.5));
+        assert!(plays[1].skipped);
+    }
+
+    #[test]
+    fn pages_and_recent_playlists_go_latest_first() {
+        let mut history = PlayHistory::load(None);
+        for name in ["A", "B", "A", "C"] {
+            history.start(playlist(name), track(name));
+        }
+        history.finish(false);
+        history.start(PlaySource::Stream { name: "Radio".to_string() }, None);
+        history.finish(false);
+
+        let (total, plays) = history.page(1, 2);
+        assert_eq!(total, 5);
+        assert_eq!(plays.iter().map(|play| play.source.clone()).collect::<Vec<_>>(), vec![playlist("C"), playlist("A")]);
+        assert!(history.page(5, 2).1.is_empty());
+
+        assert_eq!(history.play_counts().get("/music/A.mp3"), Some(&2));
+        assert_eq!(history.play_counts().len(), 3);
+
+        let recent = history.recent_playlists();
+        assert_eq!(recent.iter().map(|source| source.source.clone()).collect::<Vec<_>>(), vec![playlist("C"), playlist("A"), playlist("B")]);
+        assert_eq!(recent[1].plays, 2);
+    }
+
+    #[test]
+    fn the_oldest_plays_are_dropped_but_still_counted() {
+        let temp = TempDir::new("history-dropped");
+        let folder = &temp.path;
+        let play = |title: &str| Play {
+            started_at: 0,
+            source: playlist("Album"),
+            track: track(title),
+            played_seconds: 0,
+            skipped: false,
+        };
+        let mut lines = String::new();
+        for index in 0..MAX_PLAYS + EXTRA_PLAYS + 1 {
+            lines.push_str(&serde_json::to_string(&play(if index < 10 { "Old" } else { "New" })).unwrap());
+            lines.push('\n');
+        }
+        fs::write(folder.join(HISTORY_FILE), lines).unwrap();
+
+        let mut history = PlayHistory::load(Some(folder));
+        assert_eq!(history.page(0, 1).0, MAX_PLAYS);
+        assert_eq!(history.play_counts().get("/music/Old.mp3"), Some(&10));
+        history.start(playlist("Album"), track("Old"));
+        history.finish(false);
+
+        let reloaded = PlayHistory::load(Some(folder));
+        assert_eq!(reloaded.page(0, 1).0, MAX_PLAYS + 1);
+        assert_eq!(reloaded.play_counts().get("/music/Old.mp3"), Some(&11));
+        assert_eq!(reloaded.play_counts().get("/music/New.mp3"), Some(&(MAX_PLAYS + EXTRA_PLAYS - 9)));
+    }
+}
@@ -0,0 +1,139 @@
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use super::fades::fade_volume;
+
+/// How close to the end a sleep timer stops playback. The player is ticked
+/// more often than this, or it could miss the end of a track.
+pub const SLEEP_TIMER_MARGIN: Duration = Duration::from_secs(1);
+
+/// How long the volume takes to fade out when no other time is asked for.
+pub const DEFAULT_FADE: Duration = Duration::from_secs(30);
+
+/// The times the sleep key steps through, in minutes. One more press after
+/// the last turns the timer off again.
+const KEY_STEPS: [u64; 3] = [15, 30, 60];
+
+/// What a sleep timer waits for before it stops playback.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum SleepMode {
+    /// A set time.
+    Time,
+    /// The end of the track that is playing.
+    EndOfTrack,
+    /// The end of the playlist, with the queue.
+    EndOfPlaylist,
+}
+
+/// A sleep timer as it is reported in `PlayerState`.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SleepTimerState {
+    mode: SleepMode,
+    /// Whole seconds until playback stops, when that is known: always for a
+    /// set time, for the end of a track or playlist once its last track plays.
+    remaining_seconds: Option<u64>,
+    /// How much of that the volume is faded out over.
+    fade_seconds: u64,
+    /// The set time in minutes, so the sleep key knows where it is.
+    minutes: Option<u64>,
+}
+
+pub struct SleepTimer {
+    pub mode: SleepMode,
+    /// When a timer for a set time runs out.
+    deadline: Option<Instant>,
+    minutes: Option<u64>,
+    pub fade: Duration,
+    /// What was left when the timer was last ticked. For the end of a track
+    /// or playlist, only mpv knows, so this is what is reported.
+    pub remaining: Option<Duration>,
+}
+
+impl SleepTimer {
+    pub fn after_minutes(minutes: u64, fade: Duration) -> SleepTimer {
+        SleepTimer {
+            mode: SleepMode::Time,
+            deadline: Some(Instant::now() + Duration::from_secs(minutes * 60)),
+            minutes: Some(minutes),
+            fade,
+            remaining: None,
+        }
+    }
+
+    /// A timer for the end of the track or playlist. `mode` should not be
+    /// `SleepMode::Time`, which needs a time.
+    pub fn at_end_of(mode: SleepMode, fade: Duration) -> SleepTimer {
+        SleepTimer {
+            mode,
+            deadline: None,
+            minutes: None,
+            fade,
+            remaining: None,
+        }
+    }
+
+    /// The timer the sleep key sets next when `current` is running: the next
+    /// longer time, or none after the longest.
+    pub fn next_key_step(current: Option<&SleepTimer>) -> Option<u64> {
+        match current.and_then(|timer| timer.minutes) {
+            None => Some(KEY_STEPS[0]),
+            Some(minutes) => KEY_STEPS.iter().find(|&&step| step > minutes).copied(),
+        }
+    }
+
+    /// How long until playback stops, if that is known.
+    pub fn remaining_at(&self, now: Instant) -> Option<Duration> {
+        // A set time is counted here, everything else comes from mpv.
+        match self.deadline {
+            Some(deadline) => Some(deadline.saturating_duration_since(now)),
+            None => self.remaining,
+        }
+    }
+
+    /// How much of the volume plays with what was left at the last tick,
+    /// from 0 to 1. Faded out by the time the margin is reached.
+    pub fn volume_factor(&self) -> f64 {
+        self.remaining.map_or(1.0, |remaining| {
+            fade_volume(remaining.saturating_sub(SLEEP_TIMER_MARGIN), self.fade, 1.0)
+        })
+    }
+
+    pub fn state(&self) -> SleepTimerState {
+        SleepTimerState {
+            mode: self.mode,
+            remaining_seconds: self.remaining_at(Instant::now()).map(|remaining| remaining.as_secs()),
+            fade_seconds: self.fade.as_secs(),
+            minutes: self.minutes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_timer_for_a_set_time_counts_down() {
+        let timer = SleepTimer::after_minutes(15, DEFAULT_FADE);
+        let remaining = timer.remaining_at(Instant::now()).unwrap();
+        assert!(remaining <= Duration::from_secs(15 * 60));
+        assert!(remaining > Duration::from_secs(14 * 60));
+        let later = Instant::now() + Duration::from_secs(16 * 60);
+        assert_eq!(timer.remaining_at(later), Some(Duration::ZERO));
+
+        let timer = SleepTimer::at_end_of(SleepMode::EndOfTrack, DEFAULT_FADE);
+        assert_eq!(timer.remaining_at(Instant::now()), None);
+    }
+
+    #[test]
+    fn the_sleep_key_steps_through_the_times_and_then_off() {
+        assert_eq!(SleepTimer::next_key_step(None), Some(15));
+        let timer = SleepTimer::after_minutes(15, DEFAULT_FADE);
+        assert_eq!(SleepTimer::next_key_step(Some(&timer)), Some(30));
+        let timer = SleepTimer::after_minutes(60, DEFAULT_FADE);
+        assert_eq!(SleepTimer::next_key_step(Some(&timer)), None);
+        // a timer set some other way starts the steps over
+        let timer = SleepTimer::at_end_of(SleepMode::EndOfPlaylist, DEFAULT_FADE);
+        assert_eq!(SleepTimer::next_key_step(Some(&timer)), Some(15));
+    }
+}
@@ -0,0 +1,70 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::Serialize;
+
+/// How many songs are remembered per station.
+const SONGS_PER_STATION: usize = 50;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct HeardSong {
+    pub title: String,
+    /// When the station started playing it, in seconds since the epoch.
+    pub heard_at: u64,
+}
+
+/// The songs recently heard on each station, by the titles the stations
+/// send along with the audio. Kept in memory only: it is for looking up the
+/// song that was just on, not a record of everything ever played.
+#[derive(Default)]
+pub struct StreamHistory {
+    stations: HashMap<String, VecDeque<HeardSong>>,
+}
+
+impl StreamHistory {
+    /// Notes that a station is playing a song. Stations repeat the title now
+    /// and then, and reconnecting sends it again, so a title that is already
+    /// the latest isn't recorded twice.
+    pub fn record(&mut self, station: &str, title: &str) {
+        let songs = self.stations.entry(station.to_string()).or_default();
+        if songs.front().is_some_and(|song| song.title == title) {
+            return;
+        }
+        let heard_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        songs.push_front(HeardSong { title: title.to_string(), heard_at });
+        songs.truncate(SONGS_PER_STATION);
+    }
+
+    /// The songs heard on a station, the latest first.
+    pub fn songs(&self, station: &str) -> Vec<HeardSong> {
+        self.stations
+            .get(station)
+            .map(|songs| songs.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remembers_the_latest_songs_per_station() {
+        let mut history = StreamHistory::default();
+        history.record("A", "First");
+        history.record("A", "First");
+        history.record("B", "Other");
+        for number in 0..SONGS_PER_STATION {
+            history.record("A", &format!("Song {}", number));
+        }
+
+        let songs = history.songs("A");
+        assert_eq!(songs.len(), SONGS_PER_STATION);
+        assert_eq!(songs[0].title, format!("Song {}", SONGS_PER_STATION - 1));
+        assert!(songs.iter().all(|song| song.title != "First"));
+        assert_eq!(history.songs("B").len(), 1);
+        assert!(history.songs("C").is_empty());
+    }
+}
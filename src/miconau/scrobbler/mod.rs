@@ -0,0 +1,394 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+
+const QUEUE_FILE: &str = "scrobble-queue.jsonl";
+
+/// How long to wait before trying queued listens again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// The most listens ListenBrainz takes in one request.
+const MAX_LISTENS_PER_REQUEST: usize = 1000;
+
+/// A listen counts once half the track has played, or four minutes of it,
+/// whichever comes first.
+const LISTEN_AFTER: Duration = Duration::from_secs(4 * 60);
+
+/// Tracks shorter than this are never listens: jingles, intros, skits.
+const SHORTEST_LISTEN: Duration = Duration::from_secs(30);
+
+/// Whether a play counts as a listen, by the usual rule. Without a known
+/// length, only four minutes do.
+pub fn counts_as_listen(played: Duration, track_duration: Option<Duration>) -> bool {
+    match track_duration {
+        Some(duration) => duration >= SHORTEST_LISTEN && played >= LISTEN_AFTER.min(duration / 2),
+        None => played >= LISTEN_AFTER,
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AdditionalInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tracknumber: Option<u32>,
+    pub media_player: String,
+    pub submission_client: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TrackMetadata {
+    pub artist_name: String,
+    pub track_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub release_name: Option<String>,
+    pub additional_info: AdditionalInfo,
+}
+
+impl TrackMetadata {
+    pub fn new(
+        artist_name: String,
+        track_name: String,
+        release_name: Option<String>,
+        track_number: Option<u32>,
+        duration: Option<Duration>,
+    ) -> TrackMetadata {
+        TrackMetadata {
+            artist_name,
+            track_name,
+            release_name,
+            additional_info: AdditionalInfo {
+                duration_ms: duration.map(|duration| duration.as_millis() as u64),
+                tracknumber: track_number,
+                media_player: "miconau".to_string(),
+                submission_client: "miconau".to_string(),
+            },
+        }
+    }
+}
+
+/// A listen as ListenBrainz takes it. `listened_at` is left out for what
+/// is playing now.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Listen {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub listened_at: Option<u64>,
+    pub track_metadata: TrackMetadata,
+}
+
+#[derive(Serialize)]
+struct Submission<'a> {
+    listen_type: &'a str,
+    payload: &'a [Listen],
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SubmitError {
+    /// The server could not be reached, or had trouble of its own. Worth
+    /// trying again later.
+    Unavailable(String),
+    /// The server turned the listens down. Trying them again won't help.
+    Rejected(String),
+}
+
+/// Talks to a ListenBrainz compatible API.
+pub struct Client {
+    api_url: String,
+    token: String,
+    agent: ureq::Agent,
+}
+
+impl Client {
+    /// `api_url` is the root of the API, as in `https://api.listenbrainz.org`.
+    pub fn new(api_url: &str, token: &str) -> Client {
+        Client {
+            api_url: api_url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+            agent: ureq::AgentBuilder::new().timeout(REQUEST_TIMEOUT).build(),
+        }
+    }
+
+    fn submit(&self, listen_type: &str, listens: &[Listen]) -> Result<(), SubmitError> {
+        let body = serde_json::to_string(&Submission { listen_type, payload: listens })
+            .map_err(|error| SubmitError::Rejected(error.to_string()))?;
+        let result = self.agent
+            .post(&format!("{}/1/submit-listens", self.api_url))
+            .set("Authorization", &format!("Token {}", self.token))
+            .set("Content-Type", "application/json")
+            .send_string(&body);
+        match result {
+            Ok(_) => Ok(()),
+            // An expired token or too many requests get better by waiting:
+            // the listens are kept until the token is fixed.
+            Err(ureq::Error::Status(status, response)) if status == 401 || status == 429 || status >= 500 => {
+                Err(SubmitError::Unavailable(format!("{} {}", status, response.into_string().unwrap_or_default())))
+            }
+            Err(ureq::Error::Status(status, response)) => {
+                Err(SubmitError::Rejected(format!("{} {}", status, response.into_string().unwrap_or_default())))
+            }
+            Err(error) => Err(SubmitError::Unavailable(error.to_string())),
+        }
+    }
+
+    pub fn playing_now(&self, track_metadata: TrackMetadata) -> Result<(), SubmitError> {
+        self.submit("playing_now", &[Listen { listened_at: None, track_metadata }])
+    }
+}
+
+/// The listens not submitted yet. Kept in `scrobble-queue.jsonl` in the
+/// data folder while offline, so they survive a restart.
+pub struct ListenQueue {
+    file: Option<PathBuf>,
+    listens: Vec<Listen>,
+}
+
+impl ListenQueue {
+    pub fn load(data_folder: Option<&Path>) -> ListenQueue {
+        let file = data_folder.map(|folder| folder.join(QUEUE_FILE));
+        let listens = file
+            .as_ref()
+            .and_then(|file| fs::read_to_string(file).ok())
+            .map(|content| {
+                content
+                    .lines()
+                    .filter_map(|line| serde_json::from_str(line).ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+        ListenQueue { file, listens }
+    }
+
+    pub fn len(&self) -> usize {
+        self.listens.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.listens.is_empty()
+    }
+
+    pub fn push(&mut self, listen: Listen) {
+        if let Some(file) = &self.file {
+            let written = serde_json::to_string(&listen)
+                .map_err(std::io::Error::from)
+                .and_then(|line| {
+                    if let Some(folder) = file.parent() {
+                        fs::create_dir_all(folder)?;
+                    }
+                    OpenOptions::new().create(true).append(true).open(file)?.write_all(format!("{}\n", line).as_bytes())
+                });
+            if let Err(error) = written {
+                println!("Could not queue a listen in {:?}: {}", file, error);
+            }
+        }
+        self.listens.push(listen);
+    }
+
+    /// Submits the queued listens, oldest first, and keeps what could not be
+    /// submitted for the next try.
+    pub fn flush(&mut self, client: &Client) {
+        while !self.listens.is_empty() {
+            let count = self.listens.len().min(MAX_LISTENS_PER_REQUEST);
+            let listen_type = if count == 1 { "single" } else { "import" };
+            match client.submit(listen_type, &self.listens[..count]) {
+                Ok(()) => println!("Submitted {} listen(s)", count),
+                Err(SubmitError::Rejected(error)) => println!("Listens were turned down, dropping them: {}", error),
+                Err(SubmitError::Unavailable(error)) => {
+                    println!("Could not submit listens, trying again later: {}", error);
+                    break;
+                }
+            }
+            self.listens.drain(..count);
+            self.save();
+        }
+    }
+
+    fn save(&self) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let content: String = self.listens
+            .iter()
+            .filter_map(|listen| serde_json::to_string(listen).ok())
+            .map(|line| format!("{}\n", line))
+            .collect();
+        let temporary = file.with_extension("jsonl.tmp");
+        if let Err(error) = fs::write(&temporary, content).and_then(|_| fs::rename(&temporary, file)) {
+            println!("Could not write the scrobble queue to {:?}: {}", file, error);
+        }
+    }
+}
+
+enum Scrobble {
+    PlayingNow(TrackMetadata),
+    Listen(Listen),
+}
+
+/// Hands what is played to a thread of its own that submits it, so the
+/// player never waits for the network.
+pub struct Scrobbler {
+    sender: Sender<Scrobble>,
+}
+
+impl Scrobbler {
+    pub fn spawn(client: Client, mut queue: ListenQueue) -> Scrobbler {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            if !queue.is_empty() {
+                println!("{} listen(s) are waiting to be submitted", queue.len());
+                queue.flush(&client);
+            }
+            loop {
+                match receiver.recv_timeout(RETRY_INTERVAL) {
+                    Ok(Scrobble::PlayingNow(track_metadata)) => {
+                        // Only of interest right now, so never queued.
+                        if let Err(error) = client.playing_now(track_metadata) {
+                            println!("Could not submit what is playing now: {:?}", error);
+                        }
+                    }
+                    Ok(Scrobble::Listen(listen)) => {
+                        queue.push(listen);
+                        queue.flush(&client);
+                    }
+                    Err(RecvTimeoutError::Timeout) if !queue.is_empty() => queue.flush(&client),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        });
+        Scrobbler { sender }
+    }
+
+    pub fn playing_now(&self, track_metadata: TrackMetadata) {
+        let _ = self.sender.send(Scrobble::PlayingNow(track_metadata));
+    }
+
+    pub fn listen(&self, listened_at: u64, track_metadata: TrackMetadata) {
+        let _ = self.sender.send(Scrobble::Listen(Listen { listened_at: Some(listened_at), track_metadata }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+    use std::sync::mpsc::Receiver;
+
+    #[test]
+    fn half_the_track_or_four_minutes_is_a_listen() {
+        let minutes = |minutes: u64| Duration::from_secs(minutes * 60);
+        assert!(counts_as_listen(minutes(2), Some(minutes(3))));
+        assert!(!counts_as_listen(Duration::from_secs(89), Some(minutes(3))));
+        assert!(counts_as_listen(minutes(4), Some(minutes(20))));
+        assert!(!counts_as_listen(minutes(3), Some(minutes(20))));
+        assert!(!counts_as_listen(Duration::from_secs(20), Some(Duration::from_secs(20))));
+        assert!(counts_as_listen(minutes(4), None));
+        assert!(!counts_as_listen(minutes(3), None));
+    }
+
+    /// A stand-in for ListenBrainz that answers every request with `status`
+    /// and passes on the authorization header and body it got.
+    fn serve(status: u16) -> (String, Receiver<(String, serde_json::Value)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for connection in listener.incoming().flatten() {
+                let mut reader = BufReader::new(connection);
+                let mut authorization = String::new();
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+                        break;
+                    }
+                    let (name, value) = line.split_once(':').unwrap_or_default();
+                    match name.to_lowercase().as_str() {
+                        "authorization" => authorization = value.trim().to_string(),
+                        "content-length" => length = value.trim().parse().unwrap_or(0),
+                        _ => {}
+                    }
+                }
+                let mut body = vec![0; length];
+                let _ = reader.read_exact(&mut body);
+                let _ = sender.send((authorization, serde_json::from_slice(&body).unwrap_or_default()));
+                let response = format!("HTTP/1.1 {} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status);
+                let _ = reader.get_mut().write_all(response.as_bytes());
+            }
+        });
+        (format!("http://{}", address), receiver)
+    }
+
+    fn listen(title: &str) -> Listen {
+        Listen {
+            listened_at: Some(1_700_000_000),
+            track_metadata: TrackMetadata::new(
+                "Artist".to_string(),
+                title.to_string(),
+                Some("Album".to_string()),
+                Some(1),
+                Some(Duration::from_secs(200)),
+            ),
+        }
+    }
+
+    #[test]
+    fn listens_are_queued_while_offline_and_submitted_later() {
+        let folder = TempDir::new("scrobble-queue");
+
+        // nothing listens on a port that was just given up
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let offline = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let mut queue = ListenQueue::load(Some(&folder.path));
+        queue.push(listen("One"));
+        queue.push(listen("Two"));
+        queue.flush(&Client::new(&offline, "secret"));
+        assert_eq!(ListenQueue::load(Some(&folder.path)).len(), 2);
+
+        let (url, requests) = serve(200);
+        let mut queue = ListenQueue::load(Some(&folder.path));
+        queue.flush(&Client::new(&format!("{}/", url), "secret"));
+        let (authorization, body) = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(authorization, "Token secret");
+        assert_eq!(body["listen_type"], "import");
+        assert_eq!(body["payload"][0]["track_metadata"]["track_name"], "One");
+        assert_eq!(body["payload"][1]["track_metadata"]["artist_name"], "Artist");
+        assert_eq!(body["payload"][1]["listened_at"], 1_700_000_000);
+        assert_eq!(queue.len(), 0);
+        assert_eq!(ListenQueue::load(Some(&folder.path)).len(), 0);
+    }
+
+    #[test]
+    fn rejected_listens_are_dropped_and_server_errors_kept() {
+        let (url, _requests) = serve(400);
+        let mut queue = ListenQueue::load(None);
+        queue.push(listen("Bad"));
+        queue.flush(&Client::new(&url, "secret"));
+        assert_eq!(queue.len(), 0);
+
+        let (url, _requests) = serve(503);
+        queue.push(listen("Later"));
+        queue.flush(&Client::new(&url, "secret"));
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn playing_now_has_no_time() {
+        let (url, requests) = serve(200);
+        let client = Client::new(&url, "secret");
+        client.playing_now(listen("Now").track_metadata).unwrap();
+        let (_, body) = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(body["listen_type"], "playing_now");
+        assert!(body["payload"][0].get("listened_at").is_none());
+        assert_eq!(body["payload"][0]["track_metadata"]["additional_info"]["duration_ms"], 200_000);
+    }
+}
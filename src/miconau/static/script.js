@@ -4,9 +4,10 @@ async function loadStreams() {
     const streams = await response.json();
     const streamsContainer = document.getElementById('streams');
     streamsContainer.innerHTML = streams.map(stream => `
-            <button class="stream-item" 
+            <button class="stream-item${stream.up === false ? ' stream-down' : ''}" 
                  onclick="playStream(${stream.index})"
-                 data-name="${stream.name}">
+                 data-name="${stream.name}"
+                 ${stream.up === false ? 'title="Not answering"' : ''}>
                  ${stream.logo_svg
         ? `<img src="/api/stream-logo/${stream.name}" alt="${stream.name} icon" class="stream-icon">`
         : ''
@@ -33,6 +34,15 @@ function filterPlaylists() {
 /// their covers displayed while a scan keeps filling the library.
 const playlistRows = new Map();
 
+/// Covers in the playlist list are shown at 40px. Asking for twice that keeps
+/// them sharp on high density screens while the server scales down the
+/// multi-megabyte originals.
+const COVER_THUMBNAIL_SIZE = 80;
+
+function playlistCoverUrl(index) {
+  return `/api/playlist/${index}/cover?size=${COVER_THUMBNAIL_SIZE}`;
+}
+
 /// Builds the row for a playlist. The row keeps its playlist index in
 /// `row.playlistIndex` and every handler reads it from there, because a scan
 /// inserts playlists in sorted order and so shifts the indices of the rows
@@ -52,7 +62,7 @@ function createPlaylistRow(playlist) {
 
   if (playlist.has_cover) {
     const coverImg = document.createElement('img');
-    coverImg.src = `/api/playlist/${playlist.index}/cover`;
+    coverImg.src = playlistCoverUrl(playlist.index);
     coverImg.alt = '';
     coverImg.className = 'playlist-cover';
     coverImg.loading = 'lazy';
@@ -66,6 +76,19 @@ function createPlaylistRow(playlist) {
   summary.appendChild(titleSpan);
   details.appendChild(summary);
 
+  const playlistLoveBtn = document.createElement('button');
+  playlistLoveBtn.className = 'playlist-love-button';
+  renderLoveButton(playlistLoveBtn, playlist.rating.favourite);
+  playlistLoveBtn.addEventListener('click', async () => {
+    const rating = await ratePlaylist(playlistWrapper.playlistIndex, {
+      favourite: playlistLoveBtn.dataset.favourite !== 'true',
+    });
+    if (rating) {
+      renderLoveButton(playlistLoveBtn, rating.favourite);
+      loadFavourites();
+    }
+  });
+
   // Inner play button (visible when expanded, outside summary for accessibility)
   const innerPlayBtn = document.createElement('button');
   innerPlayBtn.textContent = '▶ Play';
@@ -73,6 +96,14 @@ function createPlaylistRow(playlist) {
   innerPlayBtn.addEventListener('click', () => {
     playPlaylist(playlistWrapper.playlistIndex);
   });
+
+  const queueBtn = document.createElement('button');
+  queueBtn.textContent = '+';
+  queueBtn.className = 'playlist-queue-button';
+  queueBtn.title = 'Add the playlist to the queue';
+  queueBtn.addEventListener('click', () => {
+    addPlaylistToQueue(playlistWrapper.playlistIndex);
+  });
   details.appendChild(innerPlayBtn);
 
   const trackList = document.createElement('ul');
@@ -88,6 +119,17 @@ function createPlaylistRow(playlist) {
     const trackIndex = Number(button.dataset.trackIndex);
     if (button.classList.contains('track-play-button')) {
       playPlaylistTrack(playlistWrapper.playlistIndex, trackIndex);
+    } else if (button.classList.contains('track-love-button')) {
+      rateTrack(playlistWrapper.playlistIndex, trackIndex, { favourite: button.dataset.favourite !== 'true' })
+        .then(rating => rating && renderRating(button.parentElement, rating));
+    } else if (button.classList.contains('star-button')) {
+      // Clicking the star a track already has takes its stars away.
+      const value = Number(button.dataset.value);
+      const stars = Number(button.parentElement.dataset.stars) === value ? 0 : value;
+      rateTrack(playlistWrapper.playlistIndex, trackIndex, { stars })
+        .then(rating => rating && renderRating(button.parentElement, rating));
+    } else if (button.classList.contains('track-next-button')) {
+      addToQueue(playlistWrapper.playlistIndex, trackIndex, true);
     } else {
       addToQueue(playlistWrapper.playlistIndex, trackIndex);
     }
@@ -119,6 +161,8 @@ function createPlaylistRow(playlist) {
                 <span class="track-title">${escapeHtml(track.title)}</span>
                 ${track.artist ? `<span class="track-artist">${escapeHtml(track.artist)}</span>` : ''}
               </button>
+              ${ratingButtons(track.index, track.rating)}
+              <button class="track-next-button" data-track-index="${track.index}" title="Play next">⤴</button>
               <button class="track-queue-button" data-track-index="${track.index}">
                 <img src="/icons/queue_music.svg" alt="Add to queue" class="queue-icon">
               </button>
@@ -134,6 +178,8 @@ function createPlaylistRow(playlist) {
   });
 
   playlistWrapper.appendChild(details);
+  playlistWrapper.appendChild(playlistLoveBtn);
+  playlistWrapper.appendChild(queueBtn);
   playlistWrapper.appendChild(playBtn);
   return playlistWrapper;
 }
@@ -147,14 +193,15 @@ function updatePlaylistIndex(row, index) {
 
   const coverImg = row.querySelector('.playlist-cover');
   if (coverImg && !coverImg.complete) {
-    coverImg.src = `/api/playlist/${index}/cover`;
+    coverImg.src = playlistCoverUrl(index);
   }
 }
 
 async function loadPlaylists() {
   try {
     const filter = document.getElementById('playlistFilter').value.trim();
-    const response = await fetch(`/api/playlists?filter=${encodeURIComponent(filter)}`);
+    const typos = document.getElementById('playlistTypos').checked;
+    const response = await fetch(`/api/playlists?filter=${encodeURIComponent(filter)}&typos=${typos}`);
     const playlists = await response.json();
     const playlistsContainer = document.getElementById('playlists');
     document.getElementById('playlistsEmpty').style.display =
@@ -192,6 +239,96 @@ async function loadPlaylists() {
   }
 }
 
+/// The love button and the five stars of a track.
+function ratingButtons(trackIndex, rating) {
+  const stars = [1, 2, 3, 4, 5].map(value =>
+    `<button class="star-button" data-track-index="${trackIndex}" data-value="${value}">${value <= (rating.stars || 0) ? '★' : '☆'}</button>`
+  ).join('');
+  return `<span class="track-rating" data-stars="${rating.stars || 0}">
+      <button class="track-love-button" data-track-index="${trackIndex}" data-favourite="${rating.favourite}">${rating.favourite ? '♥' : '♡'}</button>${stars}
+    </span>`;
+}
+
+function renderLoveButton(button, favourite) {
+  button.dataset.favourite = favourite;
+  button.textContent = favourite ? '♥' : '♡';
+  button.title = favourite ? 'Remove from favourites' : 'Add to favourites';
+}
+
+/// Shows a rating in the buttons `ratingButtons` made.
+function renderRating(container, rating) {
+  container.dataset.stars = rating.stars || 0;
+  renderLoveButton(container.querySelector('.track-love-button'), rating.favourite);
+  for (const star of container.querySelectorAll('.star-button')) {
+    star.textContent = Number(star.dataset.value) <= (rating.stars || 0) ? '★' : '☆';
+  }
+}
+
+async function rateTrack(playlistIndex, trackIndex, change) {
+  const rating = await sendRating(`/api/playlist/${playlistIndex}/track/${trackIndex}/rating`, change);
+  if (rating && change.favourite !== undefined) loadFavourites();
+  return rating;
+}
+
+async function ratePlaylist(playlistIndex, change) {
+  return sendRating(`/api/playlist/${playlistIndex}/rating`, change);
+}
+
+async function sendRating(url, change) {
+  try {
+    const response = await fetch(url, {
+      method: 'PUT',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify(change),
+    });
+    if (!response.ok) throw new Error(await response.text());
+    return await response.json();
+  } catch (error) {
+    console.error('Error rating:', error);
+    return null;
+  }
+}
+
+async function loveCurrentTrack() {
+  try {
+    const response = await fetch('/api/love', { method: 'POST' });
+    if (!response.ok) throw new Error(await response.text());
+    loadFavourites();
+  } catch (error) {
+    console.error('Error loving the current track:', error);
+  }
+}
+
+async function loadFavourites() {
+  try {
+    const response = await fetch('/api/favourites');
+    const favourites = await response.json();
+    document.getElementById('favouritesSection').hidden =
+      favourites.tracks.length === 0 && favourites.playlists.length === 0;
+    document.getElementById('favouritePlaylists').innerHTML = favourites.playlists.map(playlist =>
+      `<li><button onclick="playPlaylist(${playlist.index})">${escapeHtml(playlist.name)}</button></li>`
+    ).join('');
+    document.getElementById('favouriteTracks').innerHTML = favourites.tracks.map((track, index) =>
+      `<li>
+        <button class="track-play-button" onclick="playFavouritesTrack(${index})">
+          <span class="track-title">${escapeHtml(track.title)}</span>
+          ${track.artist ? `<span class="track-artist">${escapeHtml(track.artist)}</span>` : ''}
+        </button>
+      </li>`
+    ).join('');
+  } catch (error) {
+    console.error('Error loading favourites:', error);
+  }
+}
+
+async function playFavourites() {
+  await fetch('/api/play/favourites', { method: 'POST' });
+}
+
+async function playFavouritesTrack(trackIndex) {
+  await fetch(`/api/play/favourites/${trackIndex}`, { method: 'POST' });
+}
+
 function escapeHtml(str) {
   return str
     .replace(/&/g, '&amp;')
@@ -262,18 +399,21 @@ function renderQueue(queue) {
           ${item.track_artist ? `<span class="queue-item-artist">${escapeHtml(item.track_artist)}</span>` : ''}
           <span class="queue-item-playlist">${escapeHtml(item.playlist_name)}</span>
         </div>
+        <button class="queue-move-button" onclick="jumpToQueueItem(${index})" title="Play now">▶</button>
+        <button class="queue-move-button" onclick="moveInQueue(${index}, ${index - 1})" title="Move up" ${index === 0 ? 'disabled' : ''}>↑</button>
+        <button class="queue-move-button" onclick="moveInQueue(${index}, ${index + 1})" title="Move down" ${index === queue.length - 1 ? 'disabled' : ''}>↓</button>
         <button class="queue-remove-button" onclick="removeFromQueue(${index})">Remove</button>
       </li>
     `).join('');
   }
 }
 
-async function addToQueue(playlistIndex, trackIndex) {
+async function addToQueue(playlistIndex, trackIndex, next = false) {
   try {
     const response = await fetch('/api/queue/add', {
       method: 'POST',
       headers: { 'Content-Type': 'application/json' },
-      body: JSON.stringify({ playlist_index: playlistIndex, track_index: trackIndex }),
+      body: JSON.stringify({ playlist_index: playlistIndex, track_index: trackIndex, next }),
     });
     if (!response.ok) {
       const error = await response.text();
@@ -284,6 +424,46 @@ async function addToQueue(playlistIndex, trackIndex) {
   }
 }
 
+async function addPlaylistToQueue(playlistIndex) {
+  try {
+    const response = await fetch(`/api/queue/playlist/${playlistIndex}`, { method: 'POST' });
+    if (!response.ok) {
+      const error = await response.text();
+      console.error('Error adding playlist to queue:', error);
+    }
+  } catch (error) {
+    console.error('Error adding playlist to queue:', error);
+  }
+}
+
+async function moveInQueue(index, to) {
+  try {
+    const response = await fetch(`/api/queue/move/${index}`, {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ to }),
+    });
+    if (!response.ok) {
+      const error = await response.text();
+      console.error('Error moving in queue:', error);
+    }
+  } catch (error) {
+    console.error('Error moving in queue:', error);
+  }
+}
+
+async function jumpToQueueItem(index) {
+  try {
+    const response = await fetch(`/api/queue/play/${index}`, { method: 'POST' });
+    if (!response.ok) {
+      const error = await response.text();
+      console.error('Error playing queue item:', error);
+    }
+  } catch (error) {
+    console.error('Error playing queue item:', error);
+  }
+}
+
 async function removeFromQueue(index) {
   try {
     const response = await fetch(`/api/queue/remove/${index}`, { method: 'POST' });
@@ -304,6 +484,54 @@ async function clearQueue() {
   }
 }
 
+/// Fills the library picker of the upload form. It is only shown when there
+/// is more than one library root to choose from.
+async function loadLibraryRoots() {
+  try {
+    const response = await fetch('/api/library-roots');
+    const roots = await response.json();
+    const select = document.getElementById('libraryRoot');
+    const selected = select.value;
+    select.innerHTML = roots.map(root => `
+      <option value="${root.index}" ${root.available ? '' : 'disabled'}>
+        ${escapeHtml(root.name)}${root.available ? '' : ' (not available)'}
+      </option>
+    `).join('');
+    if (selected) select.value = selected;
+    document.getElementById('libraryRootGroup').hidden = roots.length < 2;
+  } catch (error) {
+    console.error('Error loading library roots:', error);
+  }
+}
+
+/// The upload this page is running, so its progress can be told apart from
+/// that of uploads from other browsers.
+let currentUploadId = null;
+
+const UPLOAD_STAGE_TEXT = {
+  unpacking: 'Unpacking...',
+  scanning: 'Adding to the library...',
+};
+
+function isArchive(file) {
+  const name = file.name.toLowerCase();
+  return name.endsWith('.zip') || name.endsWith('.tar');
+}
+
+function renderUploadProgress(progress) {
+  if (progress.uploadId !== currentUploadId) {
+    return;
+  }
+  const statusDiv = document.getElementById('uploadStatus');
+  if (progress.stage === 'receiving') {
+    statusDiv.textContent = progress.totalBytes
+      ? `Uploading... ${Math.floor(100 * progress.receivedBytes / progress.totalBytes)}%`
+      : `Uploading... ${(progress.receivedBytes / (1024 * 1024)).toFixed(1)} MiB`;
+  } else if (UPLOAD_STAGE_TEXT[progress.stage]) {
+    statusDiv.textContent = UPLOAD_STAGE_TEXT[progress.stage];
+  }
+}
+
 async function uploadPlaylist() {
   const playlistName = document.getElementById('playlistName').value.trim();
   const fileInput = document.getElementById('flacFiles');
@@ -311,36 +539,36 @@ async function uploadPlaylist() {
   const statusDiv = document.getElementById('uploadStatus');
   const uploadButton = document.querySelector('.upload-button');
 
-  // Validation
-  if (!playlistName) {
+  // Validation. An archive of a single album folder is named by the folder.
+  const namedByArchive = files.length === 1 && isArchive(files[0]);
+  if (!playlistName && !namedByArchive) {
     statusDiv.textContent = 'Please enter a playlist name';
     statusDiv.className = 'error';
     return;
   }
 
   if (files.length === 0) {
-    statusDiv.textContent = 'Please select at least one FLAC file';
+    statusDiv.textContent = 'Please select at least one file';
     statusDiv.className = 'error';
     return;
   }
 
-  // Check that all files are FLAC
-  for (let i = 0; i < files.length; i++) {
-    if (!files[i].name.toLowerCase().endsWith('.flac')) {
-      statusDiv.textContent = 'All files must be FLAC format';
-      statusDiv.className = 'error';
-      return;
-    }
-  }
-
+  // Which files are accepted is up to the server, which knows the audio
+  // extensions the library is scanned for and says what it rejects.
   try {
     uploadButton.disabled = true;
     statusDiv.textContent = 'Uploading...';
     statusDiv.className = 'info';
 
-    // Create FormData with files and playlist name
+    // Create FormData with files and playlist name. The server streams the
+    // files to disk as they come, so everything else has to come first.
+    currentUploadId = `${Date.now()}-${Math.random().toString(36).slice(2)}`;
     const formData = new FormData();
+    formData.append('uploadId', currentUploadId);
     formData.append('playlistName', playlistName);
+    if (!document.getElementById('libraryRootGroup').hidden) {
+      formData.append('libraryRoot', document.getElementById('libraryRoot').value);
+    }
     for (let i = 0; i < files.length; i++) {
       formData.append(`file-${i}`, files[i], files[i].name);
     }
@@ -355,7 +583,10 @@ async function uploadPlaylist() {
       throw new Error(error || `Upload failed with status ${response.status}`);
     }
 
-    statusDiv.textContent = 'Playlist uploaded successfully!';
+    const result = await response.json();
+    statusDiv.textContent = result.skipped && result.skipped.length > 0
+      ? `Playlist uploaded, skipped ${result.skipped.length} other files`
+      : 'Playlist uploaded successfully!';
     statusDiv.className = 'success';
 
     // Clear the form
@@ -371,23 +602,34 @@ async function uploadPlaylist() {
     statusDiv.textContent = `Error: ${error.message}`;
     statusDiv.className = 'error';
   } finally {
+    currentUploadId = null;
     uploadButton.disabled = false;
   }
 }
 
+/// The last file that could not be played, shown while playback is stopped
+/// because of it.
+let lastPlaybackError = null;
+
 function renderState(state) {
   const symbol = state.mode === "Stopped"
     ? "⏹"
-    : (state.mode === "Playing"
-      ? "▶️"
-      : "⏸️");
+    : (state.mode === "Error"
+      ? "⚠️"
+      : (state.mode === "Playing"
+        ? "▶️"
+        : "⏸️"));
 
   let statusText = `${symbol}`;
 
   if (state.mode === "Playing" || state.mode === "Paused") {
     if (state.source_info) {
       if (state.source_info.Stream) {
-        statusText += ` ${escapeHtml(state.source_info.Stream.stream_name)}`;
+        const info = state.source_info.Stream;
+        statusText += ` ${escapeHtml(info.stream_name)}`;
+        if (info.now_playing) {
+          statusText += `: ${escapeHtml(info.now_playing)}`;
+        }
       } else if (state.source_info.Track) {
         const info = state.source_info.Track;
         if (info.track_title) {
@@ -401,10 +643,30 @@ function renderState(state) {
         }
       }
     }
+  } else if (state.mode === "Error") {
+    statusText += lastPlaybackError
+      ? ` Could not play ${escapeHtml(lastPlaybackError.file)}: ${escapeHtml(lastPlaybackError.message)}`
+      : ` Playback error`;
   } else {
     statusText += ` Stopped`;
   }
 
+  if (state.rating && state.rating.favourite) {
+    statusText += ` ♥`;
+  }
+  if (state.rating && state.rating.stars) {
+    statusText += ` ${'★'.repeat(state.rating.stars)}`;
+  }
+
+  if (state.sleep_timer) {
+    const timer = state.sleep_timer;
+    if (timer.remaining_seconds !== null) {
+      statusText += ` 💤 ${Math.ceil(timer.remaining_seconds / 60)} min`;
+    } else {
+      statusText += timer.mode === "endOfTrack" ? ` 💤 end of track` : ` 💤 end of playlist`;
+    }
+  }
+
   document.getElementById('status').innerHTML = statusText;
 }
 
@@ -421,6 +683,12 @@ function connectToEvents() {
       // as folders are read.
       loadStreams();
       loadPlaylists();
+      loadLibraryRoots();
+    } else if (data.type === 'playbackError') {
+      console.error(`Could not play ${data.file}: ${data.message}`);
+      lastPlaybackError = data;
+    } else if (data.type === 'uploadProgress') {
+      renderUploadProgress(data);
     } else if (data.type === 'queueUpdated') {
       renderQueue(data.queue);
     }
@@ -432,6 +700,8 @@ document.addEventListener('DOMContentLoaded', () => {
   loadStreams();
   loadPlaylists();
   loadQueue();
+  loadFavourites();
+  loadLibraryRoots();
   connectToEvents();
   fetch('/api/state')
     .then(response => response.json())
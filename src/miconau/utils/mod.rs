@@ -48,6 +48,12 @@ pub fn get_source_index(key: u8, start_octave: u8) -> Option<u8> {
     }
 }
 
+/// Whether a key is the B just below the first source, which loves the
+/// track playing. It is a white key below the start octave, so it would
+/// otherwise only play the error sound.
+pub fn is_love_key(key: u8, start_octave: u8) -> bool {
+    u16::from(key) + 1 == u16::from(start_octave) * 12
+}
 
 /// What a source index refers to. Streams occupy the white keys below the
 /// playlists, so a single index addresses both.
@@ -78,7 +84,14 @@ pub fn resolve_source(
 }
 
 pub fn handle_midi_key_press(received: u8, start_octave: u8, player: &mut Player) {
-    if is_white_key(received) {
+    // Whoever an alarm woke is up, and wants the volume as it is.
+    player.cancel_volume_ramp();
+
+    if is_love_key(received, start_octave) {
+        if let Err(error) = player.love_current_track() {
+            println!("Could not love the current track: {}", error);
+        }
+    } else if is_white_key(received) {
         let source_index = get_source_index(received, start_octave);
 
         match source_index {
@@ -114,8 +127,9 @@ pub fn handle_midi_key_press(received: u8, start_octave: u8, player: &mut Player
         player.stop();
     }
 
+    // D# steps through the sleep timer: 15, 30 and 60 minutes, then off.
     if received_within_octave == 3 {
-        player.stop();
+        player.step_sleep_timer();
     }
 
     if received_within_octave == 6 {
@@ -131,6 +145,39 @@ pub fn handle_midi_key_press(received: u8, start_octave: u8, player: &mut Player
     }
 }
 
+/// A folder in the system temp dir for a test, empty to begin with and
+/// deleted again when dropped, which also happens when the test fails. The
+/// name keeps tests running in parallel out of each other's way.
+#[cfg(test)]
+pub struct TempDir {
+    pub path: std::path::PathBuf,
+}
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("miconau-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    /// Writes a file, relative to the folder, creating the folders it is in.
+    pub fn file(&self, relative: &str, content: impl AsRef<[u8]>) -> std::path::PathBuf {
+        let path = self.path.join(relative);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -183,6 +230,16 @@ mod tests {
         assert_eq!(get_source_index(36, 2).unwrap(), 7); // Higher C
     }
 
+    #[test]
+    fn love_key_is_the_b_below_the_first_source() {
+        assert!(is_love_key(47, 4)); // B3
+        assert!(!is_love_key(48, 4)); // C4, the first source
+        assert!(!is_love_key(46, 4)); // Bb3
+        assert!(!is_love_key(35, 4)); // B2
+        // no key is below the lowest C
+        assert!(!is_love_key(11, 0));
+    }
+
     #[test]
     fn get_source_index_boundary_cases() {
         // Start of keyboard (octave 0)
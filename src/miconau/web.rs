@@ -1,452 +0,0 @@
-use axum::{extract::{Path, Query, Request, State, Multipart}, http::{header, HeaderMap, StatusCode}, middleware::{self, Next}, response::{sse::{Event, KeepAlive}, Response, Sse}, routing::{get, post}, Json, Router};
-use serde::{Serialize};
-use tokio::sync::Mutex;
-use tower::ServiceBuilder;
-use tower_http::services::ServeDir;
-use std::{env::current_exe, path::PathBuf, sync::{Arc}};
-use crate::{library::Stream as AudioStream, player::{Player, PlayerState}};
-use std::error::Error;
-use axum::response::IntoResponse;
-use futures_util::stream::{Stream};
-use std::convert::Infallible;
-use futures_util::stream::StreamExt;
-use tokio_stream::wrappers::BroadcastStream;
-use serde_json::json;
-use axum::extract::DefaultBodyLimit;
-
-#[derive(Serialize)]
-struct StreamInfo {
-    name: String,
-    logo_svg: Option<String>,
-    index: usize,
-}
-
-#[derive(Serialize)]
-struct PlaylistInfo {
-    name: String,
-    index: usize,
-    has_cover: bool,
-}
-
-#[derive(Serialize)]
-struct TrackInfo {
-    title: String,
-    artist: Option<String>,
-    index: usize,
-}
-
-#[derive(Serialize)]
-struct QueueItemInfo {
-    playlist_name: String,
-    track_title: String,
-    track_artist: Option<String>,
-    index: usize,
-}
-
-#[derive(Clone)]
-struct ServerState {
-    player: Arc<Mutex<Player>>,
-}
-
-
-async fn sse_handler(
-    State(server_state): State<ServerState>,
-) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-    let player = server_state.player.lock().await;
-    let receiver = player.event_transmitter.subscribe();
-    let event_stream = BroadcastStream::new(receiver)
-        .filter_map(|result| async move {
-            match result {
-                Ok(event) => {
-                    Some(Ok(Event::default().json_data(&event).unwrap()))
-                }
-                Err(_) => None,
-            }
-        });
-    Sse::new(event_stream).keep_alive(KeepAlive::default())
-}
-
-
-fn get_static_path() -> PathBuf {
-    let mut static_path = current_exe().unwrap();
-    static_path.pop();
-    static_path.pop();
-    static_path.pop();
-    static_path.push("src");
-    static_path.push("miconau");
-    static_path.push("static");
-    static_path
-}
-
-
-async fn get_streams(
-    State(server_state): State<ServerState>
-) -> Json<Vec<StreamInfo>> {
-    let player = server_state.player.lock().await;
-    let streams: Vec<StreamInfo> = player.library.streams
-        .iter()
-        .enumerate()
-        .map(|(index, stream)| StreamInfo {
-            name: stream.name.clone(),
-            logo_svg: stream.logo_svg.clone(),
-            index,
-        })
-        .collect();
-    Json(streams)
-}
-
-async fn get_stream_logo(
-    State(server_state): State<ServerState>,
-    Path(name): Path<String>,
-) -> impl IntoResponse {
-    let player = server_state.player.lock().await;
-    let stream: Option<&AudioStream> = player.library.streams
-        .iter()
-        .find(|&x| x.name == *name);
-    println!("Getting logo for stream: {}", name);
-    if let Some(stream) = stream {
-        if let Some(logo_svg) = &stream.logo_svg {
-            let mut headers = HeaderMap::new();
-            headers.insert(header::CONTENT_TYPE, "image/svg+xml".parse().unwrap());
-            return Ok((
-                headers,
-                logo_svg.clone(),
-            ))
-        }
-    }
-    Err(StatusCode::NOT_FOUND)
-}
-
-#[derive(serde::Deserialize)]
-struct PlaylistQuery {
-    /// Text the playlist list is filtered by. Filtering happens here because
-    /// the tracks it searches are only loaded into the browser one playlist at
-    /// a time.
-    #[serde(default)]
-    filter: String,
-}
-
-async fn get_playlists(
-    State(server_state): State<ServerState>,
-    Query(query): Query<PlaylistQuery>,
-) -> Json<Vec<PlaylistInfo>> {
-    let player = server_state.player.lock().await;
-    // Numbered before filtering, so an index keeps pointing at the same
-    // playlist no matter what the list is filtered by.
-    let playlists: Vec<PlaylistInfo> = player.library.playlists
-        .iter()
-        .enumerate()
-        .filter(|(_, playlist)| playlist.matches_filter(&query.filter))
-        .map(|(index, playlist)| PlaylistInfo {
-            name: playlist.title.clone(),
-            index,
-            has_cover: playlist.cover_source.is_some(),
-        })
-        .collect();
-    Json(playlists)
-}
-
-async fn get_playlist_tracks(
-    State(server_state): State<ServerState>,
-    Path(index): Path<usize>,
-) -> Result<Json<Vec<TrackInfo>>, StatusCode> {
-    let player = server_state.player.lock().await;
-    if index >= player.library.playlists.len() {
-        return Err(StatusCode::NOT_FOUND);
-    }
-    let playlist = &player.library.playlists[index];
-    let tracks: Vec<TrackInfo> = playlist.tracks
-        .iter()
-        .enumerate()
-        .map(|(track_index, track)| TrackInfo {
-            title: track.display_title(),
-            artist: track.artist.clone(),
-            index: track_index,
-        })
-        .collect();
-    Ok(Json(tracks))
-}
-
-async fn get_playlist_cover(
-    State(server_state): State<ServerState>,
-    Path(index): Path<usize>,
-) -> Result<impl IntoResponse, StatusCode> {
-    // Take only the path from the library, so the player lock is not held
-    // while the file is read.
-    let cover_source = {
-        let player = server_state.player.lock().await;
-        player.library.playlists
-            .get(index)
-            .ok_or(StatusCode::NOT_FOUND)?
-            .cover_source
-            .clone()
-            .ok_or(StatusCode::NOT_FOUND)?
-    };
-
-    // Reading the cover hits the disk, which is slow for a library on an
-    // external drive, so keep it off the runtime threads.
-    let (data, mime) = tokio::task::spawn_blocking(move || {
-        crate::library::read_cover_art(&cover_source)
-    })
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .ok_or(StatusCode::NOT_FOUND)?;
-
-    let mut headers = HeaderMap::new();
-    headers.insert(
-        header::CONTENT_TYPE,
-        mime.parse().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
-    );
-    // Covers now come off the disk on every request and the playlist list is
-    // re-rendered often, so let the browser hold on to them.
-    headers.insert(
-        header::CACHE_CONTROL,
-        "private, max-age=3600".parse().unwrap(),
-    );
-    Ok((headers, data))
-}
-
-async fn get_state(
-    State(server_state): State<ServerState>,
-) -> Json<PlayerState> {
-    let player = server_state.player.lock().await;
-    Json(player.state.clone())
-}
-
-async fn play_stream(
-    Path(index): Path<usize>,
-    State(server_state): State<ServerState>,
-) -> Result<StatusCode, StatusCode> {
-    let mut player = server_state.player.lock().await;
-    player.play_stream(index);
-    Ok(StatusCode::OK)
-}
-
-async fn play_playlist(
-    State(server_state): State<ServerState>,
-    Path(index): Path<usize>,
-) -> Result<StatusCode, StatusCode> {
-    let mut player = server_state.player.lock().await;
-    player.play_playlist(index);
-    Ok(StatusCode::OK)
-}
-
-async fn play_playlist_track(
-    State(server_state): State<ServerState>,
-    Path((index, track_index)): Path<(usize, usize)>,
-) -> Result<StatusCode, StatusCode> {
-    let mut player = server_state.player.lock().await;
-    player.play_playlist_track(index, track_index);
-    Ok(StatusCode::OK)
-}
-
-async fn play_pause(
-    State(server_state): State<ServerState>,
-) -> Result<StatusCode, StatusCode> {
-    let mut player = server_state.player.lock().await;
-    player.play_pause();
-    Ok(StatusCode::OK)
-}
-
-async fn stop(
-    State(server_state): State<ServerState>,
-) -> Result<StatusCode, StatusCode> {
-    let mut player = server_state.player.lock().await;
-    player.stop();
-    Ok(StatusCode::OK)
-}
-
-async fn next_track(
-    State(server_state): State<ServerState>,
-) -> Result<StatusCode, StatusCode> {
-    let mut player = server_state.player.lock().await;
-    player.play_next_track();
-    Ok(StatusCode::OK)
-}
-
-async fn previous_track(
-    State(server_state): State<ServerState>,
-) -> Result<StatusCode, StatusCode> {
-    let mut player = server_state.player.lock().await;
-    player.play_previous_track();
-    Ok(StatusCode::OK)
-}
-
-async fn upload_playlist(
-    State(server_state): State<ServerState>,
-    mut multipart: Multipart,
-) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
-    let mut playlist_name = String::new();
-    let mut files: Vec<(String, Vec<u8>)> = Vec::new();
-
-    while let Some(field) = multipart.next_field().await
-        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Error parsing multipart: {}", e)))? {
-        
-        let field_name = field.name().unwrap_or("").to_string();
-        
-        if field_name == "playlistName" {
-            playlist_name = field.text().await
-                .map_err(|e| (StatusCode::BAD_REQUEST, format!("Error reading playlist name: {}", e)))?;
-        } else if field_name.starts_with("file-") {
-            let file_name = field.file_name().unwrap_or("unknown").to_string();
-            let bytes = field.bytes().await
-                .map_err(|e| (StatusCode::BAD_REQUEST, format!("Error reading file: {}", e)))?;
-            files.push((file_name, bytes.to_vec()));
-        }
-    }
-
-    if playlist_name.is_empty() {
-        return Err((StatusCode::BAD_REQUEST, "Playlist name is required".to_string()));
-    }
-
-    if files.is_empty() {
-        return Err((StatusCode::BAD_REQUEST, "At least one file is required".to_string()));
-    }
-
-    // Get the library folder from the player
-    let player = server_state.player.lock().await;
-    let library_folder = player.library.folder.clone();
-    drop(player);
-
-    // Create playlist directory
-    let playlist_path = PathBuf::from(&library_folder).join(&playlist_name);
-    tokio::fs::create_dir_all(&playlist_path)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Error creating directory: {}", e)))?;
-
-    // Write files to the playlist directory
-    for (filename, data) in files {
-        let file_path = playlist_path.join(&filename);
-        tokio::fs::write(&file_path, data)
-            .await
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Error writing file: {}", e)))?;
-    }
-
-    // Reload library. The scan is blocking and can take minutes on a large
-    // library, so it must not run on a runtime thread or hold the player lock.
-    let library = tokio::task::spawn_blocking(move || {
-        crate::library::Library::new(library_folder)
-    })
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Error scanning library: {}", e)))?;
-
-    let mut player = server_state.player.lock().await;
-    // A rescan only covers the library folder, and streams are configured
-    // outside it, so they have to be moved across to the new library. Taken
-    // here rather than before the scan so the streams stay playable while it
-    // runs.
-    let streams = std::mem::take(&mut player.library.streams);
-    player.library = library;
-    player.library.streams = streams;
-    player.notify_library_updated();
-
-    Ok(Json(json!({"success": true})))
-}
-
-async fn get_queue(
-    State(server_state): State<ServerState>,
-) -> Json<Vec<QueueItemInfo>> {
-    let player = server_state.player.lock().await;
-    let queue: Vec<QueueItemInfo> = player.queue
-        .iter()
-        .enumerate()
-        .map(|(index, item)| QueueItemInfo {
-            playlist_name: item.playlist_name.clone(),
-            track_title: item.track_title.clone(),
-            track_artist: item.track_artist.clone(),
-            index,
-        })
-        .collect();
-    Json(queue)
-}
-
-#[derive(serde::Deserialize)]
-struct AddToQueueRequest {
-    playlist_index: usize,
-    track_index: usize,
-}
-
-async fn add_to_queue(
-    State(server_state): State<ServerState>,
-    Json(payload): Json<AddToQueueRequest>,
-) -> Result<StatusCode, (StatusCode, String)> {
-    let mut player = server_state.player.lock().await;
-    player.add_to_queue(payload.playlist_index, payload.track_index)
-        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
-    Ok(StatusCode::OK)
-}
-
-async fn remove_from_queue(
-    State(server_state): State<ServerState>,
-    Path(index): Path<usize>,
-) -> Result<StatusCode, (StatusCode, String)> {
-    let mut player = server_state.player.lock().await;
-    player.remove_from_queue(index)
-        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
-    Ok(StatusCode::OK)
-}
-
-async fn clear_queue(
-    State(server_state): State<ServerState>,
-) -> StatusCode {
-    let mut player = server_state.player.lock().await;
-    player.clear_queue();
-    StatusCode::OK
-}
-
-
-async fn disable_browser_cache(request: Request, next: Next) -> Response {
-    let mut response = next.run(request).await;
-    response.headers_mut().insert(
-        "Cache-Control",
-        "no-cache, no-store, must-revalidate".parse().unwrap(),
-    );
-    response.headers_mut().insert("Pragma", "no-cache".parse().unwrap());
-    response.headers_mut().insert("Expires", "0".parse().unwrap());
-    response
-}
-
-pub async fn start_server(
-    player_arc: Arc<Mutex<Player>>,
-    address: String,
-) -> Result<(), Box<dyn Error>> {
-    let static_path = get_static_path();
-
-    let api_routes = Router::new()
-        .route("/streams", get(get_streams))
-        .route("/stream-logo/{name}", get(get_stream_logo))
-        .route("/playlists", get(get_playlists))
-        .route("/playlist/{index}/tracks", get(get_playlist_tracks))
-        .route("/playlist/{index}/cover", get(get_playlist_cover))
-        .route("/play/stream/{index}", post(play_stream))
-        .route("/play/playlist/{index}", post(play_playlist))
-        .route("/play/playlist/{index}/{track_index}", post(play_playlist_track))
-        .route("/play/pause", post(play_pause))
-        .route("/stop", post(stop))
-        .route("/next", post(next_track))
-        .route("/previous", post(previous_track))
-        .route("/upload-playlist", post(upload_playlist))
-        .route("/queue", get(get_queue))
-        .route("/queue/add", post(add_to_queue))
-        .route("/queue/remove/{index}", post(remove_from_queue))
-        .route("/queue/clear", post(clear_queue))
-        .route("/notifications", get(sse_handler))
-        .route("/state", get(get_state))
-        .layer(DefaultBodyLimit::max(512 * 1024 * 1024))
-        .with_state(ServerState {
-            player: player_arc,
-        });
-
-    let static_service = ServiceBuilder::new()
-        .layer(middleware::from_fn(disable_browser_cache))
-        .service(ServeDir::new(static_path));
-
-    let app = Router::new()
-        .nest("/api", api_routes)
-        .fallback_service(static_service);
-
-    println!("Starting HTTP server on http://{}", address);
-    let listener = tokio::net::TcpListener::bind(address).await?;
-    axum::serve(listener, app).await?;
-    Ok(())
-}
@@ -0,0 +1,51 @@
+use axum::{extract::{Path, State}, http::StatusCode, Json};
+use serde::Serialize;
+use crate::alarms::Alarm;
+use super::ServerState;
+
+#[derive(Serialize)]
+pub struct AddAlarmResponse {
+    index: usize,
+}
+
+pub async fn get_alarms(State(server_state): State<ServerState>) -> Json<Vec<Alarm>> {
+    Json(server_state.alarms.lock().await.alarms.clone())
+}
+
+/// Adds an alarm. The station or playlist it plays is not checked against
+/// the library, which may still be scanning or be on a drive that is away.
+pub async fn add_alarm(
+    State(server_state): State<ServerState>,
+    Json(alarm): Json<Alarm>,
+) -> Result<Json<AddAlarmResponse>, (StatusCode, String)> {
+    let index = server_state.alarms.lock().await.add(alarm)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    Ok(Json(AddAlarmResponse { index }))
+}
+
+pub async fn update_alarm(
+    State(server_state): State<ServerState>,
+    Path(index): Path<usize>,
+    Json(alarm): Json<Alarm>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut alarms = server_state.alarms.lock().await;
+    if index >= alarms.alarms.len() {
+        return Err((StatusCode::NOT_FOUND, format!("Alarm {} not found", index)));
+    }
+    alarms.update(index, alarm)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    Ok(StatusCode::OK)
+}
+
+pub async fn delete_alarm(
+    State(server_state): State<ServerState>,
+    Path(index): Path<usize>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut alarms = server_state.alarms.lock().await;
+    if index >= alarms.alarms.len() {
+        return Err((StatusCode::NOT_FOUND, format!("Alarm {} not found", index)));
+    }
+    alarms.delete(index)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    Ok(StatusCode::OK)
+}
@@ -0,0 +1,343 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+use crate::library::ScanConfig;
+use super::upload::{is_allowed_file, sanitize_name};
+
+/// Most entries an archive may have. Far more than the files of any box set,
+/// and few enough that an archive of nothing but empty entries can't keep
+/// the upload busy creating them.
+const MAX_ENTRIES: usize = 10_000;
+
+/// The archive formats an album can be uploaded in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+}
+
+impl ArchiveKind {
+    /// The kind of archive a file is, by its extension. None for anything
+    /// that isn't an archive.
+    pub fn for_file_name(name: &str) -> Option<ArchiveKind> {
+        let extension = Path::new(name).extension()?.to_string_lossy().to_lowercase();
+        match extension.as_str() {
+            "zip" => Some(ArchiveKind::Zip),
+            "tar" => Some(ArchiveKind::Tar),
+            _ => None,
+        }
+    }
+}
+
+/// What unpacking an archive came to.
+#[derive(Debug, Default, PartialEq)]
+pub struct Unpacked {
+    /// How many files were unpacked.
+    pub files: usize,
+    /// How many bytes they came to.
+    pub bytes: u64,
+    /// Files that were left in the archive, because they are of a type that
+    /// can't be uploaded or their path isn't safe.
+    pub skipped: Vec<String>,
+    /// The folder everything in the archive was in, if it was all in one.
+    /// Zipping up an album folder gives such an archive, and the folder is
+    /// left out, since the upload goes into a folder of its own anyway. Its
+    /// name makes a good playlist name when the upload doesn't give one.
+    pub top_folder: Option<String>,
+}
+
+/// Turns the path of a file in an archive into the path it is unpacked to,
+/// relative to the upload folder, with every part of it sanitized like the
+/// name of an uploaded file. None for paths that would leave the folder.
+fn entry_path(name: &str) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for part in name.split(['/', '\\']).filter(|part| !part.is_empty() && *part != ".") {
+        if part == ".." {
+            return None;
+        }
+        path.push(sanitize_name(part).ok()?);
+    }
+    (path.components().count() > 0).then_some(path)
+}
+
+/// The name of each entry of an archive and whether it is a regular file, in
+/// the order they are stored in. Directories, links and the like are never
+/// unpacked: folders are created as the files in them need them.
+fn list_entries(archive: &Path, kind: ArchiveKind) -> io::Result<Vec<(String, bool)>> {
+    let too_many = || io::Error::other(format!("it has more than {} entries", MAX_ENTRIES));
+    let file = File::open(archive)?;
+    match kind {
+        ArchiveKind::Zip => {
+            let mut zip = zip::ZipArchive::new(file).map_err(io::Error::other)?;
+            if zip.len() > MAX_ENTRIES {
+                return Err(too_many());
+            }
+            (0..zip.len())
+                .map(|index| {
+                    let entry = zip.by_index_raw(index).map_err(io::Error::other)?;
+                    Ok((entry.name().to_string(), entry.is_file() && !entry.is_symlink()))
+                })
+                .collect()
+        }
+        ArchiveKind::Tar => tar::Archive::new(file)
+            .entries()?
+            .enumerate()
+            .map(|(index, entry)| {
+                if index >= MAX_ENTRIES {
+                    return Err(too_many());
+                }
+                let entry = entry?;
+                // Names in tar files are bytes, UTF-8 from any recent tar.
+                Ok((
+                    String::from_utf8_lossy(&entry.path_bytes()).to_string(),
+                    entry.header().entry_type().is_file(),
+                ))
+            })
+            .collect(),
+    }
+}
+
+/// Writes an entry out, unless that takes it past `max_bytes` in all. What
+/// an entry says its size is can't be trusted, so the bytes are counted as
+/// they are written.
+fn write_entry(reader: &mut dyn Read, destination: &Path, written: &mut u64, max_bytes: u64) -> io::Result<()> {
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = File::create(destination)?;
+    let allowed = max_bytes.saturating_sub(*written);
+    *written += io::copy(&mut reader.take(allowed.saturating_add(1)), &mut file)?;
+    if *written > max_bytes {
+        return Err(io::Error::other(format!("it unpacks to more than {} MiB", max_bytes / 1024 / 1024)));
+    }
+    Ok(())
+}
+
+/// Unpacks the files of an archive that can be uploaded into `destination`,
+/// keeping the folders they are in. Files of other types, like the logs and
+/// playlists rippers add, are skipped, and so are paths that would end up
+/// outside `destination`.
+///
+/// An archive that unpacks to more than `max_bytes`, the most the upload
+/// may still take, or has more than `MAX_ENTRIES` entries is an error, so a
+/// small archive can't fill the drive the library is on.
+pub fn unpack(
+    archive: &Path,
+    kind: ArchiveKind,
+    destination: &Path,
+    scan_config: &ScanConfig,
+    max_bytes: u64,
+) -> Result<Unpacked, String> {
+    let entries = list_entries(archive, kind)
+        .map_err(|error| format!("Could not read the archive: {}", error))?;
+
+    let mut unpacked = Unpacked::default();
+    let mut targets: Vec<Option<PathBuf>> = Vec::new();
+    for (name, is_file) in &entries {
+        let target = match entry_path(name) {
+            Some(path) if *is_file && is_allowed_file(&path.to_string_lossy(), scan_config) => Some(path),
+            _ => {
+                if *is_file {
+                    unpacked.skipped.push(name.clone());
+                }
+                None
+            }
+        };
+        targets.push(target);
+    }
+
+    let kept: Vec<&PathBuf> = targets.iter().flatten().collect();
+    if kept.is_empty() {
+        return Err("The archive holds no files that can be uploaded".to_string());
+    }
+    let first_folder = |path: &PathBuf| {
+        (path.components().count() > 1)
+            .then(|| path.components().next())
+            .flatten()
+            .map(|component| component.as_os_str().to_owned())
+    };
+    let top_folder = first_folder(kept[0])
+        .filter(|top_folder| kept.iter().all(|path| first_folder(path).as_ref() == Some(top_folder)));
+    if let Some(top_folder) = top_folder {
+        for path in targets.iter_mut().flatten() {
+            *path = path.strip_prefix(&top_folder).unwrap().to_path_buf();
+        }
+        unpacked.top_folder = Some(top_folder.to_string_lossy().to_string());
+    }
+
+    let failed = |error: io::Error| format!("Could not unpack the archive: {}", error);
+    let file = File::open(archive).map_err(failed)?;
+    match kind {
+        ArchiveKind::Zip => {
+            let mut zip = zip::ZipArchive::new(file).map_err(|error| failed(io::Error::other(error)))?;
+            for (index, target) in targets.iter().enumerate() {
+                let Some(target) = target else {
+                    continue;
+                };
+                let mut entry = zip.by_index(index).map_err(|error| failed(io::Error::other(error)))?;
+                write_entry(&mut entry, &destination.join(target), &mut unpacked.bytes, max_bytes).map_err(failed)?;
+                unpacked.files += 1;
+            }
+        }
+        ArchiveKind::Tar => {
+            let mut tar = tar::Archive::new(file);
+            for (entry, target) in tar.entries().map_err(failed)?.zip(&targets) {
+                let mut entry = entry.map_err(failed)?;
+                let Some(target) = target else {
+                    continue;
+                };
+                write_entry(&mut entry, &destination.join(target), &mut unpacked.bytes, max_bytes).map_err(failed)?;
+                unpacked.files += 1;
+            }
+        }
+    }
+    Ok(unpacked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+    use std::io::Write;
+
+    /// A temp folder with an empty `unpacked` folder to unpack into.
+    fn temp_folder(name: &str) -> TempDir {
+        let folder = TempDir::new(name);
+        fs::create_dir_all(folder.path.join("unpacked")).unwrap();
+        folder
+    }
+
+    fn unpacked_files(folder: &TempDir) -> Vec<String> {
+        fn walk(dir: &Path, base: &Path, files: &mut Vec<String>) {
+            for entry in fs::read_dir(dir).unwrap().flatten() {
+                if entry.path().is_dir() {
+                    walk(&entry.path(), base, files);
+                } else {
+                    let relative = entry.path().strip_prefix(base).unwrap().to_path_buf();
+                    files.push(relative.to_string_lossy().replace('\\', "/"));
+                }
+            }
+        }
+        let mut files = Vec::new();
+        walk(&folder.path.join("unpacked"), &folder.path.join("unpacked"), &mut files);
+        files.sort();
+        files
+    }
+
+    fn write_zip(path: &Path, files: &[&str]) {
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        for name in files {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default()).unwrap();
+            zip.write_all(b"audio").unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    fn write_tar(path: &Path, files: &[&str]) {
+        let mut tar = tar::Builder::new(File::create(path).unwrap());
+        for name in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(5);
+            header.set_mode(0o644);
+            // set_path refuses `..`, which is exactly what has to be tested
+            header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_cksum();
+            tar.append(&header, &b"audio"[..]).unwrap();
+        }
+        tar.finish().unwrap();
+    }
+
+    #[test]
+    fn recognizes_archives_by_extension() {
+        assert_eq!(ArchiveKind::for_file_name("Album.ZIP"), Some(ArchiveKind::Zip));
+        assert_eq!(ArchiveKind::for_file_name("Album.tar"), Some(ArchiveKind::Tar));
+        assert_eq!(ArchiveKind::for_file_name("01.flac"), None);
+    }
+
+    #[test]
+    fn unpacks_albums_with_their_subfolders() {
+        let folder = temp_folder("unpack-zip");
+        let archive = folder.path.join("album.zip");
+        write_zip(&archive, &["CD1/01.flac", "CD2/01.flac", "cover.jpg", "rip.log"]);
+
+        let unpacked = unpack(&archive, ArchiveKind::Zip, &folder.path.join("unpacked"), &ScanConfig::default(), u64::MAX)
+            .unwrap();
+
+        assert_eq!(unpacked_files(&folder), vec!["CD1/01.flac", "CD2/01.flac", "cover.jpg"]);
+        assert_eq!(unpacked.files, 3);
+        assert_eq!(unpacked.skipped, vec!["rip.log".to_string()]);
+        assert_eq!(unpacked.top_folder, None);
+    }
+
+    #[test]
+    fn leaves_out_the_folder_everything_is_in() {
+        let folder = temp_folder("unpack-top-folder");
+        let archive = folder.path.join("album.tar");
+        write_tar(&archive, &["Album/01.flac", "Album/Bonus/01.flac"]);
+
+        let unpacked = unpack(&archive, ArchiveKind::Tar, &folder.path.join("unpacked"), &ScanConfig::default(), u64::MAX)
+            .unwrap();
+
+        assert_eq!(unpacked_files(&folder), vec!["01.flac", "Bonus/01.flac"]);
+        assert_eq!(unpacked.top_folder, Some("Album".to_string()));
+    }
+
+    #[test]
+    fn never_writes_outside_the_destination() {
+        let folder = temp_folder("unpack-traversal");
+        for (archive, kind) in [("evil.zip", ArchiveKind::Zip), ("evil.tar", ArchiveKind::Tar)] {
+            let archive = folder.path.join(archive);
+            let files = ["../escaped.flac", "Album/../../escaped.flac", "Album/.hidden.flac", "Album/01.flac"];
+            match kind {
+                ArchiveKind::Zip => write_zip(&archive, &files),
+                ArchiveKind::Tar => write_tar(&archive, &files),
+            }
+
+            let unpacked = unpack(&archive, kind, &folder.path.join("unpacked"), &ScanConfig::default(), u64::MAX)
+                .unwrap();
+
+            assert_eq!(unpacked.skipped.len(), 3);
+            assert!(!folder.path.join("escaped.flac").exists());
+        }
+        assert_eq!(unpacked_files(&folder), vec!["01.flac"]);
+    }
+
+    #[test]
+    fn archives_that_unpack_to_too_much_are_an_error() {
+        let folder = temp_folder("unpack-too-much");
+        let archive = folder.path.join("bomb.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&archive).unwrap());
+        zip.start_file("01.flac", zip::write::SimpleFileOptions::default()).unwrap();
+        zip.write_all(&[0; 4096]).unwrap();
+        zip.finish().unwrap();
+        // far smaller packed than unpacked, as such an archive is
+        assert!(fs::metadata(&archive).unwrap().len() < 1024);
+
+        let error = unpack(&archive, ArchiveKind::Zip, &folder.path.join("unpacked"), &ScanConfig::default(), 1024)
+            .unwrap_err();
+        assert!(error.contains("unpacks to more than"), "{}", error);
+        assert!(fs::metadata(folder.path.join("unpacked/01.flac")).unwrap().len() <= 1025);
+        let unpacked = unpack(&archive, ArchiveKind::Zip, &folder.path.join("unpacked"), &ScanConfig::default(), 4096)
+            .unwrap();
+        assert_eq!(unpacked.bytes, 4096);
+
+        let names: Vec<String> = (0..=MAX_ENTRIES).map(|index| format!("{}.txt", index)).collect();
+        let archive = folder.path.join("entries.zip");
+        write_zip(&archive, &names.iter().map(String::as_str).collect::<Vec<&str>>());
+        let error = unpack(&archive, ArchiveKind::Zip, &folder.path.join("unpacked"), &ScanConfig::default(), u64::MAX)
+            .unwrap_err();
+        assert!(error.contains("entries"), "{}", error);
+    }
+
+    #[test]
+    fn an_archive_without_anything_to_upload_is_an_error() {
+        let folder = temp_folder("unpack-nothing");
+        let archive = folder.path.join("notes.zip");
+        write_zip(&archive, &["notes.txt"]);
+
+        assert!(unpack(&archive, ArchiveKind::Zip, &folder.path.join("unpacked"), &ScanConfig::default(), u64::MAX).is_err());
+        assert!(unpack(&folder.path.join("missing.zip"), ArchiveKind::Zip, &folder.path, &ScanConfig::default(), u64::MAX).is_err());
+    }
+}
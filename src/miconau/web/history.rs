@@ -0,0 +1,66 @@
+use axum::{extract::{Query, State}, Json};
+use serde::{Deserialize, Serialize};
+use crate::player::{Play, RecentSource};
+use super::ServerState;
+
+/// How many plays a page has when the request doesn't say.
+const DEFAULT_PAGE_SIZE: usize = 50;
+
+fn default_page_size() -> usize {
+    DEFAULT_PAGE_SIZE
+}
+
+#[derive(Deserialize)]
+pub struct PageQuery {
+    #[serde(default)]
+    offset: usize,
+    #[serde(default = "default_page_size")]
+    limit: usize,
+}
+
+#[derive(Serialize)]
+pub struct HistoryPage {
+    total: usize,
+    plays: Vec<Play>,
+}
+
+/// What has been played, the latest first, a page at a time.
+pub async fn get_history(
+    State(server_state): State<ServerState>,
+    Query(query): Query<PageQuery>,
+) -> Json<HistoryPage> {
+    let player = server_state.player.lock().await;
+    let (total, plays) = player.play_history.page(query.offset, query.limit);
+    Json(HistoryPage { total, plays })
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecentPlaylist {
+    #[serde(flatten)]
+    recent: RecentSource,
+    /// Where the playlist is in the library now, to play it again. None
+    /// once it is gone, or while its library folder is away.
+    index: Option<usize>,
+}
+
+/// The playlists played, the last played first.
+pub async fn get_recent_playlists(
+    State(server_state): State<ServerState>,
+    Query(query): Query<PageQuery>,
+) -> Json<Vec<RecentPlaylist>> {
+    let player = server_state.player.lock().await;
+    let recent = player.play_history
+        .recent_playlists()
+        .into_iter()
+        .skip(query.offset)
+        .take(query.limit)
+        .map(|recent| {
+            let index = player.library.playlists
+                .iter()
+                .position(|playlist| recent.is_playlist(&playlist.title));
+            RecentPlaylist { recent, index }
+        })
+        .collect();
+    Json(recent)
+}
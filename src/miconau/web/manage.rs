@@ -0,0 +1,179 @@
+use axum::{extract::{Multipart, Path, State}, http::StatusCode, Json};
+use std::path::PathBuf;
+use tokio::io::AsyncWriteExt;
+use crate::player::Player;
+use super::upload::{sanitize_file_name, sanitize_name, StagingFolder};
+use super::ServerState;
+
+/// Turns down changes to files that are playing or about to, see
+/// `Player::is_in_use`.
+pub(super) fn ensure_not_in_use(player: &Player, paths: &[PathBuf]) -> Result<(), (StatusCode, String)> {
+    if player.is_in_use(paths) {
+        return Err((
+            StatusCode::CONFLICT,
+            "Files of this playlist are playing or queued; stop playback first".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+pub(super) fn ensure_track_exists(player: &Player, index: usize, track_index: usize) -> Result<(), (StatusCode, String)> {
+    let playlist = player.library.playlists.get(index)
+        .ok_or((StatusCode::NOT_FOUND, format!("Playlist {} not found", index)))?;
+    if track_index >= playlist.tracks.len() {
+        return Err((StatusCode::NOT_FOUND, format!("Track {} not found", track_index)));
+    }
+    Ok(())
+}
+
+/// Deletes a playlist, moving its files to the trash folder of its library
+/// root.
+pub async fn delete_playlist(
+    State(server_state): State<ServerState>,
+    Path(index): Path<usize>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut player = server_state.player.lock().await;
+    if index >= player.library.playlists.len() {
+        return Err((StatusCode::NOT_FOUND, format!("Playlist {} not found", index)));
+    }
+    player.library.ensure_not_smart(index)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    ensure_not_in_use(&player, &player.library.playlist_files(index))?;
+    player.library.delete_playlist(index)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    player.notify_library_updated();
+    Ok(StatusCode::OK)
+}
+
+#[derive(serde::Deserialize)]
+pub struct RenameRequest {
+    name: String,
+}
+
+/// Renames the folder of a playlist.
+pub async fn rename_playlist(
+    State(server_state): State<ServerState>,
+    Path(index): Path<usize>,
+    Json(request): Json<RenameRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let name = sanitize_name(&request.name).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let mut player = server_state.player.lock().await;
+    if index >= player.library.playlists.len() {
+        return Err((StatusCode::NOT_FOUND, format!("Playlist {} not found", index)));
+    }
+    let folder = player.library.playlist_folder(index)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    ensure_not_in_use(&player, &[folder])?;
+    player.library.rename_playlist(index, &name)
+        .map_err(|e| (StatusCode::CONFLICT, e))?;
+    player.notify_library_updated();
+    Ok(StatusCode::OK)
+}
+
+/// Deletes a single track, moving its file to the trash.
+pub async fn delete_track(
+    State(server_state): State<ServerState>,
+    Path((index, track_index)): Path<(usize, usize)>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut player = server_state.player.lock().await;
+    ensure_track_exists(&player, index, track_index)?;
+    let file = player.library.playlists[index].tracks[track_index].filename.clone();
+    ensure_not_in_use(&player, &[file])?;
+    player.library.delete_track(index, track_index)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    player.notify_library_updated();
+    Ok(StatusCode::OK)
+}
+
+/// Adds an audio file to a playlist, sent as the `file` field of a multipart
+/// form. Like an upload, it is written to a staging folder before it is moved
+/// into place, and the player is only locked again once it is all there.
+pub async fn add_track(
+    State(server_state): State<ServerState>,
+    Path(index): Path<usize>,
+    mut multipart: Multipart,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let player = server_state.player.lock().await;
+    if index >= player.library.playlists.len() {
+        return Err((StatusCode::NOT_FOUND, format!("Playlist {} not found", index)));
+    }
+    let folder = player.library.playlist_folder(index)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let root = player.library.root_of(&folder)
+        .ok_or((StatusCode::INTERNAL_SERVER_ERROR, format!("{:?} is not in the library", folder)))?
+        .folder
+        .clone();
+    let playlist_id = player.library.playlists[index].id;
+    let scan_config = player.library.scan_config.clone();
+    drop(player);
+
+    let mut field = loop {
+        let field = multipart.next_field().await
+            .map_err(|e| (e.status(), format!("Error parsing multipart: {}", e)))?
+            .ok_or((StatusCode::BAD_REQUEST, "A file is required".to_string()))?;
+        if field.name() == Some("file") {
+            break field;
+        }
+    };
+    let file_name = sanitize_file_name(field.file_name().unwrap_or(""))
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let extension = std::path::Path::new(&file_name)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if !scan_config.is_audio_extension(&extension) {
+        return Err((StatusCode::UNSUPPORTED_MEDIA_TYPE, format!("Only audio files can be added: {}", file_name)));
+    }
+
+    let write_error = |e: std::io::Error| {
+        (StatusCode::INTERNAL_SERVER_ERROR, format!("Error writing {}: {}", file_name, e))
+    };
+    let staging = StagingFolder::new(&root).map_err(write_error)?;
+    let path = staging.path().join(&file_name);
+    let mut file = tokio::fs::File::create(&path).await.map_err(write_error)?;
+    while let Some(chunk) = field.chunk().await
+        .map_err(|e| (e.status(), format!("Error reading {}: {}", file_name, e)))? {
+        file.write_all(&chunk).await.map_err(write_error)?;
+    }
+    file.flush().await.map_err(write_error)?;
+    drop(file);
+
+    // The playlist may have moved while the file came in.
+    let mut player = server_state.player.lock().await;
+    let index = player.library.playlists.iter()
+        .position(|playlist| playlist.id == playlist_id)
+        .ok_or((StatusCode::NOT_FOUND, "The playlist is gone".to_string()))?;
+    player.library.add_track(index, &path, &file_name)
+        .map_err(|e| (StatusCode::CONFLICT, e))?;
+    player.notify_library_updated();
+    Ok(StatusCode::OK)
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MoveTrackRequest {
+    from_playlist: usize,
+    track_index: usize,
+}
+
+/// Moves a track of another playlist into this one.
+pub async fn move_track(
+    State(server_state): State<ServerState>,
+    Path(index): Path<usize>,
+    Json(request): Json<MoveTrackRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut player = server_state.player.lock().await;
+    if index >= player.library.playlists.len() {
+        return Err((StatusCode::NOT_FOUND, format!("Playlist {} not found", index)));
+    }
+    ensure_track_exists(&player, request.from_playlist, request.track_index)?;
+    let file = player.library.playlists[request.from_playlist]
+        .tracks[request.track_index]
+        .filename
+        .clone();
+    ensure_not_in_use(&player, &[file])?;
+    player.library.move_track(request.from_playlist, request.track_index, index)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    player.notify_library_updated();
+    Ok(StatusCode::OK)
+}
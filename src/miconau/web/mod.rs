@@ -0,0 +1,653 @@
+mod alarms;
+mod archive;
+mod history;
+mod manage;
+mod ratings;
+mod search;
+mod streams;
+mod tags;
+mod upload;
+
+pub use upload::DEFAULT_MAX_UPLOAD_MIB;
+
+use axum::{extract::{Path, Query, Request, State}, http::{header, HeaderMap, StatusCode}, middleware::{self, Next}, response::{sse::{Event, KeepAlive}, Response, Sse}, routing::{delete, get, post, put}, Json, Router};
+use serde::{Serialize};
+use tokio::sync::Mutex;
+use tower::ServiceBuilder;
+use tower_http::services::ServeDir;
+use std::{env::current_exe, path::PathBuf, sync::{Arc}, time::Duration};
+use crate::{alarms::Alarms, library::{CoverSource, Rating, Stream as AudioStream, ThumbnailCache, ThumbnailKey}, player::{Player, PlayerState, SleepMode, DEFAULT_FADE}};
+use std::error::Error;
+use axum::response::IntoResponse;
+use futures_util::stream::{Stream};
+use std::convert::Infallible;
+use futures_util::stream::StreamExt;
+use tokio_stream::wrappers::BroadcastStream;
+use axum::extract::DefaultBodyLimit;
+
+#[derive(Serialize)]
+struct StreamInfo {
+    name: String,
+    url: String,
+    fallback_urls: Vec<String>,
+    logo_svg: Option<String>,
+    index: usize,
+    /// Whether the station answered the last health check. Null when streams
+    /// aren't checked, or not yet.
+    up: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct PlaylistInfo {
+    name: String,
+    index: usize,
+    has_cover: bool,
+    rating: Rating,
+    /// Smart playlists can't be renamed or deleted, and tracks can't be
+    /// moved out of them: they have no files of their own.
+    smart: bool,
+}
+
+#[derive(Serialize)]
+struct TrackInfo {
+    title: String,
+    artist: Option<String>,
+    index: usize,
+    has_cover: bool,
+    rating: Rating,
+}
+
+#[derive(Serialize)]
+struct LibraryRootInfo {
+    index: usize,
+    name: String,
+    available: bool,
+}
+
+#[derive(Serialize)]
+struct QueueItemInfo {
+    playlist_name: String,
+    track_title: String,
+    track_artist: Option<String>,
+    index: usize,
+    stream: bool,
+}
+
+/// How much memory the rendered cover thumbnails may take up.
+const THUMBNAIL_CACHE_BYTES: usize = 32 * 1024 * 1024;
+
+/// The largest thumbnail that can be asked for. Anything bigger is as good as
+/// the original, and scaling to it would only cost time.
+const MAX_THUMBNAIL_SIZE: u32 = 1024;
+
+#[derive(Clone)]
+struct ServerState {
+    player: Arc<Mutex<Player>>,
+    alarms: Arc<Mutex<Alarms>>,
+    /// A std mutex: it is only ever held for a lookup or an insert, never
+    /// across an await.
+    thumbnails: Arc<std::sync::Mutex<ThumbnailCache>>,
+    /// The most an upload may be, in bytes, which is also the most its
+    /// archives may unpack to.
+    max_upload_bytes: u64,
+}
+
+
+async fn sse_handler(
+    State(server_state): State<ServerState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let player = server_state.player.lock().await;
+    let receiver = player.event_transmitter.subscribe();
+    let event_stream = BroadcastStream::new(receiver)
+        .filter_map(|result| async move {
+            match result {
+                Ok(event) => {
+                    Some(Ok(Event::default().json_data(&event).unwrap()))
+                }
+                Err(_) => None,
+            }
+        });
+    Sse::new(event_stream).keep_alive(KeepAlive::default())
+}
+
+
+fn get_static_path() -> PathBuf {
+    let mut static_path = current_exe().unwrap();
+    static_path.pop();
+    static_path.pop();
+    static_path.pop();
+    static_path.push("src");
+    static_path.push("miconau");
+    static_path.push("static");
+    static_path
+}
+
+
+async fn get_streams(
+    State(server_state): State<ServerState>
+) -> Json<Vec<StreamInfo>> {
+    let player = server_state.player.lock().await;
+    let streams: Vec<StreamInfo> = player.library.streams
+        .iter()
+        .enumerate()
+        .map(|(index, stream)| StreamInfo {
+            name: stream.name.clone(),
+            url: stream.url.clone(),
+            fallback_urls: stream.fallback_urls.clone(),
+            logo_svg: stream.logo_svg.clone(),
+            index,
+            up: stream.up,
+        })
+        .collect();
+    Json(streams)
+}
+
+async fn get_stream_logo(
+    State(server_state): State<ServerState>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    let player = server_state.player.lock().await;
+    let stream: Option<&AudioStream> = player.library.streams
+        .iter()
+        .find(|&x| x.name == *name);
+    println!("Getting logo for stream: {}", name);
+    if let Some(stream) = stream {
+        if let Some(logo_svg) = &stream.logo_svg {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CONTENT_TYPE, "image/svg+xml".parse().unwrap());
+            // Logos can be uploaded now, and an SVG opened on its own runs
+            // whatever scripts it holds.
+            headers.insert(
+                header::CONTENT_SECURITY_POLICY,
+                "default-src 'none'; style-src 'unsafe-inline'".parse().unwrap(),
+            );
+            return Ok((
+                headers,
+                logo_svg.clone(),
+            ))
+        }
+    }
+    Err(StatusCode::NOT_FOUND)
+}
+
+#[derive(serde::Deserialize)]
+struct PlaylistQuery {
+    /// Text the playlist list is filtered by. Filtering happens here because
+    /// the tracks it searches are only loaded into the browser one playlist at
+    /// a time.
+    #[serde(default)]
+    filter: String,
+    /// Whether words a letter or two off match too. The playlists then come
+    /// best match first rather than in the order of the library.
+    #[serde(default)]
+    typos: bool,
+}
+
+async fn get_playlists(
+    State(server_state): State<ServerState>,
+    Query(query): Query<PlaylistQuery>,
+) -> Json<Vec<PlaylistInfo>> {
+    let player = server_state.player.lock().await;
+    // Numbered before filtering, so an index keeps pointing at the same
+    // playlist no matter what the list is filtered by.
+    let mut matches = player.library.filter_playlists(&query.filter, query.typos);
+    if query.typos {
+        matches.sort_by(|(a, _), (b, _)| b.cmp(a));
+    }
+    let playlists: Vec<PlaylistInfo> = matches
+        .into_iter()
+        .map(|(_, index)| {
+            let playlist = &player.library.playlists[index];
+            PlaylistInfo {
+                name: playlist.title.clone(),
+                index,
+                has_cover: playlist.cover_source.is_some(),
+                rating: player.library.ratings.playlist(&playlist.title),
+                smart: playlist.smart,
+            }
+        })
+        .collect();
+    Json(playlists)
+}
+
+async fn get_playlist_tracks(
+    State(server_state): State<ServerState>,
+    Path(index): Path<usize>,
+) -> Result<Json<Vec<TrackInfo>>, StatusCode> {
+    let player = server_state.player.lock().await;
+    if index >= player.library.playlists.len() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let playlist = &player.library.playlists[index];
+    let tracks: Vec<TrackInfo> = playlist.tracks
+        .iter()
+        .enumerate()
+        .map(|(track_index, track)| TrackInfo {
+            title: track.display_title(),
+            artist: track.artist.clone(),
+            index: track_index,
+            has_cover: track.has_cover_art || playlist.cover_source.is_some(),
+            rating: player.library.ratings.track(&track.location()),
+        })
+        .collect();
+    Ok(Json(tracks))
+}
+
+#[derive(serde::Deserialize)]
+struct CoverQuery {
+    /// Longest edge in pixels to scale the cover down to. Without it, the
+    /// cover is served as it is stored, which can be several megabytes.
+    size: Option<u32>,
+}
+
+/// Serves a cover, scaled down to `size` if one is given.
+async fn serve_cover(
+    server_state: &ServerState,
+    cover_source: CoverSource,
+    size: Option<u32>,
+) -> Result<(HeaderMap, Vec<u8>), StatusCode> {
+    let size = size.map(|size| size.clamp(1, MAX_THUMBNAIL_SIZE));
+    let thumbnails = server_state.thumbnails.clone();
+
+    // Reading the cover hits the disk, which is slow for a library on an
+    // external drive, and scaling it is slow everywhere, so keep both off the
+    // runtime threads.
+    let (data, mime) = tokio::task::spawn_blocking(move || {
+        let key = size.map(|size| ThumbnailKey::new(&cover_source, size));
+        if let Some(key) = &key {
+            if let Some(thumbnail) = thumbnails.lock().unwrap().get(key) {
+                return Some((thumbnail.to_vec(), "image/jpeg".to_string()));
+            }
+        }
+
+        let (data, mime) = crate::library::read_cover(&cover_source)?;
+        let (Some(key), Some(size)) = (key, size) else {
+            return Some((data, mime));
+        };
+        match crate::library::thumbnail(&data, size) {
+            Some(thumbnail) => {
+                thumbnails.lock().unwrap().insert(key, Arc::new(thumbnail.clone()));
+                Some((thumbnail, "image/jpeg".to_string()))
+            }
+            None => Some((data, mime)),
+        }
+    })
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        mime.parse().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+    );
+    // Covers now come off the disk on every request and the playlist list is
+    // re-rendered often, so let the browser hold on to them.
+    headers.insert(
+        header::CACHE_CONTROL,
+        "private, max-age=3600".parse().unwrap(),
+    );
+    Ok((headers, data))
+}
+
+async fn get_playlist_cover(
+    State(server_state): State<ServerState>,
+    Path(index): Path<usize>,
+    Query(query): Query<CoverQuery>,
+) -> Result<impl IntoResponse, StatusCode> {
+    // Take only the path from the library, so the player lock is not held
+    // while the file is read.
+    let cover_source = {
+        let player = server_state.player.lock().await;
+        player.library.playlists
+            .get(index)
+            .ok_or(StatusCode::NOT_FOUND)?
+            .cover_source
+            .clone()
+            .ok_or(StatusCode::NOT_FOUND)?
+    };
+    serve_cover(&server_state, cover_source, query.size).await
+}
+
+/// The cover of a single track: its own embedded artwork, or the cover of the
+/// playlist it is in for tracks without one.
+async fn get_track_cover(
+    State(server_state): State<ServerState>,
+    Path((index, track_index)): Path<(usize, usize)>,
+    Query(query): Query<CoverQuery>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let cover_source = {
+        let player = server_state.player.lock().await;
+        let playlist = player.library.playlists
+            .get(index)
+            .ok_or(StatusCode::NOT_FOUND)?;
+        let track = playlist.tracks
+            .get(track_index)
+            .ok_or(StatusCode::NOT_FOUND)?;
+        if track.has_cover_art {
+            CoverSource::Embedded(track.filename.clone())
+        } else {
+            playlist.cover_source.clone().ok_or(StatusCode::NOT_FOUND)?
+        }
+    };
+    serve_cover(&server_state, cover_source, query.size).await
+}
+
+async fn get_state(
+    State(server_state): State<ServerState>,
+) -> Json<PlayerState> {
+    let player = server_state.player.lock().await;
+    Json(player.state.clone())
+}
+
+async fn play_stream(
+    Path(index): Path<usize>,
+    State(server_state): State<ServerState>,
+) -> Result<StatusCode, StatusCode> {
+    let mut player = server_state.player.lock().await;
+    player.play_stream(index);
+    Ok(StatusCode::OK)
+}
+
+async fn play_playlist(
+    State(server_state): State<ServerState>,
+    Path(index): Path<usize>,
+) -> Result<StatusCode, StatusCode> {
+    let mut player = server_state.player.lock().await;
+    player.play_playlist(index);
+    Ok(StatusCode::OK)
+}
+
+async fn play_playlist_track(
+    State(server_state): State<ServerState>,
+    Path((index, track_index)): Path<(usize, usize)>,
+) -> Result<StatusCode, StatusCode> {
+    let mut player = server_state.player.lock().await;
+    player.play_playlist_track(index, track_index);
+    Ok(StatusCode::OK)
+}
+
+async fn play_pause(
+    State(server_state): State<ServerState>,
+) -> Result<StatusCode, StatusCode> {
+    let mut player = server_state.player.lock().await;
+    player.play_pause();
+    Ok(StatusCode::OK)
+}
+
+async fn stop(
+    State(server_state): State<ServerState>,
+) -> Result<StatusCode, StatusCode> {
+    let mut player = server_state.player.lock().await;
+    player.stop();
+    Ok(StatusCode::OK)
+}
+
+#[derive(serde::Deserialize)]
+struct SleepTimerRequest {
+    #[serde(default = "default_sleep_mode")]
+    mode: SleepMode,
+    minutes: Option<u64>,
+    fade_seconds: Option<u64>,
+}
+
+fn default_sleep_mode() -> SleepMode {
+    SleepMode::Time
+}
+
+/// Sets the sleep timer, replacing any that is running.
+async fn set_sleep_timer(
+    State(server_state): State<ServerState>,
+    Json(payload): Json<SleepTimerRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let fade = payload.fade_seconds.map_or(DEFAULT_FADE, Duration::from_secs);
+    let mut player = server_state.player.lock().await;
+    player.set_sleep_timer(payload.mode, payload.minutes, fade)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    Ok(StatusCode::OK)
+}
+
+async fn cancel_sleep_timer(
+    State(server_state): State<ServerState>,
+) -> StatusCode {
+    let mut player = server_state.player.lock().await;
+    player.cancel_sleep_timer();
+    StatusCode::OK
+}
+
+async fn next_track(
+    State(server_state): State<ServerState>,
+) -> Result<StatusCode, StatusCode> {
+    let mut player = server_state.player.lock().await;
+    player.play_next_track();
+    Ok(StatusCode::OK)
+}
+
+async fn previous_track(
+    State(server_state): State<ServerState>,
+) -> Result<StatusCode, StatusCode> {
+    let mut player = server_state.player.lock().await;
+    player.play_previous_track();
+    Ok(StatusCode::OK)
+}
+
+/// The library roots, so an upload can pick which one to go to.
+async fn get_library_roots(
+    State(server_state): State<ServerState>,
+) -> Json<Vec<LibraryRootInfo>> {
+    let player = server_state.player.lock().await;
+    let roots: Vec<LibraryRootInfo> = player.library.roots
+        .iter()
+        .enumerate()
+        .map(|(index, root)| LibraryRootInfo {
+            index,
+            name: root.label.clone().unwrap_or_else(|| root.folder.to_string_lossy().to_string()),
+            available: root.is_available(),
+        })
+        .collect();
+    Json(roots)
+}
+
+async fn get_queue(
+    State(server_state): State<ServerState>,
+) -> Json<Vec<QueueItemInfo>> {
+    let player = server_state.player.lock().await;
+    let queue: Vec<QueueItemInfo> = player.queue
+        .iter()
+        .enumerate()
+        .map(|(index, item)| QueueItemInfo {
+            playlist_name: item.playlist_name.clone(),
+            track_title: item.track_title.clone(),
+            track_artist: item.track_artist.clone(),
+            index,
+            stream: item.stream,
+        })
+        .collect();
+    Json(queue)
+}
+
+#[derive(serde::Deserialize)]
+struct AddToQueueRequest {
+    playlist_index: usize,
+    track_index: usize,
+    /// Whether the track plays next, at the head of the queue, rather than
+    /// after everything queued.
+    #[serde(default)]
+    next: bool,
+}
+
+async fn add_to_queue(
+    State(server_state): State<ServerState>,
+    Json(payload): Json<AddToQueueRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut player = server_state.player.lock().await;
+    player.add_to_queue(payload.playlist_index, payload.track_index, payload.next)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    Ok(StatusCode::OK)
+}
+
+#[derive(serde::Deserialize)]
+struct EnqueueQuery {
+    #[serde(default)]
+    next: bool,
+}
+
+async fn add_playlist_to_queue(
+    State(server_state): State<ServerState>,
+    Path(index): Path<usize>,
+    Query(query): Query<EnqueueQuery>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut player = server_state.player.lock().await;
+    player.add_playlist_to_queue(index, query.next)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    Ok(StatusCode::OK)
+}
+
+async fn add_stream_to_queue(
+    State(server_state): State<ServerState>,
+    Path(index): Path<usize>,
+    Query(query): Query<EnqueueQuery>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut player = server_state.player.lock().await;
+    player.add_stream_to_queue(index, query.next)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    Ok(StatusCode::OK)
+}
+
+#[derive(serde::Deserialize)]
+struct MoveInQueueRequest {
+    to: usize,
+}
+
+async fn move_in_queue(
+    State(server_state): State<ServerState>,
+    Path(index): Path<usize>,
+    Json(payload): Json<MoveInQueueRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut player = server_state.player.lock().await;
+    player.move_in_queue(index, payload.to)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    Ok(StatusCode::OK)
+}
+
+async fn jump_to_queue_item(
+    State(server_state): State<ServerState>,
+    Path(index): Path<usize>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut player = server_state.player.lock().await;
+    player.jump_to_queue_item(index)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    Ok(StatusCode::OK)
+}
+
+async fn remove_from_queue(
+    State(server_state): State<ServerState>,
+    Path(index): Path<usize>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut player = server_state.player.lock().await;
+    player.remove_from_queue(index)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    Ok(StatusCode::OK)
+}
+
+async fn clear_queue(
+    State(server_state): State<ServerState>,
+) -> StatusCode {
+    let mut player = server_state.player.lock().await;
+    player.clear_queue();
+    StatusCode::OK
+}
+
+
+async fn disable_browser_cache(request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    response.headers_mut().insert(
+        "Cache-Control",
+        "no-cache, no-store, must-revalidate".parse().unwrap(),
+    );
+    response.headers_mut().insert("Pragma", "no-cache".parse().unwrap());
+    response.headers_mut().insert("Expires", "0".parse().unwrap());
+    response
+}
+
+pub async fn start_server(
+    player_arc: Arc<Mutex<Player>>,
+    alarms: Arc<Mutex<Alarms>>,
+    address: String,
+    max_upload_mib: usize,
+) -> Result<(), Box<dyn Error>> {
+    let static_path = get_static_path();
+
+    let api_routes = Router::new()
+        .route("/streams", get(get_streams).post(streams::add_stream))
+        .route("/stream/{index}", put(streams::update_stream).delete(streams::delete_stream))
+        .route("/stream/{index}/move", post(streams::move_stream))
+        .route("/stream/{index}/logo", put(streams::set_stream_logo))
+        .route("/stream/{index}/history", get(streams::get_stream_history))
+        .route("/stream-logo/{name}", get(get_stream_logo))
+        .route("/playlists", get(get_playlists))
+        .route("/search", get(search::search))
+        .route("/playlist/{index}", delete(manage::delete_playlist))
+        .route("/playlist/{index}/rename", post(manage::rename_playlist))
+        .route("/playlist/{index}/tracks", get(get_playlist_tracks))
+        .route("/playlist/{index}/track", post(manage::add_track))
+        .route("/playlist/{index}/track/move", post(manage::move_track))
+        .route("/playlist/{index}/track/{track_index}", delete(manage::delete_track))
+        .route("/playlist/{index}/tags", put(tags::set_playlist_tags))
+        .route("/playlist/{index}/tags/from-filenames", post(tags::tag_from_filenames))
+        .route("/playlist/{index}/track/{track_index}/tags", get(tags::get_track_tags).put(tags::set_track_tags))
+        .route("/playlist/{index}/cover", get(get_playlist_cover).put(tags::set_playlist_cover))
+        .route("/playlist/{index}/track/{track_index}/cover", get(get_track_cover).put(tags::set_track_cover))
+        .route("/play/stream/{index}", post(play_stream))
+        .route("/play/playlist/{index}", post(play_playlist))
+        .route("/play/playlist/{index}/{track_index}", post(play_playlist_track))
+        .route("/playlist/{index}/rating", put(ratings::rate_playlist))
+        .route("/playlist/{index}/track/{track_index}/rating", put(ratings::rate_track))
+        .route("/favourites", get(ratings::get_favourites))
+        .route("/play/favourites", post(ratings::play_favourites))
+        .route("/play/favourites/{track_index}", post(ratings::play_favourites_track))
+        .route("/love", post(ratings::love_current_track))
+        .route("/play/pause", post(play_pause))
+        .route("/stop", post(stop))
+        .route("/sleep-timer", post(set_sleep_timer).delete(cancel_sleep_timer))
+        .route("/alarms", get(alarms::get_alarms).post(alarms::add_alarm))
+        .route("/alarm/{index}", put(alarms::update_alarm).delete(alarms::delete_alarm))
+        .route("/next", post(next_track))
+        .route("/previous", post(previous_track))
+        .route("/library-roots", get(get_library_roots))
+        .route("/upload-playlist", post(upload::upload_playlist))
+        .route("/queue", get(get_queue))
+        .route("/queue/add", post(add_to_queue))
+        .route("/queue/playlist/{index}", post(add_playlist_to_queue))
+        .route("/queue/stream/{index}", post(add_stream_to_queue))
+        .route("/queue/move/{index}", post(move_in_queue))
+        .route("/queue/play/{index}", post(jump_to_queue_item))
+        .route("/queue/remove/{index}", post(remove_from_queue))
+        .route("/queue/clear", post(clear_queue))
+        .route("/history", get(history::get_history))
+        .route("/history/playlists", get(history::get_recent_playlists))
+        .route("/notifications", get(sse_handler))
+        .route("/state", get(get_state))
+        .layer(DefaultBodyLimit::max(max_upload_mib * 1024 * 1024))
+        .with_state(ServerState {
+            player: player_arc,
+            alarms,
+            thumbnails: Arc::new(std::sync::Mutex::new(
+                ThumbnailCache::new(THUMBNAIL_CACHE_BYTES),
+            )),
+            max_upload_bytes: max_upload_mib as u64 * 1024 * 1024,
+        });
+
+    let static_service = ServiceBuilder::new()
+        .layer(middleware::from_fn(disable_browser_cache))
+        .service(ServeDir::new(static_path));
+
+    let app = Router::new()
+        .nest("/api", api_routes)
+        .fallback_service(static_service);
+
+    println!("Starting HTTP server on http://{}", address);
+    let listener = tokio::net::TcpListener::bind(address).await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use axum::{extract::{Path, State}, http::StatusCode, Json};
+use serde::Serialize;
+use crate::library::{Rating, RatingChange};
+use super::{PlaylistInfo, ServerState};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FavouriteTrack {
+    title: String,
+    artist: Option<String>,
+    /// Where the track is in the library, to rate it.
+    playlist_index: usize,
+    track_index: usize,
+    rating: Rating,
+}
+
+#[derive(Serialize)]
+pub struct Favourites {
+    playlists: Vec<PlaylistInfo>,
+    /// In the order of the favourites playlist, so the position of a track
+    /// is what plays it.
+    tracks: Vec<FavouriteTrack>,
+}
+
+pub async fn rate_track(
+    State(server_state): State<ServerState>,
+    Path((index, track_index)): Path<(usize, usize)>,
+    Json(change): Json<RatingChange>,
+) -> Result<Json<Rating>, (StatusCode, String)> {
+    let mut player = server_state.player.lock().await;
+    if player.library.playlists.get(index).is_none_or(|playlist| track_index >= playlist.tracks.len()) {
+        return Err((StatusCode::NOT_FOUND, format!("Track {} of playlist {} not found", track_index, index)));
+    }
+    let rating = player.library.rate_track(index, track_index, &change)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    player.write_pending_rating_tags();
+    player.notify_rating_changed();
+    // Smart playlists can go by ratings.
+    player.notify_library_updated();
+    Ok(Json(rating))
+}
+
+pub async fn rate_playlist(
+    State(server_state): State<ServerState>,
+    Path(index): Path<usize>,
+    Json(change): Json<RatingChange>,
+) -> Result<Json<Rating>, (StatusCode, String)> {
+    let mut player = server_state.player.lock().await;
+    if index >= player.library.playlists.len() {
+        return Err((StatusCode::NOT_FOUND, format!("Playlist {} not found", index)));
+    }
+    let rating = player.library.rate_playlist(index, &change)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    Ok(Json(rating))
+}
+
+/// The favourite playlists, and the favourite tracks that make up the
+/// favourites playlist.
+pub async fn get_favourites(State(server_state): State<ServerState>) -> Json<Favourites> {
+    let player = server_state.player.lock().await;
+    let library = &player.library;
+    let playlists = library.playlists
+        .iter()
+        .enumerate()
+        .filter(|(_, playlist)| library.ratings.playlist(&playlist.title).favourite)
+        .map(|(index, playlist)| PlaylistInfo {
+            name: playlist.title.clone(),
+            index,
+            has_cover: playlist.cover_source.is_some(),
+            rating: library.ratings.playlist(&playlist.title),
+            smart: playlist.smart,
+        })
+        .collect();
+    let tracks = library.playlists
+        .iter()
+        .enumerate()
+        .filter(|(_, playlist)| !playlist.smart)
+        .flat_map(|(playlist_index, playlist)| {
+            playlist.tracks
+                .iter()
+                .enumerate()
+                .map(move |(track_index, track)| (playlist_index, track_index, track))
+        })
+        .filter_map(|(playlist_index, track_index, track)| {
+            let rating = library.ratings.track(&track.location());
+            rating.favourite.then(|| FavouriteTrack {
+                title: track.display_title(),
+                artist: track.artist.clone(),
+                playlist_index,
+                track_index,
+                rating,
+            })
+        })
+        .collect();
+    Json(Favourites { playlists, tracks })
+}
+
+pub async fn play_favourites(State(server_state): State<ServerState>) -> StatusCode {
+    server_state.player.lock().await.play_favourites();
+    StatusCode::OK
+}
+
+pub async fn play_favourites_track(
+    State(server_state): State<ServerState>,
+    Path(track_index): Path<usize>,
+) -> StatusCode {
+    server_state.player.lock().await.play_favourites_track(track_index);
+    StatusCode::OK
+}
+
+/// Toggles whether the track playing is a favourite, like the MIDI key.
+pub async fn love_current_track(
+    State(server_state): State<ServerState>,
+) -> Result<Json<Rating>, (StatusCode, String)> {
+    let mut player = server_state.player.lock().await;
+    let rating = player.love_current_track()
+        .map_err(|e| (StatusCode::CONFLICT, e))?;
+    Ok(Json(rating))
+}
@@ -0,0 +1,30 @@
+use axum::{extract::{Query, State}, Json};
+use serde::Deserialize;
+use crate::library::SearchResults;
+use super::ServerState;
+
+/// How many tracks, playlists and streams a search returns at most when the
+/// request doesn't say.
+const DEFAULT_LIMIT: usize = 20;
+
+fn default_limit() -> usize {
+    DEFAULT_LIMIT
+}
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    #[serde(default)]
+    q: String,
+    #[serde(default = "default_limit")]
+    limit: usize,
+}
+
+/// Searches the whole library at once, the best matches first, with the
+/// words that matched marked for the browser to highlight.
+pub async fn search(
+    State(server_state): State<ServerState>,
+    Query(query): Query<SearchQuery>,
+) -> Json<SearchResults> {
+    let player = server_state.player.lock().await;
+    Json(player.library.search(&query.q, query.limit))
+}
@@ -0,0 +1,108 @@
+use axum::{extract::{Path, State}, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use crate::player::HeardSong;
+use super::ServerState;
+
+#[derive(Deserialize)]
+pub struct StreamRequest {
+    name: String,
+    url: String,
+    #[serde(default)]
+    fallback_urls: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct AddStreamResponse {
+    index: usize,
+}
+
+/// A stream that isn't there is a 404, where the library's error for it
+/// would otherwise come out as a bad request.
+fn ensure_stream_exists(streams: usize, index: usize) -> Result<(), (StatusCode, String)> {
+    if index >= streams {
+        return Err((StatusCode::NOT_FOUND, format!("Stream {} not found", index)));
+    }
+    Ok(())
+}
+
+/// Adds a stream after the others.
+pub async fn add_stream(
+    State(server_state): State<ServerState>,
+    Json(request): Json<StreamRequest>,
+) -> Result<Json<AddStreamResponse>, (StatusCode, String)> {
+    let mut player = server_state.player.lock().await;
+    let index = player.library.add_stream(&request.name, &request.url, &request.fallback_urls)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    player.notify_library_updated();
+    Ok(Json(AddStreamResponse { index }))
+}
+
+/// Changes the name and URLs of a stream.
+pub async fn update_stream(
+    State(server_state): State<ServerState>,
+    Path(index): Path<usize>,
+    Json(request): Json<StreamRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut player = server_state.player.lock().await;
+    ensure_stream_exists(player.library.streams.len(), index)?;
+    player.library.update_stream(index, &request.name, &request.url, &request.fallback_urls)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    player.notify_library_updated();
+    Ok(StatusCode::OK)
+}
+
+pub async fn delete_stream(
+    State(server_state): State<ServerState>,
+    Path(index): Path<usize>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut player = server_state.player.lock().await;
+    ensure_stream_exists(player.library.streams.len(), index)?;
+    player.library.delete_stream(index)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    player.notify_library_updated();
+    Ok(StatusCode::OK)
+}
+
+#[derive(Deserialize)]
+pub struct MoveStreamRequest {
+    to: usize,
+}
+
+/// Moves a stream to another place in the list, and so to another key.
+pub async fn move_stream(
+    State(server_state): State<ServerState>,
+    Path(index): Path<usize>,
+    Json(request): Json<MoveStreamRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut player = server_state.player.lock().await;
+    ensure_stream_exists(player.library.streams.len(), index)?;
+    player.library.move_stream(index, request.to)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    player.notify_library_updated();
+    Ok(StatusCode::OK)
+}
+
+/// Sets the logo of a stream to the SVG sent as the request body.
+pub async fn set_stream_logo(
+    State(server_state): State<ServerState>,
+    Path(index): Path<usize>,
+    svg: String,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut player = server_state.player.lock().await;
+    ensure_stream_exists(player.library.streams.len(), index)?;
+    player.library.set_stream_logo(index, &svg)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    player.notify_library_updated();
+    Ok(StatusCode::OK)
+}
+
+/// The songs recently heard on a stream, the latest first.
+pub async fn get_stream_history(
+    State(server_state): State<ServerState>,
+    Path(index): Path<usize>,
+) -> Result<Json<Vec<HeardSong>>, (StatusCode, String)> {
+    let player = server_state.player.lock().await;
+    ensure_stream_exists(player.library.streams.len(), index)?;
+    let name = &player.library.streams[index].name;
+    Ok(Json(player.stream_history.songs(name)))
+}
@@ -0,0 +1,155 @@
+use axum::{body::Bytes, extract::{Path, State}, http::{header, HeaderMap, StatusCode}, Json};
+use serde::{Deserialize, Serialize};
+use crate::library::TagChanges;
+use super::manage::{ensure_not_in_use, ensure_track_exists};
+use super::ServerState;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackTags {
+    file_name: String,
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    track_number: Option<u32>,
+    has_cover: bool,
+}
+
+/// The tags of a track as the library has them, along with the name of its
+/// file, which is what untagged tracks are shown by.
+pub async fn get_track_tags(
+    State(server_state): State<ServerState>,
+    Path((index, track_index)): Path<(usize, usize)>,
+) -> Result<Json<TrackTags>, (StatusCode, String)> {
+    let player = server_state.player.lock().await;
+    ensure_track_exists(&player, index, track_index)?;
+    let track = &player.library.playlists[index].tracks[track_index];
+    Ok(Json(TrackTags {
+        file_name: track.filename
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        title: track.title.clone(),
+        artist: track.artist.clone(),
+        album: track.album.clone(),
+        track_number: track.track_number,
+        has_cover: track.has_cover_art,
+    }))
+}
+
+/// Writes the tags of a single track.
+pub async fn set_track_tags(
+    State(server_state): State<ServerState>,
+    Path((index, track_index)): Path<(usize, usize)>,
+    Json(changes): Json<TagChanges>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut player = server_state.player.lock().await;
+    ensure_track_exists(&player, index, track_index)?;
+    let file = player.library.playlists[index].tracks[track_index].filename.clone();
+    ensure_not_in_use(&player, &[file])?;
+    player.library.write_track_tags(index, track_index, &changes)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    player.notify_library_updated();
+    Ok(StatusCode::OK)
+}
+
+/// Writes the tags every track of a playlist shares, the album and the
+/// artist. Titles and track numbers differ from track to track, so those go
+/// through the tracks or the file name pattern instead.
+pub async fn set_playlist_tags(
+    State(server_state): State<ServerState>,
+    Path(index): Path<usize>,
+    Json(changes): Json<TagChanges>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    if changes.title.is_some() || changes.track_number.is_some() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Only the album and the artist can be set for a whole playlist".to_string(),
+        ));
+    }
+    let mut player = server_state.player.lock().await;
+    if index >= player.library.playlists.len() {
+        return Err((StatusCode::NOT_FOUND, format!("Playlist {} not found", index)));
+    }
+    ensure_not_in_use(&player, &player.library.playlist_files(index))?;
+    player.library.write_playlist_tags(index, &changes)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    player.notify_library_updated();
+    Ok(StatusCode::OK)
+}
+
+#[derive(Deserialize)]
+pub struct FilenamePatternRequest {
+    pattern: String,
+}
+
+#[derive(Serialize)]
+pub struct FilenamePatternResponse {
+    tagged: usize,
+}
+
+/// Fills in the tags of a playlist's tracks from their file names, by a
+/// pattern such as `%n - %t`.
+pub async fn tag_from_filenames(
+    State(server_state): State<ServerState>,
+    Path(index): Path<usize>,
+    Json(request): Json<FilenamePatternRequest>,
+) -> Result<Json<FilenamePatternResponse>, (StatusCode, String)> {
+    let mut player = server_state.player.lock().await;
+    if index >= player.library.playlists.len() {
+        return Err((StatusCode::NOT_FOUND, format!("Playlist {} not found", index)));
+    }
+    ensure_not_in_use(&player, &player.library.playlist_files(index))?;
+    let tagged = player.library.tag_playlist_from_filenames(index, &request.pattern)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    player.notify_library_updated();
+    Ok(Json(FilenamePatternResponse { tagged }))
+}
+
+/// The image type of a cover upload, which is sent as the raw request body.
+fn image_type(headers: &HeaderMap) -> Result<String, (StatusCode, String)> {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .filter(|mime| mime.starts_with("image/"))
+        .map(|mime| mime.to_string())
+        .ok_or((StatusCode::UNSUPPORTED_MEDIA_TYPE, "The cover has to be an image".to_string()))
+}
+
+/// Embeds a cover into every track of a playlist.
+pub async fn set_playlist_cover(
+    State(server_state): State<ServerState>,
+    Path(index): Path<usize>,
+    headers: HeaderMap,
+    data: Bytes,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mime_type = image_type(&headers)?;
+    let mut player = server_state.player.lock().await;
+    if index >= player.library.playlists.len() {
+        return Err((StatusCode::NOT_FOUND, format!("Playlist {} not found", index)));
+    }
+    ensure_not_in_use(&player, &player.library.playlist_files(index))?;
+    let track_indices: Vec<usize> = (0..player.library.playlists[index].tracks.len()).collect();
+    player.library.write_covers(index, &track_indices, &data, &mime_type)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    player.notify_library_updated();
+    Ok(StatusCode::OK)
+}
+
+/// Embeds a cover into a single track.
+pub async fn set_track_cover(
+    State(server_state): State<ServerState>,
+    Path((index, track_index)): Path<(usize, usize)>,
+    headers: HeaderMap,
+    data: Bytes,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mime_type = image_type(&headers)?;
+    let mut player = server_state.player.lock().await;
+    ensure_track_exists(&player, index, track_index)?;
+    let file = player.library.playlists[index].tracks[track_index].filename.clone();
+    ensure_not_in_use(&player, &[file])?;
+    player.library.write_covers(index, &[track_index], &data, &mime_type)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    player.notify_library_updated();
+    Ok(StatusCode::OK)
+}
@@ -0,0 +1,439 @@
+use axum::{extract::{Multipart, State}, http::{header, HeaderMap, StatusCode}, Json};
+use serde_json::json;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{io::AsyncWriteExt, sync::broadcast};
+use crate::library::{self, LibraryRoot, ScanConfig, IMAGE_EXTENSIONS};
+use crate::player::{AppEvent, UploadStage};
+use super::archive::{self, ArchiveKind, Unpacked};
+use super::ServerState;
+
+/// Upload size limit unless configured otherwise, in MiB. Enough for an album
+/// in FLAC.
+pub const DEFAULT_MAX_UPLOAD_MIB: usize = 512;
+
+/// Longest name of a single file or folder, in bytes. The limit of nearly
+/// every file system a library ends up on.
+const MAX_NAME_BYTES: usize = 255;
+
+/// Characters that can't be part of a name on the FAT and NTFS drives
+/// libraries are often kept on. Replaced rather than rejected, since they are
+/// common in album titles ("Live: 1999", "What?").
+const REPLACED_CHARACTERS: [char; 7] = ['<', '>', ':', '"', '|', '?', '*'];
+
+/// Turns one name given by the client into one that is safe to create in the
+/// library: a single file or folder name that can't climb out of the folder it
+/// is created in, nor be hidden from the scan.
+pub fn sanitize_name(name: &str) -> Result<String, String> {
+    let sanitized: String = name
+        .chars()
+        .filter(|character| !character.is_control())
+        .map(|character| if REPLACED_CHARACTERS.contains(&character) { '_' } else { character })
+        .collect();
+    // Windows drops trailing dots and spaces, which would make two names the
+    // same there that differ here.
+    let sanitized = sanitized.trim().trim_end_matches(['.', ' ']).to_string();
+
+    if sanitized.is_empty() || sanitized == "." || sanitized == ".." {
+        return Err(format!("Invalid name: {:?}", name));
+    }
+    if sanitized.starts_with('.') {
+        return Err(format!("Names must not start with a dot: {:?}", name));
+    }
+    if sanitized.len() > MAX_NAME_BYTES {
+        return Err(format!("Name is too long: {:?}", name));
+    }
+    Ok(sanitized)
+}
+
+/// Turns the playlist name of an upload into the folder it goes to, relative
+/// to the library root. Slashes make subfolders, as in `Artist/Album`, like
+/// the playlist titles the scan gives nested folders. Anything that would
+/// leave the root is an error rather than cleaned up, as it's never an honest
+/// mistake.
+pub fn sanitize_playlist_name(name: &str) -> Result<PathBuf, String> {
+    let name = name.trim();
+    if name.starts_with(['/', '\\']) {
+        return Err(format!("Playlist name must not be an absolute path: {:?}", name));
+    }
+    let mut folder = PathBuf::new();
+    for segment in name.split(['/', '\\']) {
+        if segment.trim() == ".." {
+            return Err(format!("Playlist name must not contain \"..\": {:?}", name));
+        }
+        folder.push(sanitize_name(segment)?);
+    }
+    Ok(folder)
+}
+
+/// Turns the name of an uploaded file into the name it is stored under. Some
+/// browsers send the path the file had on the client, of which only the last
+/// part is kept.
+pub fn sanitize_file_name(name: &str) -> Result<String, String> {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+    sanitize_name(name)
+}
+
+/// Whether a file can be uploaded: the audio files the scan picks up, the
+/// images it takes covers from, and the CUE sheets it splits albums by.
+pub fn is_allowed_file(name: &str, scan_config: &ScanConfig) -> bool {
+    let Some(extension) = Path::new(name).extension() else {
+        return false;
+    };
+    let extension = extension.to_string_lossy().to_lowercase();
+    scan_config.is_audio_extension(&extension)
+        || IMAGE_EXTENSIONS.contains(&extension.as_str())
+        || extension == "cue"
+}
+
+/// A hidden folder in a library root that an upload is written to before it
+/// is moved into place. Moving a file within a file system is atomic, so the
+/// scan never comes across half-written files, and a failed upload leaves
+/// nothing behind: the folder is removed when dropped.
+pub struct StagingFolder {
+    path: PathBuf,
+}
+
+impl StagingFolder {
+    pub fn new(root: &Path) -> std::io::Result<StagingFolder> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.subsec_nanos())
+            .unwrap_or(0);
+        let path = root.join(format!(
+            ".miconau-upload-{}-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed),
+            nanos,
+        ));
+        fs::create_dir(&path)?;
+        Ok(StagingFolder { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Moves everything written to the staging folder into `target`,
+    /// replacing files of the same name. Subfolders that are there already
+    /// are added to rather than replaced.
+    pub fn commit(self, target: &Path) -> std::io::Result<()> {
+        fn move_into(from: &Path, to: &Path) -> std::io::Result<()> {
+            fs::create_dir_all(to)?;
+            for entry in fs::read_dir(from)? {
+                let entry = entry?;
+                let destination = to.join(entry.file_name());
+                if entry.file_type()?.is_dir() && destination.is_dir() {
+                    move_into(&entry.path(), &destination)?;
+                } else {
+                    fs::rename(entry.path(), destination)?;
+                }
+            }
+            Ok(())
+        }
+        move_into(&self.path, target)
+    }
+}
+
+impl Drop for StagingFolder {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// How often the progress of an upload is reported while its files come in.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Reports how an upload is getting on to everyone listening for events, the
+/// web UI that started it among them, which tells its own upload apart by
+/// the id it gave it.
+struct ProgressReporter {
+    upload_id: String,
+    event_transmitter: broadcast::Sender<AppEvent>,
+    total_bytes: Option<u64>,
+    received_bytes: u64,
+    last_report: Instant,
+}
+
+impl ProgressReporter {
+    fn report(&self, stage: UploadStage) {
+        if self.upload_id.is_empty() {
+            return;
+        }
+        let _ = self.event_transmitter.send(AppEvent::UploadProgress {
+            upload_id: self.upload_id.clone(),
+            stage,
+            received_bytes: self.received_bytes,
+            total_bytes: self.total_bytes,
+        });
+    }
+
+    fn received(&mut self, bytes: usize) {
+        self.received_bytes += bytes as u64;
+        if self.last_report.elapsed() >= PROGRESS_INTERVAL {
+            self.last_report = Instant::now();
+            self.report(UploadStage::Receiving);
+        }
+    }
+}
+
+/// Picks the root an upload goes to: the one asked for, or without one, the
+/// first one that is there.
+fn upload_root(roots: &[LibraryRoot], index: Option<usize>) -> Result<LibraryRoot, (StatusCode, String)> {
+    let root = match index {
+        Some(index) => roots.get(index)
+            .ok_or((StatusCode::BAD_REQUEST, format!("Library root {} not found", index)))?,
+        None => roots.iter()
+            .find(|root| root.is_available())
+            .ok_or((StatusCode::SERVICE_UNAVAILABLE, "No library root is available".to_string()))?,
+    };
+    if !root.is_available() {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("Library root {} is not available", root.name()),
+        ));
+    }
+    Ok(root.clone())
+}
+
+/// Takes an upload of audio files, covers and CUE sheets, or of albums packed
+/// into ZIP or tar archives, into the library.
+///
+/// Files are streamed to disk as they come in rather than held in memory, so
+/// the fields that say where they go, `playlistName` and `libraryRoot`, have
+/// to come before them. The playlist name can be left out for an archive
+/// holding a single folder, which then gives it. An `uploadId` field, also
+/// before the files, has progress reported as `uploadProgress` events.
+pub async fn upload_playlist(
+    State(server_state): State<ServerState>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let player = server_state.player.lock().await;
+    let roots = player.library.roots.clone();
+    let scan_config = player.library.scan_config.clone();
+    let mut progress = ProgressReporter {
+        upload_id: String::new(),
+        event_transmitter: player.event_transmitter.clone(),
+        total_bytes: headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok())
+            .and_then(|length| length.parse().ok()),
+        received_bytes: 0,
+        last_report: Instant::now(),
+    };
+    drop(player);
+
+    let mut playlist_name = String::new();
+    let mut root_index: Option<usize> = None;
+    let mut staging: Option<(LibraryRoot, StagingFolder)> = None;
+    let mut file_names: Vec<String> = Vec::new();
+    let mut archives: Vec<(PathBuf, ArchiveKind)> = Vec::new();
+
+    // Errors reading the body carry their own status, which is how a body
+    // over the size limit comes back as 413 rather than as a malformed one.
+    while let Some(mut field) = multipart.next_field().await
+        .map_err(|e| (e.status(), format!("Error parsing multipart: {}", e)))? {
+
+        let field_name = field.name().unwrap_or("").to_string();
+
+        if field_name == "uploadId" {
+            progress.upload_id = field.text().await
+                .map_err(|e| (e.status(), format!("Error reading upload id: {}", e)))?;
+        } else if field_name == "playlistName" {
+            playlist_name = field.text().await
+                .map_err(|e| (e.status(), format!("Error reading playlist name: {}", e)))?;
+        } else if field_name == "libraryRoot" {
+            if staging.is_some() {
+                return Err((StatusCode::BAD_REQUEST, "libraryRoot must come before the files".to_string()));
+            }
+            let value = field.text().await
+                .map_err(|e| (e.status(), format!("Error reading library root: {}", e)))?;
+            root_index = Some(value.trim().parse()
+                .map_err(|_| (StatusCode::BAD_REQUEST, format!("Invalid library root: {}", value)))?);
+        } else if field_name.starts_with("file-") {
+            let file_name = sanitize_file_name(field.file_name().unwrap_or(""))
+                .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+            let archive_kind = ArchiveKind::for_file_name(&file_name);
+            if archive_kind.is_none() && !is_allowed_file(&file_name, &scan_config) {
+                return Err((
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    format!("Only audio files, cover images, CUE sheets and ZIP or tar archives can be uploaded: {}", file_name),
+                ));
+            }
+            if file_names.iter().any(|name| name.eq_ignore_ascii_case(&file_name)) {
+                return Err((StatusCode::BAD_REQUEST, format!("File uploaded twice: {}", file_name)));
+            }
+
+            if staging.is_none() {
+                let root = upload_root(&roots, root_index)?;
+                let folder = StagingFolder::new(&root.folder)
+                    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Error writing files: {}", e)))?;
+                staging = Some((root, folder));
+            }
+            let (_, folder) = staging.as_ref().unwrap();
+            // Archives are kept apart from the files, under names that can't
+            // clash with theirs, until they are unpacked.
+            let path = match archive_kind {
+                Some(kind) => {
+                    let path = folder.path().join(format!(".archive-{}", archives.len()));
+                    archives.push((path.clone(), kind));
+                    path
+                }
+                None => folder.path().join(&file_name),
+            };
+
+            let write_error = |e: std::io::Error| {
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("Error writing {}: {}", file_name, e))
+            };
+            let mut file = tokio::fs::File::create(&path).await.map_err(write_error)?;
+            while let Some(chunk) = field.chunk().await
+                .map_err(|e| (e.status(), format!("Error reading {}: {}", file_name, e)))? {
+                file.write_all(&chunk).await.map_err(write_error)?;
+                progress.received(chunk.len());
+            }
+            file.flush().await.map_err(write_error)?;
+            file_names.push(file_name);
+        }
+    }
+
+    let Some((root, staging)) = staging else {
+        return Err((StatusCode::BAD_REQUEST, "At least one file is required".to_string()));
+    };
+
+    if !archives.is_empty() {
+        progress.report(UploadStage::Unpacking);
+    }
+    let unpack_config = scan_config.clone();
+    let max_bytes = server_state.max_upload_bytes;
+    let unpacked = tokio::task::spawn_blocking(move || {
+        let mut unpacked: Vec<Unpacked> = Vec::new();
+        for (archive, kind) in archives {
+            let unpacked_bytes: u64 = unpacked.iter().map(|unpacked| unpacked.bytes).sum();
+            let remaining = max_bytes.saturating_sub(unpacked_bytes);
+            unpacked.push(archive::unpack(&archive, kind, staging.path(), &unpack_config, remaining)?);
+            let _ = fs::remove_file(&archive);
+        }
+        Ok::<_, String>((staging, unpacked))
+    })
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Error unpacking: {}", e)))?;
+    let (staging, unpacked) = unpacked.map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+
+    if playlist_name.trim().is_empty() {
+        // An archive of a single folder, uploaded on its own, names itself.
+        playlist_name = match (file_names.len(), unpacked.first()) {
+            (1, Some(Unpacked { top_folder: Some(folder), .. })) => folder.clone(),
+            _ => return Err((StatusCode::BAD_REQUEST, "Playlist name is required".to_string())),
+        };
+    }
+    let playlist_folder = sanitize_playlist_name(&playlist_name)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let skipped: Vec<String> = unpacked.into_iter().flat_map(|unpacked| unpacked.skipped).collect();
+
+    progress.report(UploadStage::Scanning);
+    let target = root.folder.join(playlist_folder);
+    let scanned = target.clone();
+    let playlists = tokio::task::spawn_blocking(move || {
+        staging.commit(&target)?;
+        // Only the folder that was uploaded to is scanned, which takes as
+        // long as the upload has tracks rather than as the library has.
+        Ok::<_, std::io::Error>(library::scan_folder_playlists(&root, &target, &scan_config))
+    })
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Error writing files: {}", e)))?
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Error writing files: {}", e)))?;
+
+    let mut player = server_state.player.lock().await;
+    player.library.replace_playlists(playlists, &scanned);
+    player.notify_library_updated();
+    drop(player);
+    progress.report(UploadStage::Done);
+
+    Ok(Json(json!({"success": true, "playlistName": playlist_name, "skipped": skipped})))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+
+    #[test]
+    fn playlist_names_stay_inside_the_library() {
+        assert_eq!(sanitize_playlist_name("Artist/Album"), Ok(PathBuf::from("Artist/Album")));
+        assert!(sanitize_playlist_name("../Album").is_err());
+        assert!(sanitize_playlist_name("Artist/../../etc").is_err());
+        assert!(sanitize_playlist_name("/etc/cron.d").is_err());
+        assert!(sanitize_playlist_name("\\\\server\\share").is_err());
+        assert!(sanitize_playlist_name("Artist//Album").is_err());
+        assert!(sanitize_playlist_name(".hidden").is_err());
+        assert!(sanitize_playlist_name("   ").is_err());
+    }
+
+    #[test]
+    fn names_lose_characters_that_drives_reject() {
+        assert_eq!(sanitize_playlist_name("Live: 1999?"), Ok(PathBuf::from("Live_ 1999_")));
+        assert_eq!(sanitize_playlist_name("Album...  "), Ok(PathBuf::from("Album")));
+        assert_eq!(sanitize_file_name("01\u{0}track.flac"), Ok("01track.flac".to_string()));
+        assert!(sanitize_file_name(&"a".repeat(300)).is_err());
+    }
+
+    #[test]
+    fn file_names_keep_only_their_last_part() {
+        assert_eq!(sanitize_file_name("C:\\fakepath\\01.flac"), Ok("01.flac".to_string()));
+        assert_eq!(sanitize_file_name("../../01.flac"), Ok("01.flac".to_string()));
+        assert!(sanitize_file_name("..").is_err());
+        assert!(sanitize_file_name("").is_err());
+        assert!(sanitize_file_name("uploads/").is_err());
+    }
+
+    #[test]
+    fn only_audio_covers_and_cue_sheets_are_allowed() {
+        let scan_config = ScanConfig::default();
+
+        assert!(is_allowed_file("01.FLAC", &scan_config));
+        assert!(is_allowed_file("cover.jpg", &scan_config));
+        assert!(is_allowed_file("album.cue", &scan_config));
+        assert!(!is_allowed_file("run.sh", &scan_config));
+        assert!(!is_allowed_file("README", &scan_config));
+        assert!(!is_allowed_file("01.m4a", &scan_config));
+        assert!(is_allowed_file("01.m4a", &ScanConfig::new(Some(vec!["m4a".to_string()]))));
+    }
+
+    #[test]
+    fn staged_files_are_moved_into_place_or_removed() {
+        let temp = TempDir::new("staging");
+        let root = &temp.path;
+
+        let staging = StagingFolder::new(root).unwrap();
+        fs::write(staging.path().join("01.flac"), "audio").unwrap();
+        staging.commit(&root.join("Album")).unwrap();
+        assert_eq!(fs::read_to_string(root.join("Album/01.flac")).unwrap(), "audio");
+
+        // adds to folders that are there already
+        let staging = StagingFolder::new(root).unwrap();
+        fs::create_dir(staging.path().join("Bonus")).unwrap();
+        fs::write(staging.path().join("Bonus/01.flac"), "bonus").unwrap();
+        staging.commit(&root.join("Album")).unwrap();
+        fs::create_dir(root.join("Album/Bonus/Extra")).unwrap();
+        let staging = StagingFolder::new(root).unwrap();
+        fs::create_dir(staging.path().join("Bonus")).unwrap();
+        fs::write(staging.path().join("Bonus/02.flac"), "bonus").unwrap();
+        staging.commit(&root.join("Album")).unwrap();
+        assert!(root.join("Album/01.flac").exists());
+        assert!(root.join("Album/Bonus/01.flac").exists());
+        assert!(root.join("Album/Bonus/02.flac").exists());
+        assert!(root.join("Album/Bonus/Extra").is_dir());
+
+        let abandoned = StagingFolder::new(root).unwrap();
+        fs::write(abandoned.path().join("02.flac"), "audio").unwrap();
+        drop(abandoned);
+        let entries: Vec<_> = fs::read_dir(root).unwrap().collect();
+        assert_eq!(entries.len(), 1, "only the album should be left");
+    }
+}